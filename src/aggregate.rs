@@ -0,0 +1,302 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, TimeZone, Utc};
+use serde_json::{Map, Number, Value};
+
+use crate::headers::Sort;
+use crate::sort::{self, compare, lookup};
+
+/// largest integer a `f64` holds exactly
+const MAX_EXACT: f64 = 9_007_199_254_740_992.0;
+
+/// `Aggregation` groups documents and computes metrics for every group.
+/// without `group_by` all documents make a single group.
+/// every group is a row with its group and metric aliases as fields
+#[derive(Clone, Debug, PartialEq)]
+pub struct Aggregation {
+    pub group_by: Vec<Group>,
+    pub metrics: Vec<Metric>,
+    /// rows are sorted by group or metric aliases. by their groups otherwise
+    pub sort: Vec<Sort>,
+    pub limit: Option<usize>,
+}
+
+/// `Group` is a dotted `path` of a document, truncated when it holds a date
+#[derive(Clone, Debug, PartialEq)]
+pub struct Group {
+    pub path: String,
+    pub truncate: Option<Truncate>,
+    pub alias: String,
+}
+
+/// dates are strings or seconds since epoch
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Truncate {
+    Year,
+    Month,
+    Day,
+    Hour,
+    Minute,
+}
+
+/// `Metric` of a group. only `count` has no `path`, it counts documents then
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metric {
+    pub func: Func,
+    pub path: Option<String>,
+    pub alias: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Func {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+    Distinct,
+}
+
+/// partial groups of an aggregation. keyed by their group values as json
+#[derive(Default)]
+pub(crate) struct Groups(HashMap<String, (Vec<Value>, Vec<Acc>)>);
+
+enum Acc {
+    Count(u64),
+    Sum(f64),
+    Avg(f64, u64),
+    Min(Option<Value>),
+    Max(Option<Value>),
+    Distinct(HashSet<String>),
+}
+
+impl Aggregation {
+    /// parses metrics like `count`, `sum(total) AS revenue`, `distinct(customer)`
+    /// and groups like `status` or `day(created) AS day`
+    pub fn new(metrics: &[String], group_by: &[String]) -> Result<Self, String> {
+        let mut aggregation = Aggregation { group_by: vec![], metrics: vec![], sort: vec![], limit: None };
+        for metric in metrics {
+            let (name, path, alias) = spec(metric)?;
+            let func = match name.as_deref() {
+                Some("count") => Func::Count,
+                Some("sum") => Func::Sum,
+                Some("avg") => Func::Avg,
+                Some("min") => Func::Min,
+                Some("max") => Func::Max,
+                Some("distinct") => Func::Distinct,
+                _ => return Err(format!("unknown metric `{}`. expected count, sum, avg, min, max or distinct", metric)),
+            };
+            if func != Func::Count && path.is_none() {
+                return Err(format!("metric `{}` needs a field", metric));
+            }
+            aggregation.metrics.push(Metric { func, path, alias });
+        }
+        if aggregation.metrics.is_empty() {
+            return Err("aggregation needs at least one metric".to_string());
+        }
+        for group in group_by {
+            let (name, path, alias) = spec(group)?;
+            let (path, truncate) = match (name.as_deref(), path) {
+                (None, Some(path)) => (path, None),
+                (Some("year"), Some(path)) => (path, Some(Truncate::Year)),
+                (Some("month"), Some(path)) => (path, Some(Truncate::Month)),
+                (Some("day"), Some(path)) => (path, Some(Truncate::Day)),
+                (Some("hour"), Some(path)) => (path, Some(Truncate::Hour)),
+                (Some("minute"), Some(path)) => (path, Some(Truncate::Minute)),
+                _ => return Err(format!("invalid group `{}`. expected `path` or year, month, day, hour, minute of a path", group)),
+            };
+            aggregation.group_by.push(Group { path, truncate, alias });
+        }
+        Ok(aggregation)
+    }
+
+    /// adds a document to its group
+    pub(crate) fn add(&self, groups: &mut Groups, doc: &Value) {
+        let values = self.group_by.iter().map(|g| g.value(doc)).collect::<Vec<Value>>();
+        let key = serde_json::to_string(&values).unwrap_or_default();
+        let (_, accs) = groups.0.entry(key).or_insert_with(|| (values, self.accs()));
+        for (metric, acc) in self.metrics.iter().zip(accs.iter_mut()) {
+            let value = metric.path.as_ref().map(|p| lookup(doc, p));
+            acc.add(value);
+        }
+    }
+
+    pub(crate) fn merge(&self, mut left: Groups, right: Groups) -> Groups {
+        for (key, (values, accs)) in right.0 {
+            match left.0.get_mut(&key) {
+                Some((_, existing)) => {
+                    for (acc, other) in existing.iter_mut().zip(accs) {
+                        acc.merge(other);
+                    }
+                }
+                None => {
+                    left.0.insert(key, (values, accs));
+                }
+            }
+        }
+        left
+    }
+
+    /// the sorted and limited rows of the groups
+    pub(crate) fn rows(&self, mut groups: Groups) -> Vec<Value> {
+        if self.group_by.is_empty() && groups.0.is_empty() {
+            groups.0.insert(String::new(), (vec![], self.accs()));
+        }
+        let mut groups = groups.0.into_values().collect::<Vec<(Vec<Value>, Vec<Acc>)>>();
+        groups.sort_by(|a, b| {
+            a.0.iter().zip(b.0.iter()).map(|(a, b)| compare(a, b)).find(|o| o.is_ne()).unwrap_or(Ordering::Equal)
+        });
+        let mut rows = groups
+            .into_iter()
+            .map(|(values, accs)| {
+                let mut row = Map::new();
+                for (group, value) in self.group_by.iter().zip(values) {
+                    row.insert(group.alias.to_string(), value);
+                }
+                for (metric, acc) in self.metrics.iter().zip(accs) {
+                    row.insert(metric.alias.to_string(), acc.value());
+                }
+                Value::Object(row)
+            })
+            .collect::<Vec<Value>>();
+        sort::sort(&mut rows, &self.sort);
+        if let Some(limit) = self.limit {
+            rows.truncate(limit);
+        }
+        rows
+    }
+
+    fn accs(&self) -> Vec<Acc> {
+        self.metrics.iter().map(|m| match m.func {
+            Func::Count => Acc::Count(0),
+            Func::Sum => Acc::Sum(0.0),
+            Func::Avg => Acc::Avg(0.0, 0),
+            Func::Min => Acc::Min(None),
+            Func::Max => Acc::Max(None),
+            Func::Distinct => Acc::Distinct(HashSet::new()),
+        }).collect()
+    }
+}
+
+impl Group {
+    fn value(&self, doc: &Value) -> Value {
+        let value = lookup(doc, &self.path);
+        match self.truncate {
+            None => value.clone(),
+            Some(truncate) => match date(value) {
+                Some(dt) => Value::String(dt.format(truncate.format()).to_string()),
+                None => Value::Null,
+            },
+        }
+    }
+}
+
+impl Truncate {
+    fn format(&self) -> &'static str {
+        match self {
+            Truncate::Year => "%Y",
+            Truncate::Month => "%Y-%m",
+            Truncate::Day => "%Y-%m-%d",
+            Truncate::Hour => "%Y-%m-%dT%H",
+            Truncate::Minute => "%Y-%m-%dT%H:%M",
+        }
+    }
+}
+
+impl Acc {
+    /// `value` is `None` for a `count` of documents
+    fn add(&mut self, value: Option<&Value>) {
+        match (self, value) {
+            (Acc::Count(n), None) => *n += 1,
+            (Acc::Count(n), Some(v)) if !v.is_null() => *n += 1,
+            (Acc::Sum(sum), Some(Value::Number(v))) => *sum += v.as_f64().unwrap_or_default(),
+            (Acc::Avg(sum, n), Some(Value::Number(v))) => {
+                *sum += v.as_f64().unwrap_or_default();
+                *n += 1;
+            }
+            (Acc::Min(min), Some(v)) if comparable(v) => keep(min, v, Ordering::Less),
+            (Acc::Max(max), Some(v)) if comparable(v) => keep(max, v, Ordering::Greater),
+            (Acc::Distinct(set), Some(v)) if !v.is_null() => {
+                set.insert(v.to_string());
+            }
+            _ => {}
+        }
+    }
+
+    fn merge(&mut self, other: Acc) {
+        match (self, other) {
+            (Acc::Count(n), Acc::Count(m)) => *n += m,
+            (Acc::Sum(sum), Acc::Sum(other)) => *sum += other,
+            (Acc::Avg(sum, n), Acc::Avg(other, m)) => {
+                *sum += other;
+                *n += m;
+            }
+            (Acc::Min(min), Acc::Min(Some(v))) => keep(min, &v, Ordering::Less),
+            (Acc::Max(max), Acc::Max(Some(v))) => keep(max, &v, Ordering::Greater),
+            (Acc::Distinct(set), Acc::Distinct(other)) => set.extend(other),
+            _ => {}
+        }
+    }
+
+    fn value(self) -> Value {
+        match self {
+            Acc::Count(n) => Value::Number(Number::from(n)),
+            Acc::Sum(sum) => number(sum),
+            Acc::Avg(_, 0) => Value::Null,
+            Acc::Avg(sum, n) => number(sum / n as f64),
+            Acc::Min(v) | Acc::Max(v) => v.unwrap_or(Value::Null),
+            Acc::Distinct(set) => Value::Number(Number::from(set.len())),
+        }
+    }
+}
+
+/// `name(path) AS alias` split into its parts. the alias defaults to the spec itself
+fn spec(text: &str) -> Result<(Option<String>, Option<String>, String), String> {
+    let words = text.split_whitespace().collect::<Vec<&str>>();
+    let (expr, alias) = match words.as_slice() {
+        [expr] => (*expr, expr.to_string()),
+        [expr, as_, alias] if as_.eq_ignore_ascii_case("as") => (*expr, alias.to_string()),
+        _ => return Err(format!("invalid `{}`. expected `expression` or `expression AS alias`", text)),
+    };
+    let (name, path) = match expr.split_once('(') {
+        Some((name, rest)) => match rest.strip_suffix(')') {
+            Some("") | Some("*") => (Some(name.to_lowercase()), None),
+            Some(path) => (Some(name.to_lowercase()), Some(path.to_string())),
+            None => return Err(format!("unclosed parenthesis in `{}`", text)),
+        },
+        None if expr.eq_ignore_ascii_case("count") => (Some("count".to_string()), None),
+        None => (None, Some(expr.to_string())),
+    };
+    if path.as_ref().is_some_and(|p| p.split('.').any(|step| step.is_empty())) {
+        return Err(format!("invalid field path in `{}`", text));
+    }
+    Ok((name, path, alias))
+}
+
+fn date(value: &Value) -> Option<DateTime<Utc>> {
+    match value {
+        Value::String(s) => anydate::parse_utc(s).ok(),
+        Value::Number(n) => Utc.timestamp_opt(n.as_f64()?.trunc() as i64, 0).single(),
+        _ => None,
+    }
+}
+
+/// replaces the kept value when `v` orders before it for `Less`, after it for `Greater`
+fn keep(kept: &mut Option<Value>, v: &Value, wanted: Ordering) {
+    if kept.as_ref().is_none_or(|k| compare(v, k) == wanted) {
+        *kept = Some(v.clone());
+    }
+}
+
+fn comparable(v: &Value) -> bool {
+    matches!(v, Value::Bool(_) | Value::Number(_) | Value::String(_))
+}
+
+/// an integer when the number has no fraction
+fn number(n: f64) -> Value {
+    if n.fract() == 0.0 && n.abs() < MAX_EXACT {
+        return Value::Number(Number::from(n as i64));
+    }
+    Number::from_f64(n).map_or(Value::Null, Value::Number)
+}
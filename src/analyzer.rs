@@ -0,0 +1,358 @@
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+
+/// characters replaced by a whitespace before tokenizing
+const PUNCTUATION: [char; 9] = ['(', ')', '+', '-', '/', '\\', '_', '[', ']'];
+
+const ENGLISH_STOP_WORDS: [&str; 33] = [
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "if", "in", "into", "is", "it",
+    "no", "not", "of", "on", "or", "such", "that", "the", "their", "then", "there", "these",
+    "they", "this", "to", "was", "will", "with",
+];
+
+/// `Token` is a term with the char offsets it was read from in the original text
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Token {
+    pub text: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// `Analyzer` turns a text into search terms.
+/// The same analyzer is used while indexing, deleting and querying a field
+pub trait Analyzer: Send + Sync {
+    fn tokens(&self, text: &str) -> Vec<Token>;
+
+    fn analyze(&self, text: &str) -> Vec<String> {
+        self.tokens(text).into_iter().map(|token| token.text).collect()
+    }
+}
+
+/// `CharFilter` rewrites the raw text before it reaches the tokenizer.
+/// chars are mapped one to one so token offsets stay valid in the original text
+pub trait CharFilter: Send + Sync {
+    fn filter(&self, c: char) -> char;
+}
+
+/// `Tokenizer` splits a filtered text into tokens
+pub trait Tokenizer: Send + Sync {
+    fn tokenize(&self, text: &str) -> Vec<Token>;
+}
+
+/// `TokenFilter` transforms or drops a single token
+pub trait TokenFilter: Send + Sync {
+    fn filter(&self, token: String) -> Option<String>;
+}
+
+/// built-in char filters
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum CharFilterKind {
+    /// replaces `()+-/\_[]` with a whitespace
+    Punctuation,
+    /// replaces every `from` char with the `to` char
+    Mapping(Vec<(char, char)>),
+}
+
+/// built-in tokenizers
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub enum TokenizerKind {
+    /// splits on whitespaces
+    #[default]
+    Whitespace,
+    /// splits on every char that is not alphanumeric
+    Alphanumeric,
+    /// keeps the whole text as a single token
+    Keyword,
+}
+
+/// built-in token filters
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum TokenFilterKind {
+    Lowercase,
+    /// folds latin accented chars to their ascii counterpart. `é` becomes `e`
+    AsciiFolding,
+    /// drops the given words. compared after previous filters are applied
+    StopWords(Vec<String>),
+    /// drops common english words
+    EnglishStopWords,
+    /// light english suffix stripping. `searching` becomes `search`
+    Stemmer,
+}
+
+/// `AnalyzerConfig` is used in `CollectionOptions` to configure
+/// how a `search_opts` field is analyzed
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AnalyzerConfig {
+    pub field: String,
+    #[serde(default)]
+    pub char_filters: Vec<CharFilterKind>,
+    #[serde(default)]
+    pub tokenizer: TokenizerKind,
+    #[serde(default)]
+    pub token_filters: Vec<TokenFilterKind>,
+}
+
+impl CharFilter for CharFilterKind {
+    fn filter(&self, c: char) -> char {
+        match self {
+            CharFilterKind::Punctuation => if PUNCTUATION.contains(&c) { ' ' } else { c },
+            CharFilterKind::Mapping(mapping) => mapping
+                .iter()
+                .find(|(from, _)| *from == c)
+                .map_or(c, |(_, to)| *to),
+        }
+    }
+}
+
+impl Tokenizer for TokenizerKind {
+    fn tokenize(&self, text: &str) -> Vec<Token> {
+        match self {
+            TokenizerKind::Whitespace => split(text, |c| c.is_whitespace()),
+            TokenizerKind::Alphanumeric => split(text, |c| !c.is_alphanumeric()),
+            TokenizerKind::Keyword => {
+                let chars = text.chars().collect::<Vec<char>>();
+                let start = chars.iter().take_while(|c| c.is_whitespace()).count();
+                let end = chars.len() - chars[start..].iter().rev().take_while(|c| c.is_whitespace()).count();
+                if start == end {
+                    vec![]
+                } else {
+                    vec![Token { text: chars[start..end].iter().collect(), start, end }]
+                }
+            }
+        }
+    }
+}
+
+impl TokenFilter for TokenFilterKind {
+    fn filter(&self, token: String) -> Option<String> {
+        match self {
+            TokenFilterKind::Lowercase => Some(token.to_lowercase()),
+            TokenFilterKind::AsciiFolding => Some(fold(&token)),
+            TokenFilterKind::StopWords(words) => {
+                if words.iter().any(|w| w.eq(&token)) { None } else { Some(token) }
+            }
+            TokenFilterKind::EnglishStopWords => {
+                if ENGLISH_STOP_WORDS.contains(&token.as_str()) { None } else { Some(token) }
+            }
+            TokenFilterKind::Stemmer => Some(stem(token)),
+        }
+    }
+}
+
+/// `TextAnalyzer` chains char filters, a tokenizer and token filters
+pub struct TextAnalyzer {
+    char_filters: Vec<Box<dyn CharFilter>>,
+    tokenizer: Box<dyn Tokenizer>,
+    token_filters: Vec<Box<dyn TokenFilter>>,
+}
+
+impl TextAnalyzer {
+    pub fn new(tokenizer: Box<dyn Tokenizer>) -> Self {
+        Self {
+            char_filters: vec![],
+            tokenizer,
+            token_filters: vec![],
+        }
+    }
+
+    pub fn char_filter(mut self, filter: Box<dyn CharFilter>) -> Self {
+        self.char_filters.push(filter);
+        self
+    }
+
+    pub fn token_filter(mut self, filter: Box<dyn TokenFilter>) -> Self {
+        self.token_filters.push(filter);
+        self
+    }
+
+    /// lowercases, replaces punctuation and splits on whitespaces
+    pub fn standard() -> Self {
+        Self::new(Box::new(TokenizerKind::Whitespace))
+            .char_filter(Box::new(CharFilterKind::Punctuation))
+            .token_filter(Box::new(TokenFilterKind::Lowercase))
+    }
+
+    pub fn from_config(cnf: &AnalyzerConfig) -> Self {
+        let mut analyzer = Self::new(Box::new(cnf.tokenizer.clone()));
+        for filter in &cnf.char_filters {
+            analyzer = analyzer.char_filter(Box::new(filter.clone()));
+        }
+        for filter in &cnf.token_filters {
+            analyzer = analyzer.token_filter(Box::new(filter.clone()));
+        }
+        analyzer
+    }
+}
+
+impl Analyzer for TextAnalyzer {
+    fn tokens(&self, text: &str) -> Vec<Token> {
+        let filtered = text
+            .chars()
+            .map(|c| self.char_filters.iter().fold(c, |c, filter| filter.filter(c)))
+            .collect::<String>();
+        self.tokenizer
+            .tokenize(filtered.as_str())
+            .into_iter()
+            .filter_map(|token| {
+                let mut text = Some(token.text);
+                for filter in &self.token_filters {
+                    text = text.and_then(|t| filter.filter(t));
+                }
+                text.filter(|t| !t.is_empty())
+                    .map(|text| Token { text, start: token.start, end: token.end })
+            })
+            .collect()
+    }
+}
+
+/// `Analyzers` keeps the analyzer of every search field of a collection.
+/// Fields without a configuration use `TextAnalyzer::standard`
+pub struct Analyzers {
+    fields: DashMap<String, Arc<dyn Analyzer>>,
+    default: Arc<dyn Analyzer>,
+}
+
+impl Analyzers {
+    pub fn new(search_opts: &[String], analyzer_opts: &[AnalyzerConfig]) -> Self {
+        let default: Arc<dyn Analyzer> = Arc::new(TextAnalyzer::standard());
+        let fields = DashMap::new();
+        for field in search_opts {
+            let analyzer: Arc<dyn Analyzer> = match analyzer_opts.iter().find(|cnf| cnf.field.eq(field)) {
+                Some(cnf) => Arc::new(TextAnalyzer::from_config(cnf)),
+                None => Arc::clone(&default),
+            };
+            fields.insert(field.to_string(), analyzer);
+        }
+        Self { fields, default }
+    }
+
+    /// analyzer of a field
+    pub fn get(&self, field: &str) -> Arc<dyn Analyzer> {
+        match self.fields.get(field) {
+            Some(analyzer) => Arc::clone(analyzer.value()),
+            None => Arc::clone(&self.default),
+        }
+    }
+
+    /// replaces the analyzer of a field. returns the previous one
+    pub fn set(&self, field: &str, analyzer: Arc<dyn Analyzer>) -> Arc<dyn Analyzer> {
+        let previous = self.get(field);
+        self.fields.insert(field.to_string(), analyzer);
+        previous
+    }
+
+    /// analyzes a query with the analyzer of every field.
+    /// fields sharing the same analyzer produce the terms once
+    pub fn query(&self, text: &str) -> Vec<Vec<String>> {
+        let mut seen: Vec<Arc<dyn Analyzer>> = vec![];
+        let mut terms = vec![];
+        let mut analyzers = self.fields.iter().map(|kv| Arc::clone(kv.value())).collect::<Vec<_>>();
+        if analyzers.is_empty() {
+            analyzers.push(Arc::clone(&self.default));
+        }
+        for analyzer in analyzers {
+            if seen.iter().any(|s| Arc::ptr_eq(s, &analyzer)) {
+                continue;
+            }
+            let analyzed = analyzer.analyze(text);
+            seen.push(analyzer);
+            if !analyzed.is_empty() && !terms.contains(&analyzed) {
+                terms.push(analyzed);
+            }
+        }
+        terms
+    }
+
+    /// fields having an analyzer
+    pub fn fields(&self) -> Vec<String> {
+        self.fields.iter().map(|kv| kv.key().to_string()).collect()
+    }
+}
+
+impl Default for Analyzers {
+    fn default() -> Self {
+        Self::new(&[], &[])
+    }
+}
+
+/// splits on `sep` chars, keeping char offsets of every token
+fn split(text: &str, sep: impl Fn(char) -> bool) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut current = String::new();
+    let mut start = 0;
+    for (i, c) in text.chars().enumerate() {
+        if sep(c) {
+            if !current.is_empty() {
+                tokens.push(Token { text: std::mem::take(&mut current), start, end: i });
+            }
+        } else {
+            if current.is_empty() {
+                start = i;
+            }
+            current.push(c);
+        }
+    }
+    if !current.is_empty() {
+        let end = start + current.chars().count();
+        tokens.push(Token { text: current, start, end });
+    }
+    tokens
+}
+
+fn fold(token: &str) -> String {
+    let mut folded = String::with_capacity(token.len());
+    for c in token.chars() {
+        match c {
+            'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' => folded.push('a'),
+            'À' | 'Á' | 'Â' | 'Ã' | 'Ä' | 'Å' => folded.push('A'),
+            'ç' => folded.push('c'),
+            'Ç' => folded.push('C'),
+            'è' | 'é' | 'ê' | 'ë' => folded.push('e'),
+            'È' | 'É' | 'Ê' | 'Ë' => folded.push('E'),
+            'ì' | 'í' | 'î' | 'ï' => folded.push('i'),
+            'Ì' | 'Í' | 'Î' | 'Ï' => folded.push('I'),
+            'ñ' => folded.push('n'),
+            'Ñ' => folded.push('N'),
+            'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' => folded.push('o'),
+            'Ò' | 'Ó' | 'Ô' | 'Õ' | 'Ö' | 'Ø' => folded.push('O'),
+            'ù' | 'ú' | 'û' | 'ü' => folded.push('u'),
+            'Ù' | 'Ú' | 'Û' | 'Ü' => folded.push('U'),
+            'ý' | 'ÿ' => folded.push('y'),
+            'Ý' => folded.push('Y'),
+            'ß' => folded.push_str("ss"),
+            'æ' => folded.push_str("ae"),
+            'Æ' => folded.push_str("AE"),
+            'œ' => folded.push_str("oe"),
+            'Œ' => folded.push_str("OE"),
+            _ => folded.push(c),
+        }
+    }
+    folded
+}
+
+fn stem(token: String) -> String {
+    const RULES: [(&str, &str); 11] = [
+        ("sses", "ss"),
+        ("ches", "ch"),
+        ("shes", "sh"),
+        ("xes", "x"),
+        ("ies", "y"),
+        ("ingly", ""),
+        ("edly", ""),
+        ("ing", ""),
+        ("ed", ""),
+        ("ly", ""),
+        ("s", ""),
+    ];
+    for (suffix, replacement) in RULES {
+        if let Some(stem) = token.strip_suffix(suffix) {
+            if stem.chars().count() < 3 || (suffix.eq("s") && stem.ends_with('s')) {
+                return token;
+            }
+            return format!("{}{}", stem, replacement);
+        }
+    }
+    token
+}
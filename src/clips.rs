@@ -14,6 +14,8 @@ use crate::utils::set_view_name;
 
 pub struct Clips<K> {
     pub kv: DashMap<String, DashSet<K>>,
+    /// clip sets of every clip field, grouped by value
+    pub fields: DashMap<String, DashMap<String, DashSet<K>>>,
 }
 
 impl<K> Clips<K>
@@ -33,17 +35,24 @@ impl<K> Clips<K>
 {
     pub fn new() -> Self {
         Self {
-            kv: DashMap::new()
+            kv: DashMap::new(),
+            fields: DashMap::new(),
         }
     }
 
     pub fn put<D>(&self, k: &K, v: &D) where D: Document {
-        v.tokens().into_iter().for_each(|idx| {
-            match self.kv.get_mut(&idx) {
+        v.tokens().into_iter().for_each(|field| {
+            self.fields
+                .entry(field.key)
+                .or_default()
+                .entry(field.value.clone())
+                .or_default()
+                .insert(k.clone());
+            match self.kv.get_mut(&field.value) {
                 None => {
                     let set = DashSet::new();
                     set.insert(k.clone());
-                    self.kv.insert(idx, set);
+                    self.kv.insert(field.value, set);
                 }
                 Some(set) => {
                     set.value().insert(k.clone());
@@ -68,8 +77,13 @@ impl<K> Clips<K>
 
     pub fn delete<D>(&self, k: &K, v: &D) where D: Document {
         trace!("deleting clip key {}",&k);
-        v.tokens().into_iter().for_each(|idx| {
-            if let Some(set) = self.kv.get_mut(&idx) {
+        v.tokens().into_iter().for_each(|field| {
+            if let Some(values) = self.fields.get(&field.key) {
+                if let Some(set) = values.value().get(&field.value) {
+                    set.value().remove(k);
+                }
+            }
+            if let Some(set) = self.kv.get_mut(&field.value) {
                 set.value().remove(&k);
             }
         });
@@ -83,9 +97,19 @@ impl<K> Clips<K>
         }
     }
 
+    /// removes every document from a view
+    pub fn drop_view(&self, vw: &str) {
+        let view = set_view_name(vw);
+        trace!("dropping view {}",&view);
+        self.kv.remove(&view);
+    }
+
     pub fn delete_clip(&self, clip: &str) {
         trace!("deleting clip {}",&clip);
         self.kv.remove(clip);
+        for values in self.fields.iter() {
+            values.value().remove(clip);
+        }
     }
 
     pub fn clear_keys(&self) {
@@ -94,6 +118,7 @@ impl<K> Clips<K>
         for kv in non_ref {
             self.delete_clip(kv.0.as_str());
         }
+        self.fields.clear();
     }
 
     pub fn get(&self, clip: &str) -> Option<Ref<String, DashSet<K>>> {
@@ -1,4 +1,5 @@
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
 
 use anyhow::Result;
 use crossbeam_queue::ArrayQueue;
@@ -11,23 +12,38 @@ use serde::Serialize;
 use serde_json::Value;
 use sled::Db;
 
+use crate::aggregate::{Aggregation, Groups};
+use crate::analyzer::{Analyzer, Analyzers};
 use crate::clips::Clips;
-use crate::database::CollectionOptions;
-use crate::doc_trait::Document;
-use crate::errors::IndexError;
+use crate::database::{CollectionOptions, Conflict};
+use crate::doc_trait::{Document, Field, Point, ViewConfig};
+use crate::errors::{CollectionError, IndexError};
 use crate::events::EVENT_EMITTER;
-use crate::headers::{FuncResult, FuncType, NotificationType, PubSubEvent, PubSubRes};
+use crate::expr;
+use crate::guard;
+use crate::headers::{ClipSet, ClipSets, Conflicted, Facet, FacetValue, FuncResult, FuncType, HighlightOptions, NotificationType, Plan, PubSubEvent, PubSubRes, SearchHit};
 use crate::index_fields::InvertedIndex;
+use crate::index_geo::GeoIndex;
 use crate::index_hash::HashIndex;
+use crate::index_ngram::NGramIndex;
+use crate::index_vector::VectorIndex;
+use crate::journal::{self, Previous};
+use crate::lookup::{forms, key, POINTER, scalars};
 use crate::persistent::Persistent;
+use crate::planner::Planner;
+use crate::projection::Projection;
 use crate::pub_sub::PubSub;
 use crate::range::Range;
+use crate::sort::{self, lookup};
 use crate::ttl::{Entry, Ttl};
-use crate::utils::{ExecTime, get_ttl_name, prefix_doc, prefix_ttl, TTL_PREFIX, uuid};
+use crate::utils::{ExecTime, get_ttl_name, merge, prefix_doc, prefix_ttl, TTL_PREFIX, uuid};
+use crate::views::Views;
 use crate::watchman::Watchman;
 
 pub type ExecutionTime = String;
 pub type K = String;
+/// documents with their `knn` score
+pub type Scored<D> = Vec<(K, D, f32)>;
 
 /// Collection is a document storage
 pub struct Collection<D: Document> {
@@ -36,10 +52,17 @@ pub struct Collection<D: Document> {
     kv: DashMap<K, D>,
     hash_idx: HashIndex<K>,
     inverted_idx: InvertedIndex<K>,
+    ngram_idx: NGramIndex<K>,
+    geo_idx: GeoIndex<K>,
+    vector_idx: VectorIndex<K>,
     clips: Clips<K>,
+    views: RwLock<Arc<Views>>,
     range: Range<K>,
     watchman: PubSub<PubSubEvent<K, D>>,
-    pub opts: CollectionOptions,
+    /// held by writes that resolve a pointer and act on it, so no other write comes in between
+    writes: Arc<tokio::sync::Mutex<()>>,
+    /// replaced as a whole when a view is dropped, like `views`
+    opts: RwLock<Arc<CollectionOptions>>,
 }
 
 impl<D> Collection<D>
@@ -58,11 +81,19 @@ impl<D> Collection<D>
             bkp: Persistent::open(&db, option.name.as_str()),
             kv: DashMap::new(),
             hash_idx: HashIndex::new(),
-            inverted_idx: InvertedIndex::new(),
+            inverted_idx: InvertedIndex::new(
+                Analyzers::new(&option.search_opts, &option.analyzer_opts),
+                &option.boost_opts,
+            ),
+            ngram_idx: NGramIndex::new(),
+            geo_idx: GeoIndex::new(),
+            vector_idx: VectorIndex::new(&option.vector_opts),
             clips: Clips::new(),
+            views: RwLock::new(Arc::new(Views::new(&option.view_opts))),
             range: Range::new(),
             watchman: Watchman::<PubSubEvent<K, D>>::new(vec![]).unwrap().start(),
-            opts,
+            writes: Arc::new(tokio::sync::Mutex::new(())),
+            opts: RwLock::new(Arc::new(opts)),
         });
         instance.boot().await;
 
@@ -79,7 +110,7 @@ impl<D> Collection<D>
 
     async fn boot(&self) {
         let res = self.bkp.fetch_doc();
-        debug!("loading {} records from local storage in collection {}",res.len(), &self.opts.name);
+        debug!("loading {} records from local storage in collection {}",res.len(), &self.opts().name);
         for kv in res {
             let _ = self._put(kv.0, kv.1, false).await;
         }
@@ -111,49 +142,149 @@ impl<D> Collection<D>
 
     /// sets a TTL for a `Pointer`
     pub async fn put_ttl(&self, k: K, timestamp: i64) {
+        self.touch(&k);
         self.bkp.put_any(prefix_ttl(k.as_str()), timestamp);
         self.ttl.push(timestamp, k);
     }
 
-    /// creates a document in the collection. `K` is type of `String` and represents a `Pointer`
+    /// creates a document in the collection. `K` is type of `String` and represents a `Pointer`.
+    /// collisions on `index_opts` are resolved by `conflict_opts`
     #[inline]
     pub async fn put(&self, k: K, d: D) -> Result<ExecutionTime, IndexError> {
+        let exec = ExecTime::new();
+        self.put_with(k, d, self.opts().conflict_opts).await?;
+        Ok(exec.done())
+    }
+
+    /// creates a document like `put`, resolving collisions on `index_opts` by `conflict`.
+    /// returns the pointer the document is stored under and the collision it resolved
+    pub async fn put_with(&self, k: K, d: D, conflict: Conflict) -> Result<(K, Option<Conflicted>), IndexError> {
+        let _writing = self.writing().await;
+        self._put_with(k, d, conflict).await
+    }
+
+    async fn _put_with(&self, k: K, d: D, conflict: Conflict) -> Result<(K, Option<Conflicted>), IndexError> {
+        let others = self.collisions(&k, &d);
+        if others.is_empty() {
+            self.overwrite(k.clone(), d).await?;
+            return Ok((k, None));
+        }
+        let resolved = Conflicted { resolution: conflict, pointers: others.clone() };
+        match conflict {
+            Conflict::Reject => Err(IndexError::DuplicateDocument),
+            Conflict::Replace => {
+                for pointer in others {
+                    self._delete(pointer, true).await;
+                }
+                self.overwrite(k.clone(), d).await?;
+                Ok((k, Some(resolved)))
+            }
+            Conflict::Merge => {
+                // the documents are merged in the order of their pointers, the put one last
+                let target = others[0].clone();
+                let mut merged = Value::Object(Default::default());
+                for pointer in &others {
+                    if let Some(doc) = self.kv.get(pointer).map(|kv| kv.value().document().clone()) {
+                        merge(&mut merged, doc);
+                    }
+                }
+                merge(&mut merged, d.document().clone());
+                let merged = D::from_value(&merged).map_err(|_| IndexError::DuplicateDocument)?;
+                if self.collisions(&target, &merged).iter().any(|p| !others.contains(p)) {
+                    return Err(IndexError::DuplicateDocument);
+                }
+                for pointer in others.iter().skip(1) {
+                    self._delete(pointer.clone(), true).await;
+                }
+                // the expiry of the merged document is kept
+                self._delete(target.clone(), false).await;
+                self._put(target.clone(), merged, true).await?;
+                Ok((target, Some(resolved)))
+            }
+        }
+    }
+
+    /// pointers of the other documents holding a value of `index_opts` the document holds
+    pub fn collisions(&self, k: &K, d: &D) -> Vec<K> {
+        if self.opts().index_opts.is_empty() {
+            return vec![];
+        }
+        let mut v = d.clone();
+        v.set_opts(&self.opts());
+        let mut others = v.keys().iter()
+            .filter_map(|key| self.hash_idx.get(key).map(|p| p.value().clone()))
+            .filter(|p| p.ne(k) && self.kv.contains_key(p))
+            .collect::<Vec<K>>();
+        others.sort();
+        others.dedup();
+        others
+    }
+
+    /// puts a document in place of the one under the same pointer, keeping its expiry
+    async fn overwrite(&self, k: K, d: D) -> Result<ExecutionTime, IndexError> {
+        if !self.opts().vector_opts.is_empty() {
+            let mut v = d.clone();
+            v.set_opts(&self.opts());
+            self.vector_idx.check(&v)?;
+        }
+        if self.kv.contains_key(&k) {
+            self._delete(k.clone(), false).await;
+        }
         self._put(k, d, true).await
     }
 
     #[inline]
     async fn _put(&self, k: K, d: D, new: bool) -> Result<ExecutionTime, IndexError> {
         let exec = ExecTime::new();
+        if new {
+            self.touch(&k);
+        }
+        let opts = self.opts();
         let mut v = d;
-        v.set_opts(&self.opts);
-
-        if !self.opts.index_opts.is_empty() {
-            // FIXME: need to find a better way to handle this. if same index is found, apply upsert logic
-            if let Err((_err, key)) = self.hash_idx.put(&k, &v) {
-                if let Some((pointer, _value)) = self.get_index(key.as_str()).data {
-                    self.delete(pointer).await;
-                    let _ = self.hash_idx.put(&k, &v);
-                }
+        v.set_opts(&opts);
+
+        if !opts.vector_opts.is_empty() {
+            self.vector_idx.check(&v)?;
+        }
+
+        if !opts.index_opts.is_empty() {
+            // collisions are resolved by `put_with`. stale keys of removed documents are taken over
+            if self.hash_idx.put(&k, &v).is_err() {
+                v.keys().into_iter().for_each(|key| {
+                    self.hash_idx.kv.insert(key, k.clone());
+                });
             }
         }
 
-        if !self.opts.view_opts.is_empty() {
-            if let Some(vw) = v.binding() {
+        if !opts.view_opts.is_empty() {
+            for vw in self.compiled_views().bindings(&v) {
                 self.clips.put_view(&vw, &k);
             }
         }
 
-        if !self.opts.search_opts.is_empty() {
+        if !opts.search_opts.is_empty() {
             if let Some(val) = v.content() {
                 let _ = self.inverted_idx.put(k.clone(), val).await;
             }
         }
 
-        if !self.opts.clips_opts.is_empty() {
+        if !opts.ngram_opts.is_empty() {
+            self.ngram_idx.put(&k, &v);
+        }
+
+        if !opts.geo_opts.is_empty() {
+            self.geo_idx.put(&k, &v);
+        }
+
+        if !opts.vector_opts.is_empty() {
+            self.vector_idx.put(&k, &v);
+        }
+
+        if !opts.clips_opts.is_empty() {
             self.clips.put(&k, &v);
         }
 
-        if !self.opts.range_opts.is_empty() {
+        if !opts.range_opts.is_empty() {
             self.range.put(&k, &v);
         }
 
@@ -168,15 +299,93 @@ impl<D> Collection<D>
         Ok(exec.done())
     }
 
+    /// options of the collection. views dropped since it was created are left out
+    pub fn opts(&self) -> Arc<CollectionOptions> {
+        Arc::clone(&self.opts.read().unwrap())
+    }
+
+    /// views of the current `view_opts`
+    fn compiled_views(&self) -> Arc<Views> {
+        Arc::clone(&self.views.read().unwrap())
+    }
+
+    /// waits until no other write runs. within an atomic script the lock is held until the script is done
+    pub(crate) async fn writing(&self) -> Option<tokio::sync::OwnedMutexGuard<()>> {
+        journal::writing(&self.writes).await
+    }
+
+    /// records the document and expiry of a pointer in the journal of an atomic script, before it is written
+    fn touch(&self, k: &K) {
+        journal::touch(self.opts().name.as_str(), k, || {
+            let doc = self.kv.get(k).map(|v| v.value().string());
+            let ttl = self.bkp.get(prefix_ttl(k.as_str())).ok().flatten()
+                .and_then(|v| String::from_utf8(v.to_vec()).ok()?.parse::<i64>().ok());
+            (doc, ttl)
+        });
+    }
+
+    /// brings pointers back to the documents and expiries they had. the documents under them are removed
+    /// first, so the ones put back never collide with them on `index_opts`
+    pub(crate) async fn undo(&self, previous: Vec<Previous>) {
+        let _writing = self.writing().await;
+        for p in &previous {
+            self._delete(p.k.clone(), true).await;
+        }
+        for p in previous {
+            if let Some(d) = p.doc.and_then(|doc| D::from_str(doc.as_str()).ok()) {
+                let _ = self._put(p.k.clone(), d, true).await;
+            }
+            if let Some(timestamp) = p.ttl {
+                self.put_ttl(p.k, timestamp).await;
+            }
+        }
+    }
+
     /// deletes a document by a `Pointer`
     #[inline]
     pub async fn delete(&self, k: K) -> ExecutionTime {
+        let _writing = self.writing().await;
         self._delete(k, true).await
     }
 
+    /// pointer of the document holding an `index` value
+    #[inline]
+    pub fn index_pointer(&self, index: &str) -> Option<K> {
+        self.hash_idx.get(index)
+            .map(|p| p.value().clone())
+            .filter(|p| self.kv.contains_key(p))
+    }
+
+    /// puts a document in place of the one holding an `index` value, resolving collisions like `put_with`.
+    /// `None` when no document holds it, nothing is written then
+    pub async fn put_index(&self, index: &str, d: D, conflict: Conflict) -> Result<Option<(K, Option<Conflicted>)>, IndexError> {
+        let _writing = self.writing().await;
+        match self.index_pointer(index) {
+            Some(k) => self._put_with(k, d, conflict).await.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// deletes the document holding an `index` value. returns its pointer
+    pub async fn delete_index(&self, index: &str) -> Option<K> {
+        let _writing = self.writing().await;
+        let k = self.index_pointer(index)?;
+        self._delete(k.clone(), true).await;
+        Some(k)
+    }
+
+    /// sets a TTL for the document holding an `index` value. returns its pointer
+    pub async fn ttl_index(&self, index: &str, timestamp: i64) -> Option<K> {
+        let _writing = self.writing().await;
+        let k = self.index_pointer(index)?;
+        self.put_ttl(k.clone(), timestamp).await;
+        Some(k)
+    }
+
     #[inline]
     pub async fn _delete(&self, k: K, rm_ttl: bool) -> ExecutionTime {
         let exec = ExecTime::new();
+        self.touch(&k);
         if self.kv.contains_key(&k) {
             let query = NotificationType::<K, D>::Remove(k.clone());
             let _ = self.watchman.notify(PubSubEvent::Data(query)).await;
@@ -184,14 +393,18 @@ impl<D> Collection<D>
             let (_, v) = self.kv.remove(&k).unwrap();
 
             self.hash_idx.delete(&v.clone());
-            if let Some(view) = v.binding() {
-                self.clips.delete_inner(&view, &k)
+            // conditions on `now()` may not match anymore, so every view lets go of the pointer
+            for view in self.compiled_views().names() {
+                self.clips.delete_inner(view, &k)
             }
 
             if let Some(content) = v.content() {
                 let _ = self.inverted_idx.delete(k.to_string(), content).await;
             }
 
+            self.ngram_idx.delete(&k, &v);
+            self.geo_idx.delete(&k, &v);
+            self.vector_idx.delete(&k, &v);
             self.clips.delete(&k, &v);
             self.range.delete(&k, &v);
 
@@ -210,7 +423,11 @@ impl<D> Collection<D>
     pub async fn delete_by_range(&self, field: &str, from: String, to: String) -> ExecutionTime {
         let exec = ExecTime::new();
         let res = self.fetch_range(field, from, to).data;
+        let guard = guard::current();
         for kv in res.iter() {
+            if guard.check().is_err() {
+                break;
+            }
             self.delete(kv.0.clone()).await;
         }
         exec.done()
@@ -221,12 +438,35 @@ impl<D> Collection<D>
     pub async fn delete_by_clip(&self, clip: &str) -> ExecutionTime {
         let exec = ExecTime::new();
         let res = self.fetch_clip(clip).data;
+        let guard = guard::current();
         for kv in res.iter() {
+            if guard.check().is_err() {
+                break;
+            }
             self.delete(kv.0.clone()).await;
         }
         exec.done()
     }
 
+    /// deletes the documents of a view. returns their pointers, those deleted before a stop only
+    #[inline]
+    pub async fn delete_by_view(&self, view: &str) -> Vec<K> {
+        let keys = match self.clips.get_view(view) {
+            Some(set) => set.value().iter().map(|k| k.key().clone()).collect::<Vec<K>>(),
+            None => vec![],
+        };
+        let mut deleted = Vec::with_capacity(keys.len());
+        let guard = guard::current();
+        for k in keys {
+            if guard.check().is_err() {
+                break;
+            }
+            self.delete(k.clone()).await;
+            deleted.push(k);
+        }
+        deleted
+    }
+
     /// gets documents based on array of `Pointers`
     #[inline]
     pub fn multi_get(&self, keys: Vec<&K>) -> FuncResult<Vec<(K, D)>> {
@@ -280,6 +520,25 @@ impl<D> Collection<D>
         }
     }
 
+    /// gets the projected fields of a document by `Pointer`, with its `_pointer`.
+    /// the document itself is not cloned
+    #[inline]
+    pub fn project(&self, k: &K, projection: &Projection) -> FuncResult<Option<Value>> {
+        let exec = ExecTime::new();
+        let res = self.kv.get(k).map(|kv| projection.make(kv.key().to_string(), kv.value().document()));
+        FuncResult {
+            query: FuncType::Lookup,
+            data: res,
+            time_taken: exec.done(),
+        }
+    }
+
+    /// bytes of json of the document under a pointer, `0` when there is none
+    #[inline]
+    pub fn size(&self, k: &K) -> usize {
+        self.kv.get(k).map_or(0, |kv| kv.value().size())
+    }
+
     /// gets a document by `index` value
     #[inline]
     pub fn get_index(&self, index: &str) -> FuncResult<Option<(K, D)>> {
@@ -328,11 +587,120 @@ impl<D> Collection<D>
         }
     }
 
+    /// pointers of the documents `sets` combine. the smallest set of `all` is copied,
+    /// the others are only looked up
+    pub fn clip_keys(&self, sets: &ClipSets) -> HashSet<K> {
+        // missing sets are empty, so they come first
+        let size = |set: &ClipSet| self.clip_set(set, |keys| keys.len()).unwrap_or_default();
+        let mut all = sets.all.iter().collect::<Vec<&ClipSet>>();
+        all.sort_by_key(|set| size(set));
+        let mut keys = match (all.split_first(), sets.any.is_empty()) {
+            (Some((smallest, rest)), _) => {
+                let mut keys = self.clip_set(smallest, |set| set.iter().map(|k| k.key().clone()).collect::<HashSet<K>>()).unwrap_or_default();
+                for set in rest {
+                    self.clip_set(set, |set| keys.retain(|k| set.contains(k)));
+                }
+                if !sets.any.is_empty() {
+                    let mut found = HashSet::new();
+                    for set in &sets.any {
+                        self.clip_set(set, |set| found.extend(keys.iter().filter(|k| set.contains(*k)).cloned()));
+                    }
+                    keys = found;
+                }
+                keys
+            }
+            (None, false) => {
+                let mut keys = HashSet::new();
+                for set in &sets.any {
+                    self.clip_set(set, |set| keys.extend(set.iter().map(|k| k.key().clone())));
+                }
+                keys
+            }
+            (None, true) => self.kv.iter().map(|kv| kv.key().clone()).collect(),
+        };
+        for set in &sets.none {
+            self.clip_set(set, |set| keys.retain(|k| !set.contains(k)));
+        }
+        keys.retain(|k| self.kv.contains_key(k));
+        keys
+    }
+
+    /// runs `f` on the keys of a clip or a view, `None` when there is no such set.
+    /// one set is borrowed at a time
+    fn clip_set<T>(&self, set: &ClipSet, f: impl FnOnce(&DashSet<K>) -> T) -> Option<T> {
+        let keys = match set {
+            ClipSet::Clip(clip) => self.clips.get(clip),
+            ClipSet::View(view) => self.clips.get_view(view),
+        };
+        keys.map(|keys| f(keys.value()))
+    }
+
+    /// documents `sets` combine
+    pub fn fetch_clips(&self, sets: &ClipSets) -> FuncResult<Vec<(K, D)>> {
+        let exec = ExecTime::new();
+        let res = self.clip_keys(sets)
+            .into_iter()
+            .filter_map(|k| self.kv.get(&k).map(|kv| (k, kv.value().clone())))
+            .collect();
+        FuncResult {
+            query: FuncType::FetchClips,
+            data: res,
+            time_taken: exec.done(),
+        }
+    }
+
+    /// top `size` values of every clip field among the given keys, with hit counts.
+    /// a value counts the keys in its clip set, no document is read
+    #[inline]
+    pub fn facets(&self, keys: &[K], fields: &[&str], size: usize) -> FuncResult<Vec<Facet>> {
+        let exec = ExecTime::new();
+        let keys = keys.iter().collect::<HashSet<&K>>();
+        let res = fields
+            .iter()
+            .map(|field| {
+                let mut values = match self.clips.fields.get(*field) {
+                    Some(sets) => sets
+                        .value()
+                        .par_iter()
+                        .filter_map(|set| {
+                            // the smaller side of the intersection is walked
+                            let count = if set.value().len() <= keys.len() {
+                                set.value().iter().filter(|k| keys.contains(k.key())).count()
+                            } else {
+                                keys.iter().filter(|k| set.value().contains(**k)).count()
+                            };
+                            (count > 0).then(|| FacetValue { value: set.key().to_string(), count })
+                        })
+                        .collect::<Vec<FacetValue>>(),
+                    None => vec![],
+                };
+                values.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+                values.truncate(size);
+                Facet { field: field.to_string(), values }
+            })
+            .collect::<Vec<Facet>>();
+        FuncResult {
+            query: FuncType::Facets(fields.iter().map(|f| f.to_string()).collect()),
+            data: res,
+            time_taken: exec.done(),
+        }
+    }
+
+    /// facets of the documents matching a search
+    #[inline]
+    pub fn search_facets(&self, query: &str, fields: &[&str], size: usize) -> FuncResult<Vec<Facet>> {
+        let exec = ExecTime::new();
+        let keys = self.inverted_idx.find(query);
+        let mut res = self.facets(&keys, fields, size);
+        res.time_taken = exec.done();
+        res
+    }
+
     /// fetch a view by `name`
     #[inline]
     pub fn fetch_view(&self, view_name: &str) -> FuncResult<Vec<(K, Value)>> {
         let exec = ExecTime::new();
-        let res = match self.clips.get_view(view_name) {
+        let mut res = match self.clips.get_view(view_name) {
             Some(v) => {
                 let mut res = Vec::with_capacity(v.value().len());
                 for k in v.value().iter() {
@@ -345,6 +713,13 @@ impl<D> Collection<D>
             }
             None => vec![]
         };
+        // the view is sorted on whole documents, then projected
+        if let Some(shape) = self.compiled_views().shape(view_name) {
+            sort::sort_by(&mut res, &shape.sort, |(_, d), i| lookup(d, &shape.sort[i].field));
+            if !shape.projection.is_empty() {
+                res.par_iter_mut().for_each(|(_, d)| *d = shape.projection.apply(d));
+            }
+        }
         FuncResult {
             query: FuncType::FetchView(view_name.to_string()),
             data: res,
@@ -352,13 +727,134 @@ impl<D> Collection<D>
         }
     }
 
-    /// Search like type as you go
+    /// every view of `view_opts` with the number of documents in it
+    pub fn views(&self) -> Vec<(String, usize)> {
+        let mut res: Vec<(String, usize)> = vec![];
+        for cfg in &self.opts().view_opts {
+            if !res.iter().any(|(name, _)| name.eq(&cfg.view_name)) {
+                let size = self.clips.get_view(&cfg.view_name).map_or(0, |set| set.value().len());
+                res.push((cfg.view_name.to_string(), size));
+            }
+        }
+        res
+    }
+
+    /// removes a view from `view_opts` and lets go of the documents in it, the documents stay.
+    /// `persist` stores the options left before writes go on
+    pub async fn drop_view<F>(&self, view: &str, persist: F) -> Result<(), CollectionError>
+        where F: FnOnce(&CollectionOptions) {
+        let _writing = self.writing().await;
+        let before = self.opts().view_opts.clone();
+        if !before.iter().any(|cfg| cfg.view_name.eq(view)) {
+            return Err(CollectionError::NoSuchView);
+        }
+        journal::views(self.opts().name.as_str(), before.clone());
+        let view_opts = before.into_iter().filter(|cfg| cfg.view_name.ne(view)).collect();
+        self.set_view_opts(view_opts, persist);
+        Ok(())
+    }
+
+    /// replaces `view_opts`, like `drop_view` does
+    pub(crate) async fn put_view_opts<F>(&self, view_opts: Vec<ViewConfig>, persist: F)
+        where F: FnOnce(&CollectionOptions) {
+        let _writing = self.writing().await;
+        self.set_view_opts(view_opts, persist);
+    }
+
+    /// views left out of `view_opts` let go of their documents, views added take the documents they match
+    fn set_view_opts<F>(&self, view_opts: Vec<ViewConfig>, persist: F) where F: FnOnce(&CollectionOptions) {
+        let previous = self.compiled_views();
+        let mut opts = (*self.opts()).clone();
+        opts.view_opts = view_opts;
+        persist(&opts);
+        let views = Arc::new(Views::new(&opts.view_opts));
+        *self.opts.write().unwrap() = Arc::new(opts);
+        *self.views.write().unwrap() = Arc::clone(&views);
+        for name in previous.names().iter().filter(|name| !views.names().contains(name)) {
+            self.clips.drop_view(name);
+        }
+        let added = views.names().iter().filter(|name| !previous.names().contains(name)).collect::<Vec<&String>>();
+        if !added.is_empty() {
+            self.kv.par_iter().for_each(|kv| {
+                for vw in views.bindings(kv.value()).iter().filter(|vw| added.contains(vw)) {
+                    self.clips.put_view(vw, kv.key());
+                }
+            });
+        }
+    }
+
+    /// puts every document in the views it belongs to now, like conditions on `now()` decide.
+    /// writes wait until the views are rebuilt
+    pub async fn rebuild_views(&self) -> FuncResult<Vec<(String, usize)>> {
+        let exec = ExecTime::new();
+        let _writing = self.writing().await;
+        let (opts, views) = (self.opts(), self.compiled_views());
+        for cfg in &opts.view_opts {
+            self.clips.drop_view(&cfg.view_name);
+        }
+        if !opts.view_opts.is_empty() {
+            self.kv.par_iter().for_each(|kv| {
+                for vw in views.bindings(kv.value()) {
+                    self.clips.put_view(&vw, kv.key());
+                }
+            });
+        }
+        FuncResult {
+            query: FuncType::RebuildViews,
+            data: self.views(),
+            time_taken: exec.done(),
+        }
+    }
+
+    /// Search like type as you go. best matches come first
     #[inline]
     pub fn search(&self, query: &str) -> FuncResult<Vec<(K, D)>> {
-        let text = query.to_string();
         let exec = ExecTime::new();
-        let words: Vec<&str> = text.split_whitespace().collect();
-        let keys = self.inverted_idx.find(words);
+        let keys = self.inverted_idx.find(query);
+        self.search_result(keys, exec)
+    }
+
+    /// Search restricted to the given `search_opts` fields. best matches come first
+    #[inline]
+    pub fn search_in(&self, query: &str, fields: &[&str]) -> FuncResult<Vec<(K, D)>> {
+        let exec = ExecTime::new();
+        let fields = fields.iter().map(|f| f.to_string()).collect::<Vec<String>>();
+        let keys = self.inverted_idx.find_in(query, &fields);
+        self.search_result(keys, exec)
+    }
+
+    /// Search restricted to the given `search_opts` fields, or all of them when empty.
+    /// every hit keeps highlighted fragments of its matching fields
+    pub fn search_highlight(&self, query: &str, fields: &[&str], opts: &HighlightOptions) -> FuncResult<Vec<SearchHit<K, D>>> {
+        let exec = ExecTime::new();
+        let fields = if fields.is_empty() {
+            self.opts().search_opts.clone()
+        } else {
+            fields.iter().map(|f| f.to_string()).collect::<Vec<String>>()
+        };
+        let keys = self.inverted_idx.find_in(query, &fields);
+        let mut res = Vec::with_capacity(keys.len());
+        for key in keys {
+            if let Some(kv) = self.kv.get(&key) {
+                let highlights = kv.value()
+                    .content()
+                    .map(|content| self.inverted_idx.highlight(query, content, &fields, opts))
+                    .unwrap_or_default();
+                res.push(SearchHit {
+                    key: kv.key().clone(),
+                    document: kv.value().clone(),
+                    highlights,
+                });
+            }
+        }
+        FuncResult {
+            query: FuncType::Lookup,
+            data: res,
+            time_taken: exec.done(),
+        }
+    }
+
+    fn search_result(&self, keys: Vec<K>, exec: ExecTime) -> FuncResult<Vec<(K, D)>> {
         let mut res = Vec::with_capacity(keys.len());
         for key in keys {
             if let Some(kv) = self.kv.get(&key) {
@@ -373,13 +869,142 @@ impl<D> Collection<D>
         }
     }
 
+    /// case-insensitive substring search on `ngram_opts` fields, or the given ones.
+    /// trigram candidates are verified against the field value
+    #[inline]
+    pub fn contains(&self, text: &str, fields: &[&str]) -> FuncResult<Vec<(K, D)>> {
+        let exec = ExecTime::new();
+        let fields = if fields.is_empty() {
+            self.opts().ngram_opts.clone()
+        } else {
+            fields.iter().map(|f| f.to_string()).collect::<Vec<String>>()
+        };
+        let needle = text.to_lowercase();
+        let mut keys = HashSet::new();
+        for field in fields.iter() {
+            keys.extend(self.ngram_idx.candidates(field.as_str(), text));
+        }
+        let mut res = Vec::with_capacity(keys.len());
+        for key in keys {
+            if let Some(kv) = self.kv.get(&key) {
+                let found = kv.value()
+                    .grams()
+                    .iter()
+                    .any(|f| fields.contains(&f.key) && f.value.to_lowercase().contains(needle.as_str()));
+                if found {
+                    res.push((kv.key().clone(), kv.value().clone()));
+                }
+            }
+        }
+        res.sort_by(|a, b| a.0.cmp(&b.0));
+        FuncResult {
+            query: FuncType::Contains(text.to_string()),
+            data: res,
+            time_taken: exec.done(),
+        }
+    }
+
+    /// documents with a point of `field` within `radius` meters of `point`, nearest first.
+    /// every document comes with its distance in meters
+    #[inline]
+    pub fn near(&self, field: &str, point: Point, radius: f64, limit: Option<usize>) -> FuncResult<Vec<(K, D, f64)>> {
+        let exec = ExecTime::new();
+        let keys = self.geo_idx.near(field, &point, radius);
+        let res = self.geo_result(keys, field, &point, limit, |p| p.distance(&point) <= radius);
+        FuncResult {
+            query: FuncType::Near(field.to_string()),
+            data: res,
+            time_taken: exec.done(),
+        }
+    }
+
+    /// documents with a point of `field` inside the box of south-west `sw` and north-east `ne` corners.
+    /// nearest to the center of the box first
+    #[inline]
+    pub fn within_box(&self, field: &str, sw: Point, ne: Point, limit: Option<usize>) -> FuncResult<Vec<(K, D, f64)>> {
+        let exec = ExecTime::new();
+        let keys = self.geo_idx.within_box(field, &sw, &ne);
+        let center = Point { lat: (sw.lat + ne.lat) / 2.0, lon: (sw.lon + ne.lon) / 2.0 };
+        let inside = |p: &Point| {
+            let lon = if sw.lon <= ne.lon {
+                p.lon >= sw.lon && p.lon <= ne.lon
+            } else {
+                p.lon >= sw.lon || p.lon <= ne.lon
+            };
+            lon && p.lat >= sw.lat && p.lat <= ne.lat
+        };
+        let res = self.geo_result(keys, field, &center, limit, inside);
+        FuncResult {
+            query: FuncType::WithinBox(field.to_string()),
+            data: res,
+            time_taken: exec.done(),
+        }
+    }
+
+    fn geo_result(&self, keys: HashSet<K>, field: &str, from: &Point, limit: Option<usize>, keep: impl Fn(&Point) -> bool) -> Vec<(K, D, f64)> {
+        let mut res = vec![];
+        for key in keys {
+            if let Some(kv) = self.kv.get(&key) {
+                let distance = kv.value()
+                    .points()
+                    .iter()
+                    .filter(|f| f.key.eq(field) && keep(&f.point))
+                    .map(|f| f.point.distance(from))
+                    .min_by(|a, b| a.total_cmp(b));
+                if let Some(distance) = distance {
+                    res.push((kv.key().clone(), kv.value().clone(), distance));
+                }
+            }
+        }
+        res.sort_by(|a, b| a.2.total_cmp(&b.2).then_with(|| a.0.cmp(&b.0)));
+        if let Some(limit) = limit {
+            res.truncate(limit);
+        }
+        res
+    }
+
+    /// `k` nearest documents of a vector field passing `filter`, nearest first.
+    /// every document comes with its score. cosine similarity, dot product or euclidean distance
+    #[inline]
+    pub fn knn<F>(&self, field: &str, vector: &[f32], k: usize, filter: F) -> Result<FuncResult<Scored<D>>, IndexError>
+        where F: Fn(&K, &D) -> bool
+    {
+        let exec = ExecTime::new();
+        let total = self.vector_idx.len(field);
+        let mut ef = k.max(1);
+        let res = loop {
+            let found = self.vector_idx.search(field, vector, ef)?;
+            let searched = found.len();
+            let mut res = Vec::with_capacity(k);
+            for (key, score) in found {
+                if res.len() == k {
+                    break;
+                }
+                if let Some(kv) = self.kv.get(&key) {
+                    if filter(kv.key(), kv.value()) {
+                        res.push((kv.key().clone(), kv.value().clone(), score));
+                    }
+                }
+            }
+            // an approximate search looks at more candidates until enough pass the filter
+            if res.len() == k || searched >= total || ef >= total {
+                break res;
+            }
+            ef *= 2;
+        };
+        Ok(FuncResult {
+            query: FuncType::Knn(field.to_string()),
+            data: res,
+            time_taken: exec.done(),
+        })
+    }
+
     /// search in inverted index
     #[inline]
     pub fn like_search(&self, query: &str) -> FuncResult<ArrayQueue<(K, D)>> {
         let text = query.to_string();
         let exec = ExecTime::new();
-        let words: Vec<&str> = text.split_whitespace().collect();
-        let keys = self.inverted_idx.w_find(words);
+        let keys = self.inverted_idx.w_find(query);
         if keys.len() > 0 {
             let res = ArrayQueue::new(keys.len());
             keys.par_iter().for_each(|key| {
@@ -402,6 +1027,222 @@ impl<D> Collection<D>
         }
     }
 
+    /// replaces the analyzer of a search field and re-indexes the field with it.
+    /// writes wait until the field is re-indexed
+    pub async fn set_analyzer(&self, field: &str, analyzer: Arc<dyn Analyzer>) {
+        let _writing = self.writing().await;
+        let docs = self.kv
+            .iter()
+            .filter_map(|kv| kv.value().content().map(|content| (kv.key().clone(), content)))
+            .collect::<Vec<(K, Vec<Field>)>>();
+        self.inverted_idx.set_analyzer(field, analyzer, docs);
+    }
+
+    /// documents a `flql` condition has to be evaluated on. indexed comparisons
+    /// joined by `&&` and `||` narrow it down to index candidates
+    pub fn plan(&self, condition: &str) -> Plan<K> {
+        let opts = self.opts();
+        let planner = Planner {
+            opts: &opts,
+            hash_idx: &self.hash_idx,
+            clips: &self.clips,
+            range: &self.range,
+            inverted_idx: &self.inverted_idx,
+            ngram_idx: &self.ngram_idx,
+        };
+        match expr::parse(condition).ok().and_then(|e| planner.candidates(&e)) {
+            Some(keys) => Plan::Candidates(keys),
+            None => Plan::Scan,
+        }
+    }
+
+    /// maps the documents of the `condition` plan, in parallel. `None` skips a document
+    pub fn when<T, F>(&self, condition: &str, f: F) -> Vec<T>
+        where T: Send, F: Fn(&K, &D) -> Option<T> + Send + Sync
+    {
+        self.scan(self.plan(condition), f)
+    }
+
+    /// maps the documents of a plan, in parallel. `None` skips a document
+    pub fn scan<T, F>(&self, plan: Plan<K>, f: F) -> Vec<T>
+        where T: Send, F: Fn(&K, &D) -> Option<T> + Send + Sync
+    {
+        // documents left once the statement is stopped are skipped
+        let guard = guard::current();
+        let f = |k: &K, d: &D| guard.check().ok().and_then(|_| f(k, d));
+        match plan {
+            Plan::Scan => self.kv.par_iter().filter_map(|kv| f(kv.key(), kv.value())).collect(),
+            Plan::Candidates(keys) => keys
+                .par_iter()
+                .filter_map(|k| self.kv.get(k).and_then(|kv| f(kv.key(), kv.value())))
+                .collect(),
+        }
+    }
+
+    /// groups the documents of the `condition` plan that pass `filter`, in parallel.
+    /// every document is aggregated without a condition
+    pub fn aggregate<F>(&self, condition: Option<&str>, aggregation: &Aggregation, filter: F) -> FuncResult<Vec<Value>>
+        where F: Fn(&K, &D) -> bool + Send + Sync
+    {
+        let exec = ExecTime::new();
+        let guard = guard::current();
+        let add = |mut groups: Groups, k: &K, d: &D| {
+            if guard.check().is_ok() && filter(k, d) {
+                aggregation.add(&mut groups, d.document());
+            }
+            groups
+        };
+        let groups = match condition.map(|c| self.plan(c)) {
+            Some(Plan::Candidates(keys)) => keys
+                .par_iter()
+                .filter_map(|k| self.kv.get(k))
+                .fold(Groups::default, |groups, kv| add(groups, kv.key(), kv.value()))
+                .reduce(Groups::default, |a, b| aggregation.merge(a, b)),
+            _ => self.kv
+                .par_iter()
+                .fold(Groups::default, |groups, kv| add(groups, kv.key(), kv.value()))
+                .reduce(Groups::default, |a, b| aggregation.merge(a, b)),
+        };
+        FuncResult {
+            query: FuncType::Aggregate,
+            data: aggregation.rows(groups),
+            time_taken: exec.done(),
+        }
+    }
+
+    /// number of documents of the `condition` plan that pass `filter`, in parallel.
+    /// every document is counted without a condition
+    pub fn count<F>(&self, condition: Option<&str>, filter: F) -> FuncResult<usize>
+        where F: Fn(&K, &D) -> bool + Send + Sync
+    {
+        let exec = ExecTime::new();
+        let guard = guard::current();
+        let filter = |k: &K, d: &D| guard.check().is_ok() && filter(k, d);
+        let count = match condition.map(|c| self.plan(c)) {
+            Some(Plan::Candidates(keys)) => keys
+                .par_iter()
+                .filter(|k| self.kv.get(*k).is_some_and(|kv| filter(kv.key(), kv.value())))
+                .count(),
+            _ => self.kv.par_iter().filter(|kv| filter(kv.key(), kv.value())).count(),
+        };
+        FuncResult {
+            query: FuncType::Count,
+            data: count,
+            time_taken: exec.done(),
+        }
+    }
+
+    /// whether a document of the `condition` plan passes `filter`. the scan stops at the first one
+    pub fn exists_when<F>(&self, condition: Option<&str>, filter: F) -> FuncResult<bool>
+        where F: Fn(&K, &D) -> bool + Send + Sync
+    {
+        let exec = ExecTime::new();
+        let guard = guard::current();
+        let filter = |k: &K, d: &D| guard.check().is_ok() && filter(k, d);
+        let exists = match condition.map(|c| self.plan(c)) {
+            Some(Plan::Candidates(keys)) => keys
+                .par_iter()
+                .any(|k| self.kv.get(k).is_some_and(|kv| filter(kv.key(), kv.value()))),
+            _ => self.kv.par_iter().any(|kv| filter(kv.key(), kv.value())),
+        };
+        FuncResult {
+            query: FuncType::Exists,
+            data: exists,
+            time_taken: exec.done(),
+        }
+    }
+
+    /// distinct values of `field` in the documents of the `condition` plan that pass `filter`, in their sort order.
+    /// missing and null values are skipped, only the first document holding a value is copied
+    pub fn distinct<F>(&self, field: &str, condition: Option<&str>, filter: F) -> FuncResult<Vec<Value>>
+        where F: Fn(&K, &D) -> bool + Send + Sync
+    {
+        let exec = ExecTime::new();
+        let guard = guard::current();
+        let add = |mut values: HashMap<String, Value>, k: &K, d: &D| {
+            if guard.check().is_ok() && filter(k, d) {
+                let v = lookup(d.document(), field);
+                if !v.is_null() {
+                    values.entry(v.to_string()).or_insert_with(|| v.clone());
+                }
+            }
+            values
+        };
+        let merge = |mut a: HashMap<String, Value>, b: HashMap<String, Value>| {
+            a.extend(b);
+            a
+        };
+        let values = match condition.map(|c| self.plan(c)) {
+            Some(Plan::Candidates(keys)) => keys
+                .par_iter()
+                .filter_map(|k| self.kv.get(k))
+                .fold(HashMap::new, |values, kv| add(values, kv.key(), kv.value()))
+                .reduce(HashMap::new, merge),
+            _ => self.kv
+                .par_iter()
+                .fold(HashMap::new, |values, kv| add(values, kv.key(), kv.value()))
+                .reduce(HashMap::new, merge),
+        };
+        let mut values = values.into_values().collect::<Vec<Value>>();
+        values.sort_by(sort::compare);
+        FuncResult {
+            query: FuncType::Distinct(field.to_string()),
+            data: values,
+            time_taken: exec.done(),
+        }
+    }
+
+    /// documents whose `field` holds one of `values`, with their `_pointer`, keyed by the value they match.
+    /// pointers and hash indexed fields are looked up, other fields are scanned once
+    pub fn join(&self, field: &str, values: &[&Value]) -> FuncResult<HashMap<String, Vec<Value>>> {
+        let exec = ExecTime::new();
+        let projection = Projection::default();
+        let indexed = field == POINTER || self.opts().index_opts.iter().any(|f| f == field);
+        let found = if indexed {
+            values
+                .iter()
+                .filter_map(|v| Some((key(v)?, forms(v))))
+                .collect::<HashMap<String, Vec<String>>>()
+                .into_par_iter()
+                .filter_map(|(matched, texts)| {
+                    let pointer = texts.iter().find_map(|f| match field {
+                        POINTER => self.kv.get(f).map(|kv| kv.key().clone()),
+                        _ => self.hash_idx.get(f).map(|k| k.value().clone()),
+                    })?;
+                    let kv = self.kv.get(&pointer)?;
+                    // hash indexes of every field share their keys
+                    let holds = field == POINTER || scalars(lookup(kv.value().document(), field)).iter().any(|v| forms(v).contains(&matched));
+                    holds.then(|| (matched, vec![projection.make(pointer, kv.value().document())]))
+                })
+                .collect::<HashMap<String, Vec<Value>>>()
+        } else {
+            let wanted = values.iter().filter_map(|v| key(v)).collect::<HashSet<String>>();
+            let mut matches = self.kv
+                .par_iter()
+                .filter_map(|kv| {
+                    let doc = kv.value().document();
+                    let mut keys = scalars(lookup(doc, field)).into_iter().filter_map(key).filter(|k| wanted.contains(k)).collect::<Vec<String>>();
+                    keys.sort();
+                    keys.dedup();
+                    (!keys.is_empty()).then(|| (kv.key().clone(), keys, projection.make(kv.key().clone(), doc)))
+                })
+                .collect::<Vec<(K, Vec<String>, Value)>>();
+            matches.sort_by(|a, b| a.0.cmp(&b.0));
+            let mut found: HashMap<String, Vec<Value>> = HashMap::new();
+            for (_, keys, doc) in matches {
+                for k in keys {
+                    found.entry(k).or_default().push(doc.clone());
+                }
+            }
+            found
+        };
+        FuncResult {
+            query: FuncType::Join(field.to_string()),
+            data: found,
+            time_taken: exec.done(),
+        }
+    }
+
     /// returns an parallel iterator on `Flinch` storage
     #[inline]
     pub fn iter(&self) -> Iter<'_, K, D> {
@@ -437,6 +1278,9 @@ impl<D> Collection<D>
             self.delete(kv.0).await;
         }
         self.clips.clear_keys();
+        self.ngram_idx.clear();
+        self.geo_idx.clear();
+        self.vector_idx.clear();
         self.range.clear_trees();
     }
     /// allows to save documents in `sled` storage on demand
@@ -0,0 +1,63 @@
+use std::cmp::Ordering;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::headers::Sort;
+use crate::sort::compare_values;
+
+/// `Cursor` is where a page ends, the sort values and pointer of its last document.
+/// clients get it as hex encoded json and only hand it back
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub(crate) struct Cursor {
+    /// the sort clause it was made for
+    sort: String,
+    values: Vec<Value>,
+    pointer: String,
+}
+
+/// `page(offset, limit)` or `page('cursor', limit)`
+pub(crate) enum Page {
+    Offset(usize, usize),
+    After(Cursor, usize),
+}
+
+impl Cursor {
+    pub(crate) fn new(sort: &[Sort], values: &[Value], pointer: &str) -> Self {
+        Cursor { sort: signature(sort), values: values.to_vec(), pointer: pointer.to_string() }
+    }
+
+    pub(crate) fn encode(&self) -> String {
+        serde_json::to_vec(self).unwrap_or_default().iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// a cursor is only valid for the sort clause it was made for
+    pub(crate) fn decode(text: &str, sort: &[Sort]) -> Result<Self, String> {
+        let invalid = || format!("invalid cursor `{}`", text);
+        if !text.len().is_multiple_of(2) || !text.is_ascii() {
+            return Err(invalid());
+        }
+        let bytes = (0..text.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&text[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| invalid())?;
+        let cursor = serde_json::from_slice::<Cursor>(&bytes).map_err(|_| invalid())?;
+        if cursor.sort != signature(sort) || cursor.values.len() != sort.len() {
+            return Err(format!("cursor `{}` was made for another sort", text));
+        }
+        Ok(cursor)
+    }
+
+    /// order of a document against the one the cursor stands on
+    pub(crate) fn compare(&self, sort: &[Sort], values: &[Value], pointer: &str) -> Ordering {
+        compare_values(values, &self.values, sort).then_with(|| pointer.cmp(&self.pointer))
+    }
+}
+
+fn signature(sort: &[Sort]) -> String {
+    sort.iter()
+        .map(|s| format!("{} {:?} {:?}", s.field, s.direction, s.nulls))
+        .collect::<Vec<String>>()
+        .join(",")
+}
@@ -8,15 +8,18 @@ use serde::{Deserialize, Serialize};
 use serde::de::DeserializeOwned;
 use sled::Db;
 
+use crate::analyzer::AnalyzerConfig;
 use crate::collection::Collection;
 use crate::doc_trait::{Document, ViewConfig};
 use crate::errors::CollectionError;
+use crate::journal;
 use crate::persistent::Persistent;
 use crate::pri_headers::INTERNAL_COL;
 use crate::utils::{COL_PREFIX, database_path, get_col_name, prefix_col_name};
+use crate::views;
 
 /// `CollectionOptions` is used while creating a collection
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct CollectionOptions {
     pub name: String,
     pub index_opts: Vec<String>,
@@ -24,6 +27,80 @@ pub struct CollectionOptions {
     pub view_opts: Vec<ViewConfig>,
     pub range_opts: Vec<String>,
     pub clips_opts: Vec<String>,
+    /// analyzers of `search_opts` fields. fields without one use the standard analyzer
+    #[serde(default)]
+    pub analyzer_opts: Vec<AnalyzerConfig>,
+    /// ranking weight of `search_opts` fields. fields without one weigh `1.0`
+    #[serde(default)]
+    pub boost_opts: Vec<FieldBoost>,
+    /// fields indexed by trigrams for `contains` search
+    #[serde(default)]
+    pub ngram_opts: Vec<String>,
+    /// `{lat, lon}` fields indexed by geohash for `near` and `within_box` queries
+    #[serde(default)]
+    pub geo_opts: Vec<String>,
+    /// embedding fields indexed for `knn` queries
+    #[serde(default)]
+    pub vector_opts: Vec<VectorConfig>,
+    /// what a put does when its document holds a value of `index_opts` another document holds
+    #[serde(default)]
+    pub conflict_opts: Conflict,
+}
+
+/// resolution of a put colliding with other documents on a unique index
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Conflict {
+    /// fails with `IndexError::DuplicateDocument`, nothing is written
+    Reject,
+    /// deletes the other documents
+    #[default]
+    Replace,
+    /// merges the document into the other one, kept under its pointer. nested objects are merged
+    /// field by field. a document colliding with more than one other merges them all into the first,
+    /// the rest are deleted
+    Merge,
+}
+
+/// `FieldBoost` multiplies the score of search matches found in `field`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FieldBoost {
+    pub field: String,
+    pub boost: f32,
+}
+
+/// `VectorConfig` declares an embedding field. its values are arrays of `dimension` numbers
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct VectorConfig {
+    pub field: String,
+    pub dimension: usize,
+    #[serde(default)]
+    pub metric: Metric,
+    #[serde(default)]
+    pub mode: VectorMode,
+}
+
+/// similarity of two vectors
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Metric {
+    #[default]
+    Cosine,
+    Dot,
+    L2,
+}
+
+/// how `knn` finds the neighbours
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub enum VectorMode {
+    /// compares with every vector
+    #[default]
+    Exact,
+    /// approximate search on a hierarchical navigable small world graph.
+    /// `m` links per node, `ef_construction` and `ef_search` candidates while inserting and searching
+    Hnsw {
+        m: usize,
+        ef_construction: usize,
+        ef_search: usize,
+    },
 }
 
 /// `Database<D>` keeps a bunch of collections. Where `D` inherits `Document`
@@ -92,9 +169,14 @@ impl<D> Database<D>
         if let Err(err) = self.exi(name.as_str()) {
             return Err(err);
         }
+        if let Err(err) = views::check(&opts.view_opts) {
+            warn!("collection - {} not added. {}", name, err);
+            return Err(CollectionError::OptionsProvidedAreNotValid);
+        }
         let col = Collection::<D>::new(&self.persist, opts.to_owned()).await;
         self.storage.insert(name.to_owned(), col);
         self.internal_tree.put_any(prefix_col_name(name.as_str()), opts);
+        journal::created(name);
 
         info!("collection - {} added",name);
 
@@ -114,24 +196,47 @@ impl<D> Database<D>
         if let None = self.storage.get(name) {
             return Err(CollectionError::NoSuchCollection);
         }
-        {
+        let opts = {
             let col = self.using(name);
             let col = col.unwrap();
             col.value().empty().await;
-        }
+            (*col.value().opts()).clone()
+        };
         self.storage.remove(name);
         self.internal_tree.remove(prefix_col_name(name)).expect("remove from local storage");
         self.persist.drop_tree(name).expect("drop collection from local storage");
+        journal::dropped(opts);
 
         warn!("collection - {} dropped",name);
 
         Ok(())
     }
 
+    /// removes a view from the options of a collection by `name`. the documents stay,
+    /// the other views keep theirs
+    pub async fn drop_view(&self, name: &str, view: &str) -> Result<(), CollectionError> {
+        let col = self.using(name).map(|col| Arc::clone(col.value()))?;
+        col.drop_view(view, |opts| self.persist_opts(opts)).await?;
+        warn!("view - {} of collection - {} dropped", view, name);
+        Ok(())
+    }
+
+    /// gives a collection by `name` the views of `view_opts`
+    pub(crate) async fn put_view_opts(&self, name: &str, view_opts: Vec<ViewConfig>) {
+        let col = self.using(name).map(|col| Arc::clone(col.value()));
+        if let Ok(col) = col {
+            col.put_view_opts(view_opts, |opts| self.persist_opts(opts)).await;
+        }
+    }
+
     pub fn delete_disk_dir(&self) -> std::io::Result<()> {
         std::fs::remove_dir_all(self.db_path.as_str())
     }
 
+    fn persist_opts(&self, opts: &CollectionOptions) {
+        self.internal_tree.put_any(prefix_col_name(opts.name.as_str()), opts.clone());
+    }
+
     fn exi(&self, name: &str) -> Result<(), CollectionError> {
         if let Some(_) = self.storage.get(name) {
             return Err(CollectionError::DuplicateCollection);
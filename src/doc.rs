@@ -2,7 +2,7 @@ use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
 
 use crate::database::CollectionOptions;
-use crate::doc_trait::{Clips, Document, DocumentSearch, Field, Index, Range, View, ViewConfig};
+use crate::doc_trait::{Clips, Document, DocumentSearch, Field, Geo, GeoField, Grams, Index, Point, Range, VectorField, Vectors, View, ViewConfig};
 use crate::errors::DocumentError;
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -13,125 +13,147 @@ pub struct QueryBased {
     fields: Option<Vec<String>>,
     view_cfg: Vec<ViewConfig>,
     content: Option<Vec<String>>,
+    grams: Option<Vec<String>>,
+    geo: Option<Vec<String>>,
+    vectors: Option<Vec<String>>,
+    /// bytes of json of `data`, measured when the document is put
+    #[serde(skip)]
+    size: usize,
 }
 
 impl Index for QueryBased {
     fn keys(&self) -> Vec<String> {
-        if self.keys.is_some() {
-            let obj = self.object();
-            let mut indexes = vec![];
-            for key in self.keys.clone().unwrap() {
-                if obj.contains_key(&key) {
-                    let chk = obj.get(&key)
-                        .unwrap()
-                        .as_str();
-                    if chk.is_some() {
-                        let v = chk.unwrap()
-                            .to_string();
-                        indexes.push(v);
-                    }
-                }
-            }
-            indexes
-        } else {
-            vec![]
+        match &self.keys {
+            Some(keys) => self.values(keys).into_iter().map(|f| f.value).collect(),
+            None => vec![],
         }
     }
 }
 
 impl Clips for QueryBased {
-    fn tokens(&self) -> Vec<String> {
-        if self.tokens.is_some() {
-            let obj = self.object();
-            let mut tokens = vec![];
-            for token in self.tokens.clone().unwrap() {
-                if obj.contains_key(&token) {
-                    let v = obj.get(&token)
-                        .unwrap();
-                    if v.as_str().is_some() {
-                        let chk = v
-                            .as_str();
-                        if chk.is_some() {
-                            let v = chk.unwrap()
-                                .to_string();
-                            tokens.push(v);
-                        }
-                    }
-                }
-            }
-            tokens
-        } else {
-            vec![]
+    fn tokens(&self) -> Vec<Field> {
+        match &self.tokens {
+            Some(tokens) => self.values(tokens),
+            None => vec![],
         }
     }
 }
 
 impl Range for QueryBased {
     fn fields(&self) -> Vec<Field> {
-        if self.fields.is_some() {
-            let obj = self.object();
-            let mut rfields = vec![];
-            let fields = self.fields.clone().unwrap();
-            for field in fields {
-                let f = field.as_str();
-                if obj.contains_key(f.clone()) {
-                    rfields.push(
-                        Field {
-                            key: f.clone().to_string(),
-                            value: obj
-                                .get(f.clone())
-                                .unwrap()
-                                .to_string(),
-                        }
-                    );
+        match &self.fields {
+            Some(fields) => self.values(fields),
+            None => vec![],
+        }
+    }
+}
+
+impl View for QueryBased {
+    fn bindings(&self) -> Vec<String> {
+        let mut res: Vec<String> = vec![];
+        let obj = self.object();
+        for cfg in self.view_cfg.iter().filter(|cfg| cfg.condition.is_none()) {
+            if let Some(v) = obj.get(&cfg.prop) {
+                // `18` and `"18"` both match a number or a string
+                let expected = cfg.expected.trim_matches('"');
+                let bound = v.to_string().eq(&cfg.expected) || scalars(v).iter().any(|s| s.eq(expected));
+                if bound && !res.contains(&cfg.view_name) {
+                    res.push(cfg.view_name.to_string());
                 }
             }
-            rfields
-        } else {
-            vec![]
         }
+        res
     }
 }
 
-impl View for QueryBased {
-    fn binding(&self) -> Option<String> {
-        if self.view_cfg.len() > 0 {
+impl DocumentSearch for QueryBased {
+    fn content(&self) -> Option<Vec<Field>> {
+        self.content.as_ref().map(|content| self.values(content))
+    }
+}
+
+impl Grams for QueryBased {
+    fn grams(&self) -> Vec<Field> {
+        match &self.grams {
+            Some(grams) => self.values(grams),
+            None => vec![],
+        }
+    }
+}
+
+impl Geo for QueryBased {
+    fn points(&self) -> Vec<GeoField> {
+        let mut res = vec![];
+        if let Some(geo) = &self.geo {
             let obj = self.object();
-            let vw_cfg = self.view_cfg.clone();
-            for cfg in vw_cfg {
-                if obj.contains_key(&cfg.prop) {
-                    let v = obj.get(&cfg.prop)
-                        .unwrap()
-                        .to_string();
-                    if v.eq(&cfg.expected) {
-                        return Some(cfg.view_name);
+            for field in geo {
+                if let Some(v) = obj.get(field) {
+                    for point in points(v) {
+                        res.push(GeoField { key: field.to_string(), point });
                     }
                 }
             }
         }
-        None
+        res
     }
 }
 
-impl DocumentSearch for QueryBased {
-    fn content(&self) -> Option<String> {
-        if self.content.is_some() {
+impl Vectors for QueryBased {
+    fn vectors(&self) -> Vec<VectorField> {
+        let mut res = vec![];
+        if let Some(vectors) = &self.vectors {
             let obj = self.object();
-            let content_fields = self.content.clone().unwrap();
-            let mut content = String::from("");
-            for content_field in content_fields {
-                if obj.contains_key(content_field.as_str()) {
-                    let res = obj.get(content_field.as_str());
-                    if res.is_some() {
-                        let t = res.unwrap().as_str().unwrap().to_string();
-                        content.push_str(t.as_str());
+            for field in vectors {
+                if let Some(Value::Array(arr)) = obj.get(field) {
+                    let vector = arr.iter().filter_map(|v| v.as_f64()).map(|v| v as f32).collect::<Vec<f32>>();
+                    if vector.len() == arr.len() {
+                        res.push(VectorField { key: field.to_string(), vector });
                     }
                 }
             }
-            Some(content)
-        } else {
-            None
         }
+        res
+    }
+}
+
+impl QueryBased {
+    /// a `Field` for every scalar value of the given props. arrays give one per element
+    fn values(&self, props: &[String]) -> Vec<Field> {
+        let obj = self.object();
+        let mut res = vec![];
+        for prop in props {
+            if let Some(v) = obj.get(prop) {
+                for value in scalars(v) {
+                    res.push(Field { key: prop.to_string(), value });
+                }
+            }
+        }
+        res
+    }
+}
+
+/// `{lat, lon}` objects with valid coordinates. arrays are flattened
+fn points(v: &Value) -> Vec<Point> {
+    match v {
+        Value::Object(obj) => match (obj.get("lat").and_then(|v| v.as_f64()), obj.get("lon").and_then(|v| v.as_f64())) {
+            (Some(lat), Some(lon)) if (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon) => {
+                vec![Point { lat, lon }]
+            }
+            _ => vec![],
+        },
+        Value::Array(arr) => arr.iter().flat_map(points).collect(),
+        _ => vec![],
+    }
+}
+
+/// strings, numbers and booleans as text. arrays are flattened, objects and nulls skipped
+fn scalars(v: &Value) -> Vec<String> {
+    match v {
+        Value::String(s) => vec![s.to_string()],
+        Value::Number(n) => vec![n.to_string()],
+        Value::Bool(b) => vec![b.to_string()],
+        Value::Array(arr) => arr.iter().flat_map(scalars).collect(),
+        Value::Null | Value::Object(_) => vec![],
     }
 }
 
@@ -152,6 +174,10 @@ impl Document for QueryBased {
             fields: None,
             view_cfg: vec![],
             content: None,
+            grams: None,
+            geo: None,
+            vectors: None,
+            size: 0,
         })
     }
 
@@ -166,6 +192,10 @@ impl Document for QueryBased {
             fields: None,
             view_cfg: vec![],
             content: None,
+            grams: None,
+            geo: None,
+            vectors: None,
+            size: 0,
         })
     }
 
@@ -174,7 +204,7 @@ impl Document for QueryBased {
         let k = self.keys().len() > 0;
         let t = self.tokens().len() > 0;
         let f = self.fields().len() > 0;
-        let fl = self.binding().is_some();
+        let fl = !self.bindings().is_empty();
         let c = self.content().is_some();
 
         self.keys = if !k { Some(opts.index_opts.clone()) } else { None };
@@ -182,6 +212,10 @@ impl Document for QueryBased {
         self.view_cfg = if !f { opts.view_opts.clone() } else { vec![] };
         self.fields = if !fl { Some(opts.range_opts.clone()) } else { None };
         self.content = if !c { Some(opts.search_opts.clone()) } else { None };
+        self.grams = Some(opts.ngram_opts.clone());
+        self.geo = Some(opts.geo_opts.clone());
+        self.vectors = Some(opts.vector_opts.iter().map(|v| v.field.to_string()).collect());
+        self.size = serde_json::to_vec(&self.data).map_or(0, |v| v.len());
     }
 
     fn object(&self) -> &Map<String, Value> {
@@ -198,6 +232,10 @@ impl Document for QueryBased {
         Value::Object(obj)
     }
 
+    fn size(&self) -> usize {
+        self.size
+    }
+
     fn string(&self) -> String {
         serde_json::to_string(self.document()).unwrap()
     }
@@ -15,12 +15,57 @@ pub struct Field {
     pub value: String,
 }
 
-/// `ViewConfig` is used for `View` filter
+/// `Point` is a `{lat, lon}` location in degrees
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Point {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+impl Point {
+    /// great-circle distance in meters
+    pub fn distance(&self, other: &Point) -> f64 {
+        const EARTH_RADIUS: f64 = 6_371_008.8;
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let dlat = lat2 - lat1;
+        let dlon = (other.lon - self.lon).to_radians();
+        let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS * a.sqrt().min(1.0).asin()
+    }
+}
+
+/// `GeoField` is a point of a geo field
 #[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct GeoField {
+    pub key: String,
+    pub point: Point,
+}
+
+/// `VectorField` is the embedding of a vector field
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct VectorField {
+    pub key: String,
+    pub vector: Vec<f32>,
+}
+
+/// `ViewConfig` is used for `View` filter. a document is in the view when `prop` holds `expected`,
+/// or when it matches `condition` if there is one
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
 pub struct ViewConfig {
+    #[serde(default)]
     pub prop: String,
+    #[serde(default)]
     pub expected: String,
     pub view_name: String,
+    /// a `get.when` condition, used instead of `prop` and `expected`
+    #[serde(default)]
+    pub condition: Option<String>,
+    /// sort keys of the view, like `'age DESC','name'`
+    #[serde(default)]
+    pub sort: Option<String>,
+    /// fields the view returns, `path` or `path AS alias`. every field when empty
+    #[serde(default)]
+    pub select: Vec<String>,
 }
 
 /// `Index` is similar to RDMS unique key
@@ -28,9 +73,10 @@ pub trait Index {
     fn keys(&self) -> Vec<String>;
 }
 
-/// `Clips` help to group documents
+/// `Clips` help to group documents.
+/// `tokens` returns the value of every clip field as a `Field`
 pub trait Clips {
-    fn tokens(&self) -> Vec<String>;
+    fn tokens(&self) -> Vec<Field>;
 }
 
 /// `Range` is for filtering a range of values
@@ -40,23 +86,45 @@ pub trait Range {
 
 /// `View` is similar to `Tag`
 /// For example: Get all documents that matches
-/// prop `age` expected `18` and `view_name` is ADULT
+/// prop `age` expected `18` and `view_name` is ADULT.
+/// `bindings` returns every view the document is in by `prop` and `expected`
 pub trait View {
-    fn binding(&self) -> Option<String>;
+    fn bindings(&self) -> Vec<String>;
 }
 
-/// `DocumentSearch` is used for search engine
+/// `DocumentSearch` is used for search engine.
+/// `content` returns the text of every search field as a `Field`
 pub trait DocumentSearch {
-    fn content(&self) -> Option<String>;
+    fn content(&self) -> Option<Vec<Field>>;
+}
+
+/// `Grams` is used for the n-gram index.
+/// `grams` returns the value of every n-gram field as a `Field`
+pub trait Grams {
+    fn grams(&self) -> Vec<Field>;
+}
+
+/// `Geo` is used for the geo index.
+/// `points` returns every `{lat, lon}` value of the geo fields
+pub trait Geo {
+    fn points(&self) -> Vec<GeoField>;
+}
+
+/// `Vectors` is used for the vector index.
+/// `vectors` returns every array of numbers of the vector fields
+pub trait Vectors {
+    fn vectors(&self) -> Vec<VectorField>;
 }
 
-pub trait Document: Index + Clips + Range + View + DocumentSearch {
+pub trait Document: Index + Clips + Range + View + DocumentSearch + Grams + Geo + Vectors {
     fn from_str(input: &str) -> Result<Self, DocumentError> where Self: Sized;
     fn from_value(input: &Value) -> Result<Self, DocumentError> where Self: Sized;
     fn set_opts(&mut self, opts: &CollectionOptions);
     fn object(&self) -> &Map<String, Value>;
     fn document(&self) -> &Value;
     fn make(&self, key: String) -> Value;
+    /// bytes of json the document takes, as it was put
+    fn size(&self) -> usize;
     fn string(&self) -> String;
 }
 
@@ -7,6 +7,8 @@ pub enum IndexError {
     DuplicateDocument,
     #[error("no such index")]
     NoSuchIndex,
+    #[error("vector `{0}` must have `{1}` dimensions")]
+    DimensionMismatch(String, usize),
 }
 
 #[derive(Serialize, Deserialize, Error, Clone, Debug, PartialEq)]
@@ -35,6 +37,8 @@ pub enum CollectionError {
     NoSuchCollection,
     #[error("collection options are not valid")]
     OptionsProvidedAreNotValid,
+    #[error("no such view")]
+    NoSuchView,
 }
 
 #[derive(Serialize, Deserialize, Error, Clone, Debug, PartialEq)]
@@ -63,6 +67,12 @@ pub enum QueryError {
     UnknownOperatorCompare,
     #[error("no result found")]
     NoResult,
+    #[error("statement timed out")]
+    Timeout,
+    #[error("statement cancelled")]
+    Cancelled,
+    #[error("result limit exceeded. {0}")]
+    LimitExceeded(String),
 }
 
 #[derive(Serialize, Deserialize, Error, Clone, Debug, PartialEq)]
@@ -0,0 +1,389 @@
+/// a parsed `flql` condition. parsing follows `flql::expr_parse` token for token,
+/// `&&` and `||` take the rest of the expression as their right side
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Path(String),
+    Str(String),
+    Num(f64),
+    Bool(bool),
+    Null,
+    Arr(Vec<Expr>),
+    /// value with its `COERCE` identifiers, in order
+    Coerce(Box<Expr>, Vec<String>),
+    Not(Box<Expr>),
+    Op(Op, Box<Expr>, Box<Expr>),
+    /// value, lower and upper bound
+    Between(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// one of `FUNCTIONS` with its arguments
+    Call(String, Vec<Expr>),
+}
+
+/// functions a condition may call, e.g. `exists(.email)`. `flql` has none of them
+pub const FUNCTIONS: [&str; 7] = ["exists", "in", "matches", "lower", "upper", "date", "now"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Eq,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    Or,
+    And,
+    StartsWith,
+    EndsWith,
+    In,
+    Contains,
+    ContainsAny,
+    ContainsAll,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Op(Op),
+    Between,
+    Not,
+    Coerce,
+    Path,
+    Str,
+    Num,
+    True,
+    False,
+    Null,
+    Ident,
+    Call,
+    OpenParen,
+    CloseParen,
+    OpenBracket,
+    CloseBracket,
+    Comma,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Token {
+    kind: Kind,
+    start: usize,
+    len: usize,
+}
+
+/// parses a condition, e.g. `.age > 20 && .name == "x"`
+pub fn parse(src: &str) -> Result<Expr, String> {
+    let mut parser = Parser { src: src.as_bytes(), pos: 0, peeked: None };
+    parser.expression()?.ok_or_else(|| "no expression results found".to_string())
+}
+
+struct Parser<'a> {
+    src: &'a [u8],
+    pos: usize,
+    peeked: Option<Result<Token, String>>,
+}
+
+impl<'a> Parser<'a> {
+    fn expression(&mut self) -> Result<Option<Expr>, String> {
+        let mut current: Option<Expr> = None;
+        while let Some(token) = self.next() {
+            let token = token?;
+            current = match current {
+                Some(expr) if token.kind == Kind::CloseParen => return Ok(Some(expr)),
+                Some(expr) => Some(self.operation(token, expr)?),
+                None => Some(self.value(token)?),
+            };
+        }
+        Ok(current)
+    }
+
+    fn value(&mut self, token: Token) -> Result<Expr, String> {
+        match token.kind {
+            Kind::OpenBracket => {
+                let mut arr = vec![];
+                loop {
+                    let token = self.next().ok_or_else(|| "unclosed Array '['".to_string())??;
+                    match token.kind {
+                        Kind::CloseBracket => break,
+                        Kind::Comma => continue,
+                        _ => arr.push(self.value(token)?),
+                    }
+                }
+                Ok(Expr::Arr(arr))
+            }
+            Kind::OpenParen => self.expression()?
+                .ok_or_else(|| "expression after open parenthesis '(' ends unexpectedly.".to_string()),
+            Kind::Path => Ok(Expr::Path(self.text(token.start + 1, token.start + token.len))),
+            Kind::Str => Ok(Expr::Str(unescape(&self.text(token.start + 1, token.start + token.len - 1), self.src[token.start] as char))),
+            Kind::Num => self.text(token.start, token.start + token.len)
+                .parse::<f64>()
+                .map(Expr::Num)
+                .map_err(|e| e.to_string()),
+            Kind::True => Ok(Expr::Bool(true)),
+            Kind::False => Ok(Expr::Bool(false)),
+            Kind::Null => Ok(Expr::Null),
+            Kind::Coerce => {
+                let next = self.after(token)?;
+                let value = self.value(next)?;
+                let mut idents = vec![];
+                loop {
+                    let token = self.next().ok_or_else(|| "no identifier after value for: COERCE".to_string())??;
+                    let ident = self.text(token.start, token.start + token.len);
+                    if token.kind != Kind::Ident {
+                        return Err(format!("COERCE missing data type identifier, found instead: {}", ident));
+                    }
+                    match ident.as_str() {
+                        "_datetime_" | "_string_" | "_number_" | "_lowercase_" | "_uppercase_" => idents.push(ident),
+                        _ => return Err(format!("invalid COERCE data type '{}'", ident)),
+                    }
+                    if let Some(Ok(token)) = self.peek() {
+                        if token.kind == Kind::Comma {
+                            self.next();
+                            continue;
+                        }
+                    }
+                    break;
+                }
+                Ok(Expr::Coerce(Box::new(value), idents))
+            }
+            Kind::Not => {
+                let next = self.after(token)?;
+                Ok(Expr::Not(Box::new(self.value(next)?)))
+            }
+            Kind::Call => {
+                let name = self.text(token.start, token.start + token.len - 1);
+                let mut args = vec![];
+                while let Some(arg) = self.argument(&name)? {
+                    args.push(arg);
+                }
+                Ok(Expr::Call(name, args))
+            }
+            _ => Err(format!("token is not a valid value: {:?}", token)),
+        }
+    }
+
+    fn operation(&mut self, token: Token, current: Expr) -> Result<Expr, String> {
+        match token.kind {
+            Kind::Op(op @ (Op::Or | Op::And)) => {
+                let right = self.expression()?.ok_or_else(|| format!("invalid operation after {:?}", op))?;
+                Ok(Expr::Op(op, Box::new(current), Box::new(right)))
+            }
+            Kind::Op(op) => {
+                let next = self.after(token)?;
+                let right = self.value(next)?;
+                Ok(Expr::Op(op, Box::new(current), Box::new(right)))
+            }
+            Kind::Between => {
+                let next = self.after(token)?;
+                let lower = self.value(next)?;
+                let next = self.after(token)?;
+                let upper = self.value(next)?;
+                Ok(Expr::Between(Box::new(current), Box::new(lower), Box::new(upper)))
+            }
+            Kind::Not => {
+                let next = self.after(token)?;
+                Ok(Expr::Not(Box::new(self.operation(next, current)?)))
+            }
+            Kind::CloseBracket => Ok(current),
+            _ => Err(format!("invalid operation: {:?}", token)),
+        }
+    }
+
+    /// next argument of a call, up to a `,` or the closing `)`. `None` once the call is closed
+    fn argument(&mut self, name: &str) -> Result<Option<Expr>, String> {
+        let mut current: Option<Expr> = None;
+        loop {
+            let token = self.next().ok_or_else(|| format!("unclosed call of {}", name))??;
+            current = match (current, token.kind) {
+                (None, Kind::CloseParen) => return Ok(None),
+                (Some(expr), Kind::CloseParen) => {
+                    // the call ends after its last argument
+                    self.peeked = Some(Ok(token));
+                    return Ok(Some(expr));
+                }
+                (Some(expr), Kind::Comma) => return Ok(Some(expr)),
+                (Some(expr), _) => Some(self.operation(token, expr)?),
+                (None, _) => Some(self.value(token)?),
+            };
+        }
+    }
+
+    /// the token following an operation
+    fn after(&mut self, token: Token) -> Result<Token, String> {
+        match self.next() {
+            Some(token) => token,
+            None => Err(format!("no value found after operation: {}", self.text(token.start, token.start + token.len))),
+        }
+    }
+
+    fn text(&self, from: usize, to: usize) -> String {
+        String::from_utf8_lossy(&self.src[from..to]).into_owned()
+    }
+
+    fn peek(&mut self) -> Option<&Result<Token, String>> {
+        if self.peeked.is_none() {
+            self.peeked = self.lex();
+        }
+        self.peeked.as_ref()
+    }
+
+    fn next(&mut self) -> Option<Result<Token, String>> {
+        match self.peeked.take() {
+            Some(token) => Some(token),
+            None => self.lex(),
+        }
+    }
+
+    fn lex(&mut self) -> Option<Result<Token, String>> {
+        self.pos += take_while(&self.src[self.pos..], |c| c.is_ascii_whitespace());
+        if self.pos >= self.src.len() {
+            return None;
+        }
+        let start = self.pos;
+        let res = token(&self.src[start..]).map(|(kind, len)| {
+            self.pos += len;
+            Token { kind, start, len }
+        });
+        if res.is_err() {
+            // a lexer error ends the expression
+            self.pos = self.src.len();
+        }
+        Some(res)
+    }
+}
+
+fn take_while(data: &[u8], mut pred: impl FnMut(u8) -> bool) -> usize {
+    data.iter().take_while(|c| pred(**c)).count()
+}
+
+fn invalid(what: &str, data: &[u8]) -> String {
+    format!("invalid {}: {}", what, String::from_utf8_lossy(data))
+}
+
+fn token(data: &[u8]) -> Result<(Kind, usize), String> {
+    let next = data.get(1).copied();
+    let res = match data[0] {
+        b'=' if next == Some(b'=') => (Kind::Op(Op::Eq), 2),
+        b'=' => (Kind::Op(Op::Eq), 1),
+        b'+' | b'-' if next.is_some_and(|c| c.is_ascii_digit()) => number(data)?,
+        b'+' => (Kind::Op(Op::Add), 1),
+        b'-' => (Kind::Op(Op::Sub), 1),
+        b'*' => (Kind::Op(Op::Mul), 1),
+        b'/' => (Kind::Op(Op::Div), 1),
+        b'>' if next == Some(b'=') => (Kind::Op(Op::Gte), 2),
+        b'>' => (Kind::Op(Op::Gt), 1),
+        b'<' if next == Some(b'=') => (Kind::Op(Op::Lte), 2),
+        b'<' => (Kind::Op(Op::Lt), 1),
+        b'(' => (Kind::OpenParen, 1),
+        b')' => (Kind::CloseParen, 1),
+        b'[' => (Kind::OpenBracket, 1),
+        b']' => (Kind::CloseBracket, 1),
+        b',' => (Kind::Comma, 1),
+        b'!' => (Kind::Not, 1),
+        b'&' if next == Some(b'&') => (Kind::Op(Op::And), 2),
+        b'|' if next == Some(b'|') => (Kind::Op(Op::Or), 2),
+        b'"' | b'\'' => string(data)?,
+        b'.' => match take_while(&data[1..], |c| !c.is_ascii_whitespace() && c != b')' && c != b']' && c != b',') {
+            0 => return Err(invalid("identifier", data)),
+            len => (Kind::Path, len + 1),
+        },
+        b'a'..=b'z' => match (call(data), &data[..take_while(data, |c| c.is_ascii_alphabetic())]) {
+            (Some(len), _) => (Kind::Call, len),
+            (None, b"true") => (Kind::True, 4),
+            (None, b"false") => (Kind::False, 5),
+            (None, _) if matches!(data[0], b't' | b'f') => return Err(invalid("boolean", data)),
+            _ => return Err(format!("Unsupported Character `{}`", data[0])),
+        },
+        b'N' => match take_while(data, |c| c.is_ascii_alphabetic()) {
+            4 if data.starts_with(b"NULL") => (Kind::Null, 4),
+            _ => return Err(invalid("keyword", data)),
+        },
+        b'_' => match take_while(data, |c| !c.is_ascii_whitespace() && c != b')' && c != b']' && c != b',') {
+            len if data[len - 1] == b'_' => (Kind::Ident, len),
+            _ => return Err(invalid("identifier", data)),
+        },
+        b'0'..=b'9' => number(data)?,
+        b'O' | b'C' | b'I' | b'S' | b'E' | b'B' => keyword(data)?,
+        c => return Err(format!("Unsupported Character `{}`", c)),
+    };
+    Ok(res)
+}
+
+/// length of a function name with its `(`
+fn call(data: &[u8]) -> Option<usize> {
+    let len = take_while(data, |c| c.is_ascii_lowercase());
+    let name = std::str::from_utf8(&data[..len]).ok()?;
+    (data.get(len) == Some(&b'(') && FUNCTIONS.contains(&name)).then_some(len + 1)
+}
+
+fn keyword(data: &[u8]) -> Result<(Kind, usize), String> {
+    let len = take_while(data, |c| !c.is_ascii_whitespace());
+    let kind = match &data[..len] {
+        b"OR" => Kind::Op(Op::Or),
+        b"CONTAINS" => Kind::Op(Op::Contains),
+        b"CONTAINS_ANY" => Kind::Op(Op::ContainsAny),
+        b"CONTAINS_ALL" => Kind::Op(Op::ContainsAll),
+        b"COERCE" => Kind::Coerce,
+        b"IN" => Kind::Op(Op::In),
+        b"STARTS_WITH" => Kind::Op(Op::StartsWith),
+        b"ENDS_WITH" => Kind::Op(Op::EndsWith),
+        b"BETWEEN" => Kind::Between,
+        _ => return Err(invalid("keyword", data)),
+    };
+    // a keyword is never the last token
+    if data.len() == len {
+        return Err(invalid("keyword", data));
+    }
+    Ok((kind, len))
+}
+
+fn number(data: &[u8]) -> Result<(Kind, usize), String> {
+    let mut dot = false;
+    let mut bad = false;
+    let len = take_while(data, |c| match c {
+        b'.' if dot => {
+            bad = true;
+            false
+        }
+        b'.' => {
+            dot = true;
+            true
+        }
+        b'-' | b'+' | b'e' => true,
+        _ => c.is_ascii_digit(),
+    });
+    if bad {
+        return Err(invalid("number", data));
+    }
+    Ok((Kind::Num, len))
+}
+
+/// quoted text. a backslash escapes the quote and a backslash
+fn string(data: &[u8]) -> Result<(Kind, usize), String> {
+    let quote = data[0];
+    let mut escaped = false;
+    for (i, c) in data.iter().enumerate().skip(1) {
+        match *c {
+            b'\\' => escaped = !escaped,
+            c if c == quote && !escaped => return Ok((Kind::Str, i + 1)),
+            _ => escaped = false,
+        }
+    }
+    Err(format!("Unterminated string `{}`", String::from_utf8_lossy(data)))
+}
+
+/// text of a quoted string. escaped quotes and backslashes lose their backslash,
+/// other escapes are kept as written, like the `\d` of a pattern
+fn unescape(text: &str, quote: char) -> String {
+    let mut res = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match chars.peek() {
+            Some(&next) if c == '\\' && (next == quote || next == '\\') => {
+                res.push(next);
+                chars.next();
+            }
+            _ => res.push(c),
+        }
+    }
+    res
+}
@@ -7,7 +7,7 @@ use serde_json::{Map, Value};
 use crate::database::Database;
 use crate::doc::QueryBased;
 use crate::doc_trait::Document;
-use crate::extension::FlinchDbHelper;
+use crate::extension::{FlinchDbHelper, FuncResultExtractor};
 
 #[async_trait]
 impl FlinchDbHelper for Arc<Database<QueryBased>> {
@@ -113,18 +113,12 @@ impl FlinchDbHelper for Arc<Database<QueryBased>> {
 
 	async fn update_by_index<'a, T>(&self, col: &'a str, index: &'a str, value: T) -> bool where T: Serialize + Deserialize<'a> + Sync + Send + ToString {
 		if let Ok(db) = self.using(col) {
-			return match self.find_id_by_index(col,index) {
-				None => false,
-				Some(id) => {
-					let key = format!("{}",id);
-					let document = QueryBased::from_str(value.to_string().as_str()).unwrap();
-					match db.put(key.to_owned(), document).await {
-						Ok(_) => true,
-						Err(err) => {
-							error!("{}",err);
-							false
-						}
-					}
+			let document = QueryBased::from_str(value.to_string().as_str()).unwrap();
+			return match db.put_index(index, document, db.opts().conflict_opts).await {
+				Ok(res) => res.is_some(),
+				Err(err) => {
+					error!("{}",err);
+					false
 				}
 			};
 		}
@@ -0,0 +1,148 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::errors::QueryError;
+
+tokio::task_local! {
+    /// guard of the statement running on the task
+    static GUARD: Guard;
+}
+
+/// `Limits` of every statement of a `Query`. `None` does not limit
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct Limits {
+    /// time a statement may run
+    pub timeout: Option<Duration>,
+    /// documents a statement may return
+    pub max_rows: Option<usize>,
+    /// bytes of json the documents of a statement may take
+    pub max_bytes: Option<usize>,
+}
+
+/// `Cancel` stops the statements it is given to. clones cancel the same statements
+#[derive(Clone, Debug, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// `Guard` of a running statement. scans, sorts and bulk deletes check it and stop
+/// once it is cancelled or past its deadline. documents deleted before are not restored
+#[derive(Clone, Debug, Default)]
+pub struct Guard {
+    deadline: Option<Instant>,
+    cancel: Cancel,
+    limits: Limits,
+    /// why the statement stopped, shared by the clones checked on other threads
+    stopped: Arc<OnceLock<QueryError>>,
+    /// whether the statement held its documents to the limits itself
+    measured: Arc<AtomicBool>,
+}
+
+impl Guard {
+    /// a guard of `limits` starting now
+    pub fn new(limits: Limits, cancel: Cancel) -> Self {
+        Self {
+            deadline: limits.timeout.map(|timeout| Instant::now() + timeout),
+            cancel,
+            limits,
+            stopped: Arc::new(OnceLock::new()),
+            measured: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// whether the statement may go on. once it may not, it never may again
+    pub fn check(&self) -> Result<(), QueryError> {
+        if let Some(err) = self.stopped.get() {
+            return Err(err.clone());
+        }
+        if self.cancel.is_cancelled() {
+            return Err(self.stop(QueryError::Cancelled));
+        }
+        if self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(self.stop(QueryError::Timeout));
+        }
+        Ok(())
+    }
+
+    /// stops the statement, the first reason is kept
+    pub(crate) fn stop(&self, err: QueryError) -> QueryError {
+        self.stopped.get_or_init(|| err).clone()
+    }
+
+    /// why the statement was stopped while it ran
+    pub fn stopped(&self) -> Option<QueryError> {
+        self.stopped.get().cloned()
+    }
+
+    /// whether the documents were held to the limits by `fits`
+    pub fn measured(&self) -> bool {
+        self.measured.load(Ordering::Relaxed)
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// whether `rows` documents may be returned
+    pub fn rows(&self, rows: usize) -> Result<(), QueryError> {
+        match self.limits.max_rows {
+            Some(max) if rows > max => Err(QueryError::LimitExceeded(format!("{} rows of at most {}", rows, max))),
+            _ => Ok(()),
+        }
+    }
+
+    /// whether the documents may be returned. bytes are counted up to the limit only
+    pub fn size(&self, data: &[Value]) -> Result<(), QueryError> {
+        self.fits(data.len(), data.iter().map(|value| serde_json::to_vec(value).map_or(0, |v| v.len())))
+    }
+
+    /// whether `rows` documents of the given sizes in bytes may be returned. sizes are read up to the limit only.
+    /// the documents returned are not measured again
+    pub fn fits<I: IntoIterator<Item = usize>>(&self, rows: usize, sizes: I) -> Result<(), QueryError> {
+        self.measured.store(true, Ordering::Relaxed);
+        self.rows(rows)?;
+        let Some(max) = self.limits.max_bytes else {
+            return Ok(());
+        };
+        let mut bytes = 0;
+        for size in sizes {
+            bytes += size;
+            if bytes > max {
+                return Err(QueryError::LimitExceeded(format!("more than {} bytes", max)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// the guard of the statement running on the task, one that never stops outside of a statement
+pub(crate) fn current() -> Guard {
+    GUARD.try_with(Guard::clone).unwrap_or_default()
+}
+
+/// whether a statement is already guarded on the task
+pub(crate) fn guarded() -> bool {
+    GUARD.try_with(|_| ()).is_ok()
+}
+
+/// runs a statement with its guard
+pub(crate) async fn scope<F: Future>(guard: Guard, f: F) -> F::Output {
+    GUARD.scope(guard, f).await
+}
@@ -1,7 +1,11 @@
+use std::collections::HashSet;
+use std::hash::Hash;
+
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use tokio::sync::mpsc::Sender;
 
+use crate::database::Conflict;
 use crate::errors::{CollectionError, DbError, DocumentError, IndexError, QueryError};
 
 pub type DbName = String;
@@ -57,14 +61,78 @@ pub enum PubSubRes {
     Err(String),
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub enum SortDirection {
     Asc,
     Desc,
 }
 
+/// where documents missing the sort field go
+#[derive(Clone, Debug, PartialEq)]
+pub enum Nulls {
+    First,
+    Last,
+}
+
+/// `Sort` is a key of a sort clause. `field` is a dotted path.
+/// nulls come first when sorting up and last when sorting down, unless `nulls` is set
+#[derive(Clone, Debug, PartialEq)]
 pub struct Sort {
     pub field: String,
     pub direction: SortDirection,
+    pub nulls: Option<Nulls>,
+}
+
+/// `HighlightOptions` is used to build search snippets
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HighlightOptions {
+    pub pre_tag: String,
+    pub post_tag: String,
+    /// chars of a fragment. `0` keeps the whole field
+    pub fragment_size: usize,
+    /// fragments kept per field
+    pub max_fragments: usize,
+}
+
+impl Default for HighlightOptions {
+    fn default() -> Self {
+        Self {
+            pre_tag: "<em>".to_string(),
+            post_tag: "</em>".to_string(),
+            fragment_size: 100,
+            max_fragments: 3,
+        }
+    }
+}
+
+/// `Highlight` keeps the matches of a search field.
+/// `offsets` are the char ranges of every match in the field value
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Highlight {
+    pub field: String,
+    pub fragments: Vec<String>,
+    pub offsets: Vec<(usize, usize)>,
+}
+
+/// `SearchHit` is a search result with its highlighted fields
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SearchHit<K, D> {
+    pub key: K,
+    pub document: D,
+    pub highlights: Vec<Highlight>,
+}
+
+/// `Facet` counts the hits of every value of a clip field
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Facet {
+    pub field: String,
+    pub values: Vec<FacetValue>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FacetValue {
+    pub value: String,
+    pub count: usize,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -73,12 +141,33 @@ pub enum FuncType {
     LookupMulti,
     LookupIndex(String),
     LikeSearch(String),
+    Contains(String),
+    Near(String),
+    WithinBox(String),
+    Knn(String),
     FetchView(String),
     FetchClip(String),
+    FetchClips,
+    Facets(Vec<String>),
     FetchRange(String),
+    Aggregate,
+    Join(String),
+    Count,
+    Exists,
+    Distinct(String),
+    RebuildViews,
     Query(String),
 }
 
+/// documents a condition is evaluated on
+#[derive(Clone, Debug, PartialEq)]
+pub enum Plan<K: Eq + Hash> {
+    /// every document of the collection
+    Scan,
+    /// only the index candidates
+    Candidates(HashSet<K>),
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct FuncResult<T> {
     pub query: FuncType,
@@ -86,7 +175,7 @@ pub struct FuncResult<T> {
     pub time_taken: String,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
 pub enum FlinchError {
     ExpressionError(String),
     QueryError(QueryError),
@@ -95,14 +184,48 @@ pub enum FlinchError {
     CustomError(String),
     IndexError(IndexError),
     SchemaError(DbError),
+    #[default]
     None,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct QueryResult {
     pub data: Vec<Value>,
     pub error: FlinchError,
     pub time_taken: String,
+    /// number of matching documents before paging
+    #[serde(default)]
+    pub total: Option<usize>,
+    /// continues a paged result after its last document. `None` on the last page
+    #[serde(default)]
+    pub cursor: Option<String>,
+    /// collisions on unique indexes resolved by a put
+    #[serde(default)]
+    pub conflicts: Vec<Conflicted>,
+}
+
+/// `Conflicted` tells how a put resolved a collision on a unique index.
+/// `pointers` are the documents holding the same index values, the first one the merged document on `Merge`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Conflicted {
+    pub resolution: Conflict,
+    pub pointers: Vec<String>,
+}
+
+/// `ClipSet` is the set of documents of a clip or of a view
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ClipSet {
+    Clip(String),
+    View(String),
+}
+
+/// `ClipSets` combines clips and views. documents are in every set of `all`, in a set of `any`
+/// and in no set of `none`. `all` and `any` left empty do not narrow the documents down
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ClipSets {
+    pub all: Vec<ClipSet>,
+    pub any: Vec<ClipSet>,
+    pub none: Vec<ClipSet>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Debug, Display};
 use std::hash::Hash;
 use std::sync::{Arc, Mutex};
@@ -10,10 +10,18 @@ use serde::de::DeserializeOwned;
 use serde::Serialize;
 use tokio::task::JoinHandle;
 
-use crate::utils::tokenize;
+use crate::analyzer::{Analyzer, Analyzers};
+use crate::database::FieldBoost;
+use crate::doc_trait::Field;
+use crate::headers::{Highlight, HighlightOptions};
+
+/// documents of a term, with the term frequency in each source field
+type Postings<K> = DashMap<K, HashMap<String, usize>>;
 
 pub struct InvertedIndex<K> {
-    pub kv: Arc<DashMap<String, DashSet<K>>>,
+    pub kv: Arc<DashMap<String, Postings<K>>>,
+    analyzers: Arc<Analyzers>,
+    boosts: HashMap<String, f32>,
 }
 
 impl<K> InvertedIndex<K>
@@ -31,91 +39,202 @@ impl<K> InvertedIndex<K>
     Display +
     'static
 {
-    pub fn new() -> Self {
+    pub fn new(analyzers: Analyzers, boost_opts: &[FieldBoost]) -> Self {
         Self {
-            kv: Arc::new(DashMap::new())
+            kv: Arc::new(DashMap::new()),
+            analyzers: Arc::new(analyzers),
+            boosts: boost_opts.iter().map(|b| (b.field.to_string(), b.boost)).collect(),
         }
     }
 
-    pub fn put(&self, k: K, v: String) -> JoinHandle<()> {
+    pub fn put(&self, k: K, content: Vec<Field>) -> JoinHandle<()> {
         let rf = self.kv.clone();
+        let analyzers = self.analyzers.clone();
         tokio::spawn(async move {
-            let separated = tokenize(&v);
-            for w in separated {
-                let token = w.to_lowercase();
-                let key = &k;
-                match rf.get_mut(&token) {
-                    None => {
-                        let set = DashSet::new();
-                        set.insert(key.to_owned());
-                        rf.insert(token, set);
-                    }
-                    Some(set) => {
-                        if set.value().get(&key).is_none() {
-                            set.value().insert(key.to_owned());
-                        }
-                    }
-                }
+            for field in content {
+                let analyzer = analyzers.get(field.key.as_str());
+                Self::insert_terms(&rf, &k, field.key.as_str(), analyzer.analyze(field.value.as_str()));
             }
         })
     }
 
-    pub fn delete(&self, k: K, v: String) -> JoinHandle<()> {
+    pub fn delete(&self, k: K, content: Vec<Field>) -> JoinHandle<()> {
         trace!("deleting inverted index for key - {}",&k);
         let rf = self.kv.clone();
+        let analyzers = self.analyzers.clone();
         tokio::spawn(async move {
-            for w in v.split_whitespace() {
-                let token = w.to_lowercase();
-                if let Some(set) = rf.get_mut(&token) {
-                    set.value().remove(&k);
-                }
+            for field in content {
+                let analyzer = analyzers.get(field.key.as_str());
+                Self::remove_terms(&rf, &k, field.key.as_str(), analyzer.analyze(field.value.as_str()));
             }
         })
     }
 
-    pub fn find(&self, words: Vec<&str>) -> Vec<K> {
-        let mut res = HashSet::new();
-        for w in words {
-            let token = w.to_lowercase();
-            let dk = match self.kv.get(&token) {
-                None => {
-                    vec![]
-                }
-                Some(set) => {
-                    set.value().iter().map(|r| r.key().to_owned()).collect()
+    /// swaps the analyzer of a field and re-indexes the given documents with it
+    pub fn set_analyzer(&self, field: &str, analyzer: Arc<dyn Analyzer>, docs: Vec<(K, Vec<Field>)>) {
+        let previous = self.analyzers.set(field, Arc::clone(&analyzer));
+        for (k, content) in docs {
+            for f in content.iter().filter(|f| f.key.eq(field)) {
+                Self::remove_terms(&self.kv, &k, field, previous.analyze(f.value.as_str()));
+                Self::insert_terms(&self.kv, &k, field, analyzer.analyze(f.value.as_str()));
+            }
+        }
+    }
+
+    /// keys matching any term of the query in any search field, best match first
+    pub fn find(&self, query: &str) -> Vec<K> {
+        self.find_in(query, &self.analyzers.fields())
+    }
+
+    /// keys matching any term of the query in the given fields, best match first.
+    /// a match scores the term frequency times the boost of the field
+    pub fn find_in(&self, query: &str, fields: &[String]) -> Vec<K> {
+        let mut scores: HashMap<K, f32> = HashMap::new();
+        for field in fields {
+            let boost = self.boost(field.as_str());
+            let mut terms = self.analyzers.get(field.as_str()).analyze(query);
+            terms.sort();
+            terms.dedup();
+            for term in terms {
+                if let Some(postings) = self.kv.get(&term) {
+                    for posting in postings.value().iter() {
+                        if let Some(tf) = posting.value().get(field) {
+                            *scores.entry(posting.key().to_owned()).or_insert(0.0) += boost * *tf as f32;
+                        }
+                    }
                 }
+            }
+        }
+        let mut ranked = scores.into_iter().collect::<Vec<(K, f32)>>();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        ranked.into_iter().map(|(k, _)| k).collect()
+    }
+
+    /// keys with every term of `text` in `field`. `None` when the text has no terms
+    pub fn matching(&self, field: &str, text: &str) -> Option<HashSet<K>> {
+        let mut res: Option<HashSet<K>> = None;
+        for term in self.analyzers.get(field).analyze(text) {
+            let keys = match self.kv.get(&term) {
+                Some(postings) => postings
+                    .value()
+                    .iter()
+                    .filter(|posting| posting.value().contains_key(field))
+                    .map(|posting| posting.key().clone())
+                    .collect::<HashSet<K>>(),
+                None => HashSet::new(),
             };
-            for k in dk {
-                if res.get(&k).is_none() {
-                    res.insert(k);
+            res = Some(match res {
+                Some(res) => res.intersection(&keys).cloned().collect(),
+                None => keys,
+            });
+        }
+        res
+    }
+
+    /// highlights the query matches in the given fields of a document content
+    pub fn highlight(&self, query: &str, content: Vec<Field>, fields: &[String], opts: &HighlightOptions) -> Vec<Highlight> {
+        let mut res = vec![];
+        for field in content.into_iter().filter(|f| fields.contains(&f.key)) {
+            let analyzer = self.analyzers.get(field.key.as_str());
+            let terms = analyzer.analyze(query);
+            let offsets = analyzer
+                .tokens(field.value.as_str())
+                .into_iter()
+                .filter(|token| terms.contains(&token.text))
+                .map(|token| (token.start, token.end))
+                .collect::<Vec<(usize, usize)>>();
+            if offsets.is_empty() {
+                continue;
+            }
+            let chars = field.value.chars().collect::<Vec<char>>();
+            let mut fragments = vec![];
+            let mut i = 0;
+            while i < offsets.len() && fragments.len() < opts.max_fragments {
+                let (start, end) = Self::fragment_bounds(chars.len(), offsets[i], opts.fragment_size);
+                let mut fragment = String::new();
+                let mut pos = start;
+                while i < offsets.len() && offsets[i].1 <= end {
+                    let (m_start, m_end) = offsets[i];
+                    fragment.extend(&chars[pos..m_start]);
+                    fragment.push_str(opts.pre_tag.as_str());
+                    fragment.extend(&chars[m_start..m_end]);
+                    fragment.push_str(opts.post_tag.as_str());
+                    pos = m_end;
+                    i += 1;
                 }
+                fragment.extend(&chars[pos..end]);
+                fragments.push(fragment);
             }
+            res.push(Highlight { field: field.key, fragments, offsets });
         }
-        res.into_iter().collect()
+        res
+    }
+
+    /// window of `size` chars centered on a match
+    fn fragment_bounds(len: usize, (start, end): (usize, usize), size: usize) -> (usize, usize) {
+        if size == 0 || size >= len {
+            return (0, len);
+        }
+        let from = start.saturating_sub(size.saturating_sub(end - start) / 2);
+        let to = len.min(from + size).max(end);
+        (to.saturating_sub(size).min(from), to)
     }
 
-    pub fn w_find(&self, words: Vec<&str>) -> Vec<K> {
+    /// boost of a field. `1.0` unless set in `CollectionOptions::boost_opts`
+    pub fn boost(&self, field: &str) -> f32 {
+        self.boosts.get(field).copied().unwrap_or(1.0)
+    }
+
+    pub fn w_find(&self, query: &str) -> Vec<K> {
         let res = DashSet::new();
-        self.kv.par_iter().for_each(|rkv| {
-            let kv = rkv.pair();
-            let key = kv.0;
-            let counter = Arc::new(Mutex::new(0));
-            words.par_iter().for_each(|word| {
-                let mut c = counter.lock().unwrap();
-                let token = word.to_lowercase();
-                if key.contains(token.as_str()) {
-                    *c += 1;
-                }
-                if *c >= words.len() {
-                    let dk = kv.1.iter().map(|r| r.key().to_owned()).collect::<Vec<K>>();
-                    for k in dk {
-                        if res.get(&k).is_none() {
-                            res.insert(k);
+        for words in self.analyzers.query(query) {
+            self.kv.par_iter().for_each(|rkv| {
+                let kv = rkv.pair();
+                let key = kv.0;
+                let counter = Arc::new(Mutex::new(0));
+                words.par_iter().for_each(|word| {
+                    let mut c = counter.lock().unwrap();
+                    if key.contains(word.as_str()) {
+                        *c += 1;
+                    }
+                    if *c >= words.len() {
+                        let dk = kv.1.iter().map(|r| r.key().to_owned()).collect::<Vec<K>>();
+                        for k in dk {
+                            if res.get(&k).is_none() {
+                                res.insert(k);
+                            }
                         }
                     }
-                }
+                });
             });
-        });
+        }
         res.into_iter().collect()
     }
+
+    fn insert_terms(kv: &DashMap<String, Postings<K>>, k: &K, field: &str, terms: Vec<String>) {
+        for token in terms {
+            let postings = kv.entry(token).or_default();
+            let mut posting = postings.value().entry(k.to_owned()).or_default();
+            *posting.value_mut().entry(field.to_string()).or_insert(0) += 1;
+        }
+    }
+
+    fn remove_terms(kv: &DashMap<String, Postings<K>>, k: &K, field: &str, terms: Vec<String>) {
+        for token in terms {
+            if let Some(postings) = kv.get(&token) {
+                let empty = match postings.value().get_mut(k) {
+                    Some(mut posting) => {
+                        posting.value_mut().remove(field);
+                        posting.value().is_empty()
+                    }
+                    None => false,
+                };
+                if empty {
+                    postings.value().remove(k);
+                }
+            }
+            // a term without documents is dropped, under the lock `insert_terms` takes
+            kv.remove_if(&token, |_, postings| postings.is_empty());
+        }
+    }
 }
@@ -0,0 +1,183 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::{Debug, Display};
+use std::hash::Hash;
+
+use dashmap::{DashMap, DashSet};
+use log::trace;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::doc_trait::{Document, Point};
+
+const BASE32: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// geohash chars of indexed points. about 1.2km x 0.6km cells
+const PRECISION: usize = 6;
+
+/// most cells a query may scan. coarser cells are used beyond it
+const MAX_CELLS: usize = 64;
+
+/// meters of a degree of latitude
+const METERS_PER_DEGREE: f64 = 111_320.0;
+
+/// `GeoIndex` keeps the geohash of every point of the `geo_opts` fields
+pub struct GeoIndex<K> {
+    pub tree: DashMap<String, BTreeMap<String, DashSet<K>>>,
+}
+
+impl<K> GeoIndex<K>
+    where K: Serialize +
+    DeserializeOwned +
+    PartialOrd +
+    Ord +
+    PartialEq +
+    Eq +
+    Hash +
+    Clone +
+    Send +
+    Sync +
+    Debug +
+    Display +
+    'static
+{
+    pub fn new() -> Self {
+        Self {
+            tree: DashMap::new()
+        }
+    }
+
+    pub fn put<D>(&self, k: &K, v: &D) where D: Document {
+        for f in v.points() {
+            let mut tree = self.tree.entry(f.key).or_default();
+            tree.value_mut()
+                .entry(geohash(&f.point, PRECISION))
+                .or_default()
+                .insert(k.clone());
+        }
+    }
+
+    pub fn delete<D>(&self, k: &K, v: &D) where D: Document {
+        trace!("deleting geo index for key - {}",&k);
+        for f in v.points() {
+            if let Some(mut tree) = self.tree.get_mut(&f.key) {
+                let hash = geohash(&f.point, PRECISION);
+                let empty = match tree.value_mut().get_mut(&hash) {
+                    Some(set) => {
+                        set.remove(k);
+                        set.is_empty()
+                    }
+                    None => false,
+                };
+                if empty {
+                    tree.value_mut().remove(&hash);
+                }
+            }
+        }
+    }
+
+    pub fn clear(&self) {
+        self.tree.clear();
+    }
+
+    /// keys with a point of `field` in the cells around `center`.
+    /// candidates must be verified against the distance
+    pub fn near(&self, field: &str, center: &Point, radius: f64) -> HashSet<K> {
+        let dlat = radius / METERS_PER_DEGREE;
+        let cos = center.lat.to_radians().cos();
+        let dlon = if cos > f64::EPSILON { dlat / cos } else { 360.0 };
+        let sw = Point { lat: center.lat - dlat, lon: center.lon - dlon };
+        let ne = Point { lat: center.lat + dlat, lon: center.lon + dlon };
+        self.within_box(field, &sw, &ne)
+    }
+
+    /// keys with a point of `field` in the cells covering the box.
+    /// candidates must be verified against the box
+    pub fn within_box(&self, field: &str, sw: &Point, ne: &Point) -> HashSet<K> {
+        let mut res = HashSet::new();
+        let tree = match self.tree.get(field) {
+            Some(tree) => tree,
+            None => return res,
+        };
+        for prefix in cells(sw, ne) {
+            for (hash, set) in tree.value().range(prefix.clone()..) {
+                if !hash.starts_with(prefix.as_str()) {
+                    break;
+                }
+                res.extend(set.iter().map(|k| k.key().clone()));
+            }
+        }
+        res
+    }
+}
+
+/// geohash of a point with `precision` chars
+fn geohash(point: &Point, precision: usize) -> String {
+    let (mut lat, mut lon) = ((-90.0, 90.0), (-180.0, 180.0));
+    let mut hash = String::with_capacity(precision);
+    let (mut bits, mut ch, mut even) = (0, 0, true);
+    while hash.len() < precision {
+        let (range, value) = if even { (&mut lon, point.lon) } else { (&mut lat, point.lat) };
+        let mid = (range.0 + range.1) / 2.0;
+        ch <<= 1;
+        if value >= mid {
+            ch |= 1;
+            range.0 = mid;
+        } else {
+            range.1 = mid;
+        }
+        even = !even;
+        bits += 1;
+        if bits == 5 {
+            hash.push(BASE32[ch] as char);
+            bits = 0;
+            ch = 0;
+        }
+    }
+    hash
+}
+
+/// degrees of latitude and longitude covered by a cell of `precision` chars
+fn cell_size(precision: usize) -> (f64, f64) {
+    let bits = precision as i32 * 5;
+    let lon_bits = (bits + 1) / 2;
+    let lat_bits = bits / 2;
+    (180.0 / 2f64.powi(lat_bits), 360.0 / 2f64.powi(lon_bits))
+}
+
+/// geohash prefixes covering the box, as fine as `MAX_CELLS` allows
+fn cells(sw: &Point, ne: &Point) -> HashSet<String> {
+    let (min_lat, max_lat) = (sw.lat.max(-90.0), ne.lat.min(90.0));
+    let (mut min_lon, mut max_lon) = (sw.lon, ne.lon);
+    if min_lon < -180.0 || max_lon > 180.0 || min_lon > max_lon {
+        // crossing the antimeridian. scan every longitude
+        min_lon = -180.0;
+        max_lon = 180.0;
+    }
+    let mut precision = PRECISION;
+    while precision > 1 {
+        let (h, w) = cell_size(precision);
+        let count = (((max_lat - min_lat) / h).floor() + 2.0) * (((max_lon - min_lon) / w).floor() + 2.0);
+        if count <= MAX_CELLS as f64 {
+            break;
+        }
+        precision -= 1;
+    }
+    let (h, w) = cell_size(precision);
+    let mut res = HashSet::new();
+    let mut lat = min_lat;
+    loop {
+        let mut lon = min_lon;
+        loop {
+            res.insert(geohash(&Point { lat: lat.min(max_lat), lon: lon.min(max_lon) }, precision));
+            if lon >= max_lon {
+                break;
+            }
+            lon += w;
+        }
+        if lat >= max_lat {
+            break;
+        }
+        lat += h;
+    }
+    res
+}
@@ -0,0 +1,112 @@
+use std::collections::HashSet;
+use std::fmt::{Debug, Display};
+use std::hash::Hash;
+
+use dashmap::{DashMap, DashSet};
+use log::trace;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::doc_trait::Document;
+
+/// chars of a gram
+const NGRAM: usize = 3;
+
+/// `NGramIndex` keeps the lowercased trigrams of every `ngram_opts` field.
+/// values shorter than a gram are kept whole
+pub struct NGramIndex<K> {
+    pub kv: DashMap<String, DashMap<String, DashSet<K>>>,
+}
+
+impl<K> NGramIndex<K>
+    where K: Serialize +
+    DeserializeOwned +
+    PartialOrd +
+    Ord +
+    PartialEq +
+    Eq +
+    Hash +
+    Clone +
+    Send +
+    Sync +
+    Debug +
+    Display +
+    'static
+{
+    pub fn new() -> Self {
+        Self {
+            kv: DashMap::new()
+        }
+    }
+
+    pub fn put<D>(&self, k: &K, v: &D) where D: Document {
+        for field in v.grams() {
+            let grams = self.kv.entry(field.key).or_default();
+            for gram in Self::grams(field.value.as_str()) {
+                grams.value().entry(gram).or_default().insert(k.clone());
+            }
+        }
+    }
+
+    pub fn delete<D>(&self, k: &K, v: &D) where D: Document {
+        trace!("deleting n-gram index for key - {}",&k);
+        for field in v.grams() {
+            if let Some(grams) = self.kv.get(&field.key) {
+                for gram in Self::grams(field.value.as_str()) {
+                    let empty = match grams.value().get(&gram) {
+                        Some(set) => {
+                            set.value().remove(k);
+                            set.value().is_empty()
+                        }
+                        None => false,
+                    };
+                    if empty {
+                        grams.value().remove_if(&gram, |_, set| set.is_empty());
+                    }
+                }
+            }
+        }
+    }
+
+    /// keys whose `field` may contain `text`. candidates must be verified against the value
+    pub fn candidates(&self, field: &str, text: &str) -> HashSet<K> {
+        let grams = match self.kv.get(field) {
+            Some(grams) => grams,
+            None => return HashSet::new(),
+        };
+        let text = text.to_lowercase();
+        if text.chars().count() < NGRAM {
+            // any gram holding the text is a candidate
+            return grams
+                .value()
+                .iter()
+                .filter(|kv| kv.key().contains(text.as_str()))
+                .flat_map(|kv| kv.value().iter().map(|k| k.key().clone()).collect::<Vec<K>>())
+                .collect();
+        }
+        let mut res: Option<HashSet<K>> = None;
+        for gram in Self::grams(text.as_str()) {
+            let keys = match grams.value().get(&gram) {
+                Some(set) => set.value().iter().map(|k| k.key().clone()).collect::<HashSet<K>>(),
+                None => return HashSet::new(),
+            };
+            res = Some(match res {
+                Some(res) => res.intersection(&keys).cloned().collect(),
+                None => keys,
+            });
+        }
+        res.unwrap_or_default()
+    }
+
+    pub fn clear(&self) {
+        self.kv.clear();
+    }
+
+    fn grams(value: &str) -> HashSet<String> {
+        let chars = value.to_lowercase().chars().collect::<Vec<char>>();
+        if chars.len() < NGRAM {
+            return HashSet::from([chars.iter().collect::<String>()]);
+        }
+        chars.windows(NGRAM).map(|w| w.iter().collect::<String>()).collect()
+    }
+}
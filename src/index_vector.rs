@@ -0,0 +1,332 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fmt::{Debug, Display};
+use std::hash::Hash;
+use std::sync::RwLock;
+
+use dashmap::DashMap;
+use log::trace;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::database::{Metric, VectorConfig, VectorMode};
+use crate::doc_trait::Document;
+use crate::errors::IndexError;
+
+/// `VectorIndex` keeps the embeddings of every `vector_opts` field
+pub struct VectorIndex<K> {
+    stores: DashMap<String, RwLock<Store<K>>>,
+}
+
+impl<K> VectorIndex<K>
+    where K: Serialize +
+    DeserializeOwned +
+    PartialOrd +
+    Ord +
+    PartialEq +
+    Eq +
+    Hash +
+    Clone +
+    Send +
+    Sync +
+    Debug +
+    Display +
+    'static
+{
+    pub fn new(vector_opts: &[VectorConfig]) -> Self {
+        let stores = DashMap::new();
+        for cnf in vector_opts {
+            stores.insert(cnf.field.to_string(), RwLock::new(Store::new(cnf)));
+        }
+        Self { stores }
+    }
+
+    /// every vector of the document must match the dimension of its field
+    pub fn check<D>(&self, v: &D) -> Result<(), IndexError> where D: Document {
+        for f in v.vectors() {
+            if let Some(store) = self.stores.get(&f.key) {
+                let dimension = store.value().read().unwrap().cnf.dimension;
+                if f.vector.len() != dimension {
+                    return Err(IndexError::DimensionMismatch(f.key, dimension));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn put<D>(&self, k: &K, v: &D) where D: Document {
+        for f in v.vectors() {
+            if let Some(store) = self.stores.get(&f.key) {
+                store.value().write().unwrap().put(k.clone(), f.vector);
+            }
+        }
+    }
+
+    pub fn delete<D>(&self, k: &K, v: &D) where D: Document {
+        trace!("deleting vector index for key - {}",&k);
+        for f in v.vectors() {
+            if let Some(store) = self.stores.get(&f.key) {
+                store.value().write().unwrap().delete(k);
+            }
+        }
+    }
+
+    pub fn clear(&self) {
+        for store in self.stores.iter() {
+            store.value().write().unwrap().clear();
+        }
+    }
+
+    /// nearest keys of `field` with their score, nearest first.
+    /// `ef` is the least number of candidates an approximate search looks at
+    pub fn search(&self, field: &str, query: &[f32], ef: usize) -> Result<Vec<(K, f32)>, IndexError> {
+        let store = match self.stores.get(field) {
+            Some(store) => store,
+            None => return Err(IndexError::NoSuchIndex),
+        };
+        let store = store.value().read().unwrap();
+        if query.len() != store.cnf.dimension {
+            return Err(IndexError::DimensionMismatch(field.to_string(), store.cnf.dimension));
+        }
+        Ok(store.search(query, ef))
+    }
+
+    /// number of vectors of `field`
+    pub fn len(&self, field: &str) -> usize {
+        self.stores.get(field).map_or(0, |store| store.value().read().unwrap().ids.len())
+    }
+}
+
+struct Node<K> {
+    key: K,
+    vector: Vec<f32>,
+    /// links of every layer the node is in
+    links: Vec<Vec<usize>>,
+    deleted: bool,
+}
+
+/// vectors of a field. nodes are linked as a HNSW graph when the field is approximate
+struct Store<K> {
+    cnf: VectorConfig,
+    nodes: Vec<Node<K>>,
+    ids: HashMap<K, usize>,
+    entry: Option<usize>,
+    deleted: usize,
+    seed: u64,
+}
+
+/// a node with its distance to the query. ordered by distance
+#[derive(Clone, Copy, PartialEq)]
+struct Candidate(f32, usize);
+
+impl Eq for Candidate {}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0).then_with(|| self.1.cmp(&other.1))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K> Store<K> where K: Eq + Hash + Clone {
+    fn new(cnf: &VectorConfig) -> Self {
+        Self {
+            cnf: cnf.clone(),
+            nodes: vec![],
+            ids: HashMap::new(),
+            entry: None,
+            deleted: 0,
+            seed: 0x2545_f491_4f6c_dd1d,
+        }
+    }
+
+    fn put(&mut self, k: K, vector: Vec<f32>) {
+        self.delete(&k);
+        let id = self.nodes.len();
+        let (m, ef_construction) = match self.cnf.mode {
+            VectorMode::Exact => {
+                self.nodes.push(Node { key: k.clone(), vector, links: vec![], deleted: false });
+                self.ids.insert(k, id);
+                return;
+            }
+            VectorMode::Hnsw { m, ef_construction, .. } => (m.max(2), ef_construction.max(1)),
+        };
+        let level = self.level(m);
+        self.nodes.push(Node { key: k.clone(), vector, links: vec![vec![]; level + 1], deleted: false });
+        self.ids.insert(k, id);
+
+        let entry = match self.entry {
+            Some(entry) => entry,
+            None => {
+                self.entry = Some(id);
+                return;
+            }
+        };
+        let top = self.nodes[entry].links.len() - 1;
+        let query = self.nodes[id].vector.clone();
+        let mut nearest = Candidate(self.distance(&query, entry), entry);
+        for layer in (level + 1..=top).rev() {
+            nearest = self.greedy(&query, nearest, layer);
+        }
+        let mut entries = vec![nearest];
+        for layer in (0..=level.min(top)).rev() {
+            let found = self.search_layer(&query, &entries, ef_construction, layer);
+            let max_links = if layer == 0 { m * 2 } else { m };
+            let neighbours = found.iter().take(m).map(|c| c.1).collect::<Vec<usize>>();
+            self.nodes[id].links[layer] = neighbours.clone();
+            for n in neighbours {
+                self.nodes[n].links[layer].push(id);
+                if self.nodes[n].links[layer].len() > max_links {
+                    self.prune(n, layer, max_links);
+                }
+            }
+            entries = found;
+        }
+        if level > top {
+            self.entry = Some(id);
+        }
+    }
+
+    /// deleted nodes stay in the graph to keep it connected. they are never returned.
+    /// the store is rebuilt once most of its nodes are deleted
+    fn delete(&mut self, k: &K) {
+        if let Some(id) = self.ids.remove(k) {
+            self.nodes[id].deleted = true;
+            self.deleted += 1;
+            if self.deleted > 64 && self.deleted * 2 > self.nodes.len() {
+                let live = std::mem::take(&mut self.nodes)
+                    .into_iter()
+                    .filter(|n| !n.deleted)
+                    .map(|n| (n.key, n.vector))
+                    .collect::<Vec<(K, Vec<f32>)>>();
+                self.clear();
+                for (k, vector) in live {
+                    self.put(k, vector);
+                }
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.nodes.clear();
+        self.ids.clear();
+        self.entry = None;
+        self.deleted = 0;
+    }
+
+    fn search(&self, query: &[f32], ef: usize) -> Vec<(K, f32)> {
+        let found = match (&self.cnf.mode, self.entry) {
+            (VectorMode::Hnsw { ef_search, .. }, Some(entry)) => {
+                let mut nearest = Candidate(self.distance(query, entry), entry);
+                for layer in (1..self.nodes[entry].links.len()).rev() {
+                    nearest = self.greedy(query, nearest, layer);
+                }
+                self.search_layer(query, &[nearest], ef.max(*ef_search), 0)
+            }
+            _ => {
+                let mut found = self.ids.values().map(|id| Candidate(self.distance(query, *id), *id)).collect::<Vec<Candidate>>();
+                found.sort();
+                found
+            }
+        };
+        found
+            .into_iter()
+            .filter(|c| !self.nodes[c.1].deleted)
+            .map(|c| (self.nodes[c.1].key.clone(), self.score(c.0)))
+            .collect()
+    }
+
+    /// moves to the nearest neighbour until none is nearer
+    fn greedy(&self, query: &[f32], mut nearest: Candidate, layer: usize) -> Candidate {
+        loop {
+            let mut moved = false;
+            for n in &self.nodes[nearest.1].links[layer] {
+                let candidate = Candidate(self.distance(query, *n), *n);
+                if candidate < nearest {
+                    nearest = candidate;
+                    moved = true;
+                }
+            }
+            if !moved {
+                return nearest;
+            }
+        }
+    }
+
+    /// best `ef` nodes of a layer reachable from `entries`, nearest first
+    fn search_layer(&self, query: &[f32], entries: &[Candidate], ef: usize, layer: usize) -> Vec<Candidate> {
+        let mut visited = entries.iter().map(|c| c.1).collect::<HashSet<usize>>();
+        let mut candidates = entries.iter().map(|c| std::cmp::Reverse(*c)).collect::<BinaryHeap<_>>();
+        let mut found = entries.iter().copied().collect::<BinaryHeap<Candidate>>();
+        while let Some(std::cmp::Reverse(current)) = candidates.pop() {
+            if found.len() >= ef && current > *found.peek().unwrap() {
+                break;
+            }
+            for n in &self.nodes[current.1].links[layer] {
+                if !visited.insert(*n) {
+                    continue;
+                }
+                let candidate = Candidate(self.distance(query, *n), *n);
+                if found.len() < ef || candidate < *found.peek().unwrap() {
+                    candidates.push(std::cmp::Reverse(candidate));
+                    found.push(candidate);
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+        found.into_sorted_vec()
+    }
+
+    /// keeps the nearest `max_links` links of a node
+    fn prune(&mut self, id: usize, layer: usize, max_links: usize) {
+        let vector = self.nodes[id].vector.clone();
+        let mut links = self.nodes[id].links[layer]
+            .iter()
+            .map(|n| Candidate(self.distance(&vector, *n), *n))
+            .collect::<Vec<Candidate>>();
+        links.sort();
+        links.truncate(max_links);
+        self.nodes[id].links[layer] = links.into_iter().map(|c| c.1).collect();
+    }
+
+    /// random layer of a new node. xorshift keeps the graph reproducible
+    fn level(&mut self, m: usize) -> usize {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        let uniform = (self.seed >> 11) as f64 / (1u64 << 53) as f64;
+        (-(1.0 - uniform).ln() / (m as f64).ln()).floor() as usize
+    }
+
+    /// lower is nearer whatever the metric
+    fn distance(&self, query: &[f32], id: usize) -> f32 {
+        let vector = &self.nodes[id].vector;
+        match self.cnf.metric {
+            Metric::Cosine => {
+                let (dot, qn, vn) = query.iter().zip(vector).fold((0f32, 0f32, 0f32), |(dot, qn, vn), (q, v)| {
+                    (dot + q * v, qn + q * q, vn + v * v)
+                });
+                let norm = (qn * vn).sqrt();
+                if norm == 0.0 { 1.0 } else { 1.0 - dot / norm }
+            }
+            Metric::Dot => -query.iter().zip(vector).map(|(q, v)| q * v).sum::<f32>(),
+            Metric::L2 => query.iter().zip(vector).map(|(q, v)| (q - v) * (q - v)).sum::<f32>().sqrt(),
+        }
+    }
+
+    /// cosine similarity, dot product or euclidean distance
+    fn score(&self, distance: f32) -> f32 {
+        match self.cnf.metric {
+            Metric::Cosine => 1.0 - distance,
+            Metric::Dot => -distance,
+            Metric::L2 => distance,
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::{BTreeMap, HashSet};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use flql::Flql;
+use tokio::sync::OwnedMutexGuard;
+
+use crate::collection::K;
+use crate::database::{CollectionOptions, Database};
+use crate::doc::QueryBased;
+use crate::doc_trait::ViewConfig;
+use crate::headers::FlinchError;
+use crate::statement::{self, Statement};
+use crate::utils::trim_apos;
+
+tokio::task_local! {
+    /// journal of the atomic script running on the task
+    static JOURNAL: Arc<Journal>;
+}
+
+/// a write lock held by the journal
+type Held = (Arc<tokio::sync::Mutex<()>>, OwnedMutexGuard<()>);
+
+/// document and expiry of a pointer before a script first wrote to it
+pub(crate) struct Previous {
+    pub k: K,
+    pub doc: Option<String>,
+    pub ttl: Option<i64>,
+}
+
+/// a write of a script, the way to undo it
+enum Undo {
+    Pointer(String, Previous),
+    /// a collection the script created by name
+    Created(String),
+    /// a collection the script dropped, its documents are recorded as pointers before
+    Dropped(CollectionOptions),
+    /// views of a collection before the script dropped one
+    Views(String, Vec<ViewConfig>),
+}
+
+/// `Journal` of an atomic script. keeps what its statements write over, the first write first,
+/// and the write locks of the collections it writes to until the script is done
+#[derive(Default)]
+pub(crate) struct Journal {
+    undo: Mutex<Vec<Undo>>,
+    touched: Mutex<HashSet<(String, K)>>,
+    locks: Mutex<Vec<Held>>,
+    undoing: AtomicBool,
+}
+
+impl Journal {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// runs a script with the journal
+    pub async fn scope<F: Future>(self: Arc<Self>, f: F) -> F::Output {
+        JOURNAL.scope(self, f).await
+    }
+
+    /// takes the write locks of the collections the statements write to, in the order of their names
+    /// so two scripts never wait on each other. collections created by the script are locked on their first write
+    pub async fn lock(&self, db: &Database<QueryBased>, statements: &[Result<Statement, FlinchError>]) {
+        let mut names = statements.iter().flatten().filter_map(writes).collect::<Vec<String>>();
+        names.sort();
+        names.dedup();
+        for name in names {
+            let col = db.using(name.as_str()).map(|col| Arc::clone(col.value()));
+            if let Ok(col) = col {
+                col.writing().await;
+            }
+        }
+    }
+
+    /// undoes the writes, the last first. nothing is recorded meanwhile
+    pub async fn rollback(&self, db: &Database<QueryBased>) {
+        self.undoing.store(true, Ordering::Relaxed);
+        let undo = std::mem::take(&mut *self.undo.lock().unwrap());
+        let mut pointers = vec![];
+        for write in undo.into_iter().rev() {
+            match write {
+                Undo::Pointer(collection, previous) => pointers.push((collection, previous)),
+                Undo::Created(name) => {
+                    restore(db, std::mem::take(&mut pointers)).await;
+                    let _ = db.drop(name.as_str()).await;
+                }
+                Undo::Dropped(opts) => {
+                    restore(db, std::mem::take(&mut pointers)).await;
+                    let _ = db.add(opts).await;
+                }
+                Undo::Views(name, view_opts) => {
+                    restore(db, std::mem::take(&mut pointers)).await;
+                    db.put_view_opts(name.as_str(), view_opts).await;
+                }
+            }
+        }
+        restore(db, pointers).await;
+    }
+
+    /// holds a write lock until the script is done, once
+    async fn hold(&self, lock: &Arc<tokio::sync::Mutex<()>>) {
+        if self.locks.lock().unwrap().iter().any(|(held, _)| Arc::ptr_eq(held, lock)) {
+            return;
+        }
+        let guard = Arc::clone(lock).lock_owned().await;
+        self.locks.lock().unwrap().push((Arc::clone(lock), guard));
+    }
+
+    fn push(&self, undo: Undo) {
+        if !self.undoing.load(Ordering::Relaxed) {
+            self.undo.lock().unwrap().push(undo);
+        }
+    }
+}
+
+/// puts the documents and expiries back, by collection
+async fn restore(db: &Database<QueryBased>, pointers: Vec<(String, Previous)>) {
+    let mut by_collection: BTreeMap<String, Vec<Previous>> = BTreeMap::new();
+    for (collection, previous) in pointers {
+        by_collection.entry(collection).or_default().push(previous);
+    }
+    for (name, previous) in by_collection {
+        let col = db.using(name.as_str()).map(|col| Arc::clone(col.value()));
+        if let Ok(col) = col {
+            col.undo(previous).await;
+        }
+    }
+}
+
+/// waits for a write lock. within an atomic script the journal takes it and holds it until the script is done
+pub(crate) async fn writing(lock: &Arc<tokio::sync::Mutex<()>>) -> Option<OwnedMutexGuard<()>> {
+    match JOURNAL.try_with(Arc::clone) {
+        Ok(journal) => {
+            journal.hold(lock).await;
+            None
+        }
+        Err(_) => Some(Arc::clone(lock).lock_owned().await),
+    }
+}
+
+/// records a pointer of a collection before the first write of an atomic script to it.
+/// `before` reads its document and expiry
+pub(crate) fn touch<F>(collection: &str, k: &K, before: F) where F: FnOnce() -> (Option<String>, Option<i64>) {
+    let _ = JOURNAL.try_with(|journal| {
+        if journal.undoing.load(Ordering::Relaxed) {
+            return;
+        }
+        if journal.touched.lock().unwrap().insert((collection.to_string(), k.to_string())) {
+            let (doc, ttl) = before();
+            journal.push(Undo::Pointer(collection.to_string(), Previous { k: k.to_string(), doc, ttl }));
+        }
+    });
+}
+
+/// records a collection an atomic script created
+pub(crate) fn created(name: &str) {
+    let _ = JOURNAL.try_with(|journal| journal.push(Undo::Created(name.to_string())));
+}
+
+/// records a collection an atomic script dropped
+pub(crate) fn dropped(opts: CollectionOptions) {
+    let _ = JOURNAL.try_with(|journal| journal.push(Undo::Dropped(opts)));
+}
+
+/// records the views of a collection before an atomic script dropped one
+pub(crate) fn views(name: &str, view_opts: Vec<ViewConfig>) {
+    let _ = JOURNAL.try_with(|journal| journal.push(Undo::Views(name.to_string(), view_opts)));
+}
+
+/// the collection a statement writes to
+pub(crate) fn writes(parsed: &Statement) -> Option<String> {
+    match parsed {
+        Statement::Flql(flql) => match flql {
+            Flql::New(options) => serde_json::from_str::<CollectionOptions>(options.as_str()).ok().map(|opts| opts.name),
+            Flql::Drop(collection)
+            | Flql::Ttl(_, _, collection)
+            | Flql::Put(_, collection)
+            | Flql::PutWhen(_, _, collection)
+            | Flql::PutPointer(_, _, collection)
+            | Flql::Delete(collection)
+            | Flql::DeleteWhen(_, collection)
+            | Flql::DeletePointer(_, collection)
+            | Flql::DeleteClip(_, collection) => Some(trim_apos(collection)),
+            _ => None,
+        },
+        Statement::DropView(_, collection)
+        | Statement::DeleteView(_, collection)
+        | Statement::PutIndex(_, _, collection)
+        | Statement::DeleteIndex(_, collection)
+        | Statement::TtlIndex(_, _, collection) => Some(trim_apos(collection)),
+        Statement::OnConflict(parsed, _) => writes(parsed),
+        Statement::Profile(stmt) => statement::parse(format!("{};", stmt).as_str()).ok().as_ref().and_then(writes),
+        _ => None,
+    }
+}
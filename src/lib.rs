@@ -1,24 +1,42 @@
 mod authenticate;
 mod clips;
+mod cursor;
 mod events;
+mod expr;
 mod index_fields;
+mod index_geo;
 mod index_hash;
+mod index_ngram;
+mod index_vector;
+mod journal;
 mod notif_type;
 mod persistent;
+mod planner;
 mod pri_headers;
 mod pub_sub;
 mod range;
+mod sort;
 mod ttl;
 mod utils;
+mod views;
 mod watchman;
 mod zalloc;
 
+pub mod aggregate;
+pub mod analyzer;
 pub mod collection;
 pub mod database;
 pub mod doc_trait;
 pub mod doc;
+pub mod guard;
 pub mod headers;
+pub mod lookup;
+pub mod predicate;
+pub mod prepared;
+pub mod profile;
+pub mod projection;
 pub mod query;
 pub mod schemas;
+pub mod statement;
 pub mod errors;
 pub mod extension;
@@ -0,0 +1,100 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Number, Value};
+
+use crate::sort::lookup;
+
+/// largest integer a `f64` holds exactly
+const MAX_EXACT: f64 = 9_007_199_254_740_992.0;
+
+/// matches pointers instead of a field of the other collection
+pub const POINTER: &str = "_pointer";
+
+/// `Lookup` embeds documents of another `collection` in every document as `alias`.
+/// a document of `collection` matches when its `foreign` field holds a value of the `local` field.
+/// `many` embeds every match in an array, otherwise the first match or null
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Lookup {
+    pub collection: String,
+    pub local: String,
+    pub foreign: String,
+    pub alias: String,
+    pub many: bool,
+}
+
+impl Lookup {
+    pub fn new(collection: &str, local: &str, foreign: &str, alias: &str, many: bool) -> Result<Self, String> {
+        for (name, path) in [("local", local), ("foreign", foreign), ("alias", alias)] {
+            if path.is_empty() || path.split('.').any(|step| step.is_empty()) {
+                return Err(format!("invalid {} field `{}` of lookup", name, path));
+            }
+        }
+        if collection.is_empty() {
+            return Err("lookup needs a collection".to_string());
+        }
+        Ok(Lookup {
+            collection: collection.to_string(),
+            local: local.to_string(),
+            foreign: foreign.to_string(),
+            alias: alias.to_string(),
+            many,
+        })
+    }
+
+    /// the values of the `local` field of a document, arrays are flattened
+    pub(crate) fn values<'a>(&self, doc: &'a Value) -> Vec<&'a Value> {
+        scalars(lookup(doc, &self.local))
+    }
+
+    /// sets `alias` of a document to its matches, keyed by their value
+    pub(crate) fn embed(&self, doc: &mut Value, matches: &HashMap<String, Vec<Value>>) {
+        let mut seen = HashSet::new();
+        let found = self
+            .values(doc)
+            .into_iter()
+            .filter_map(|v| matches.get(&key(v)?))
+            .flatten()
+            .filter(|m| seen.insert(m.get(POINTER).and_then(|p| p.as_str()).unwrap_or_default().to_string()))
+            .cloned()
+            .collect::<Vec<Value>>();
+        let value = if self.many {
+            Value::Array(found)
+        } else {
+            found.into_iter().next().unwrap_or(Value::Null)
+        };
+        if let Some(obj) = doc.as_object_mut() {
+            obj.insert(self.alias.to_string(), value);
+        }
+    }
+}
+
+/// strings, numbers and booleans of a value. arrays are flattened
+pub(crate) fn scalars(v: &Value) -> Vec<&Value> {
+    match v {
+        Value::String(_) | Value::Number(_) | Value::Bool(_) => vec![v],
+        Value::Array(arr) => arr.iter().flat_map(scalars).collect(),
+        Value::Null | Value::Object(_) => vec![],
+    }
+}
+
+/// the text a value matches by. integral numbers match whatever way they are written
+pub(crate) fn key(v: &Value) -> Option<String> {
+    forms(v).into_iter().next()
+}
+
+/// the ways a value is written as text, like hash indexes keep it
+pub(crate) fn forms(v: &Value) -> Vec<String> {
+    match v {
+        Value::String(s) => vec![s.to_string()],
+        Value::Bool(b) => vec![b.to_string()],
+        Value::Number(n) => match n.as_f64() {
+            Some(f) if f.fract() == 0.0 && f.abs() < MAX_EXACT => {
+                let float = Number::from_f64(f).map(|n| n.to_string()).unwrap_or_default();
+                vec![(f as i64).to_string(), float]
+            }
+            _ => vec![n.to_string()],
+        },
+        _ => vec![],
+    }
+}
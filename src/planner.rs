@@ -0,0 +1,201 @@
+use std::collections::HashSet;
+use std::fmt::{Debug, Display};
+use std::hash::Hash;
+use std::ops::Bound;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Number;
+
+use crate::clips::Clips;
+use crate::database::CollectionOptions;
+use crate::expr::{Expr, Op};
+use crate::index_fields::InvertedIndex;
+use crate::index_hash::HashIndex;
+use crate::index_ngram::NGramIndex;
+use crate::range::{Range, RangeKey};
+
+/// largest integer a `f64` holds exactly
+const MAX_EXACT: f64 = 9_007_199_254_740_992.0;
+
+/// `Planner` narrows a condition down to the keys its indexed comparisons allow.
+/// candidates are a superset of the matches, the condition is still evaluated on them
+pub struct Planner<'a, K> {
+    pub opts: &'a CollectionOptions,
+    pub hash_idx: &'a HashIndex<K>,
+    pub clips: &'a Clips<K>,
+    pub range: &'a Range<K>,
+    pub inverted_idx: &'a InvertedIndex<K>,
+    pub ngram_idx: &'a NGramIndex<K>,
+}
+
+impl<'a, K> Planner<'a, K>
+    where K: Serialize +
+    DeserializeOwned +
+    PartialOrd +
+    Ord +
+    PartialEq +
+    Eq +
+    Hash +
+    Clone +
+    Send +
+    Sync +
+    Debug +
+    Display +
+    'static
+{
+    /// keys of every document `expr` may match. `None` when no index narrows it down
+    pub fn candidates(&self, expr: &Expr) -> Option<HashSet<K>> {
+        match expr {
+            Expr::Op(Op::And, left, right) => match (self.candidates(left), self.candidates(right)) {
+                (Some(left), Some(right)) => {
+                    let (small, large) = if left.len() <= right.len() { (left, right) } else { (right, left) };
+                    Some(small.into_iter().filter(|k| large.contains(k)).collect())
+                }
+                (Some(keys), None) | (None, Some(keys)) => Some(keys),
+                (None, None) => None,
+            },
+            Expr::Op(Op::Or, left, right) => {
+                let mut keys = self.candidates(left)?;
+                keys.extend(self.candidates(right)?);
+                Some(keys)
+            }
+            Expr::Call(name, args) if name == "in" => match args.as_slice() {
+                [Expr::Path(path), list] => self.compare(field(path)?, Op::In, list),
+                _ => None,
+            },
+            Expr::Op(op, left, right) => match (left.as_ref(), right.as_ref()) {
+                (Expr::Path(path), value) => self.compare(field(path)?, *op, value),
+                (value, Expr::Path(path)) => self.compare(field(path)?, flip(*op)?, value),
+                _ => None,
+            },
+            Expr::Between(value, lower, upper) => match (value.as_ref(), lower.as_ref(), upper.as_ref()) {
+                (Expr::Path(path), Expr::Num(lower), Expr::Num(upper)) => {
+                    let field = self.ranged(field(path)?)?;
+                    Some(self.range.keys(field, Bound::Excluded(RangeKey::Number(*lower)), Bound::Excluded(RangeKey::Number(*upper))))
+                }
+                (Expr::Path(path), Expr::Str(lower), Expr::Str(upper)) => {
+                    let field = self.ranged(field(path)?)?;
+                    Some(self.text_range(field, Bound::Excluded(lower), Bound::Excluded(upper)))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// candidates of `.field <op> value`
+    fn compare(&self, field: &str, op: Op, value: &Expr) -> Option<HashSet<K>> {
+        match (op, value) {
+            (Op::Eq, value) => self.equal(field, value),
+            (Op::In, Expr::Arr(values)) => {
+                let mut keys = HashSet::new();
+                for value in values {
+                    keys.extend(self.equal(field, value)?);
+                }
+                Some(keys)
+            }
+            // a string holding the text, or an array holding it as an element
+            (Op::Contains, Expr::Str(text)) if !text.is_empty() && self.opts.ngram_opts.iter().any(|f| f == field) => {
+                Some(self.ngram_idx.candidates(field, text))
+            }
+            (Op::Contains, Expr::Num(_) | Expr::Bool(_)) => self.equal(field, value),
+            (Op::Gt | Op::Gte | Op::Lt | Op::Lte, Expr::Num(n)) => {
+                let field = self.ranged(field)?;
+                let (from, to) = bounds(op, RangeKey::Number(*n));
+                Some(self.range.keys(field, from, to))
+            }
+            (Op::Gt | Op::Gte | Op::Lt | Op::Lte, Expr::Str(s)) => {
+                let field = self.ranged(field)?;
+                let (from, to) = bounds(op, s);
+                Some(self.text_range(field, from, to))
+            }
+            _ => None,
+        }
+    }
+
+    /// candidates of `.field == value`, from the first index kept for the field
+    fn equal(&self, field: &str, value: &Expr) -> Option<HashSet<K>> {
+        let forms = forms(value)?;
+        let has = |opts: &[String]| opts.iter().any(|f| f == field);
+        if has(&self.opts.index_opts) {
+            return Some(forms.iter().filter_map(|f| self.hash_idx.get(f).map(|k| k.value().clone())).collect());
+        }
+        if has(&self.opts.clips_opts) {
+            return Some(match self.clips.fields.get(field) {
+                Some(values) => forms
+                    .iter()
+                    .filter_map(|f| values.value().get(f).map(|set| set.iter().map(|k| k.key().clone()).collect::<Vec<K>>()))
+                    .flatten()
+                    .collect(),
+                None => HashSet::new(),
+            });
+        }
+        if has(&self.opts.range_opts) {
+            let key = RangeKey::from(forms[0].as_str());
+            return Some(self.range.keys(field, Bound::Included(key.clone()), Bound::Included(key)));
+        }
+        match value {
+            Expr::Str(text) if has(&self.opts.search_opts) => self.inverted_idx.matching(field, text),
+            _ => None,
+        }
+    }
+
+    /// text bounds of a string comparison. strings holding a number are kept as numbers
+    /// by the range tree, so every number is a candidate too
+    fn text_range(&self, field: &str, from: Bound<&String>, to: Bound<&String>) -> HashSet<K> {
+        let text = |b: Bound<&String>| b.map(|s| RangeKey::Text(s.to_string()));
+        let mut keys = self.range.keys(field, text(from), text(to));
+        keys.extend(self.range.keys(field, Bound::Unbounded, Bound::Excluded(RangeKey::Text(String::new()))));
+        keys
+    }
+
+    fn ranged<'f>(&self, field: &'f str) -> Option<&'f str> {
+        self.opts.range_opts.iter().any(|f| f == field).then_some(field)
+    }
+}
+
+/// field of a selector path. nested or pattern paths are never planned
+fn field(path: &str) -> Option<&str> {
+    let plain = !path.is_empty() && path.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-');
+    plain.then_some(path)
+}
+
+/// the comparison with its sides swapped
+fn flip(op: Op) -> Option<Op> {
+    match op {
+        Op::Eq => Some(Op::Eq),
+        Op::Gt => Some(Op::Lt),
+        Op::Gte => Some(Op::Lte),
+        Op::Lt => Some(Op::Gt),
+        Op::Lte => Some(Op::Gte),
+        _ => None,
+    }
+}
+
+fn bounds<T>(op: Op, value: T) -> (Bound<T>, Bound<T>) {
+    match op {
+        Op::Gt => (Bound::Excluded(value), Bound::Unbounded),
+        Op::Gte => (Bound::Included(value), Bound::Unbounded),
+        Op::Lt => (Bound::Unbounded, Bound::Excluded(value)),
+        _ => (Bound::Unbounded, Bound::Included(value)),
+    }
+}
+
+/// texts a document value equal to a literal is indexed under.
+/// a number may have been written as an integer or as a float
+fn forms(value: &Expr) -> Option<Vec<String>> {
+    match value {
+        Expr::Str(s) => Some(vec![s.to_string()]),
+        Expr::Bool(b) => Some(vec![b.to_string()]),
+        Expr::Num(n) if n.abs() < MAX_EXACT => {
+            let float = Number::from_f64(*n)?.to_string();
+            if n.fract() == 0.0 {
+                Some(vec![(*n as i64).to_string(), float])
+            } else {
+                Some(vec![float])
+            }
+        }
+        _ => None,
+    }
+}
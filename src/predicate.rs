@@ -0,0 +1,400 @@
+use std::borrow::Cow;
+
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use flql::exp_parser::BoxedExpression;
+use regex::Regex;
+use serde_json::{Map, Value};
+
+use crate::expr::{self, Expr, Op};
+
+/// `Predicate` is a compiled `flql` condition, evaluated right on the `serde_json::Value` of a document.
+/// besides the `flql` syntax a condition may call
+/// - `exists(.path)` true when the path is in the document, even if null
+/// - `in(value, [..])` true when the value, or any of its elements, is in the array
+/// - `matches(value, "regex")` regex match of a string, or of any string element
+/// - `lower(value)` and `upper(value)`
+/// - `date(value)` datetime of a string or of epoch seconds, comparable with `now()`
+pub struct Predicate {
+    compiled: Compiled,
+}
+
+enum Compiled {
+    Native(Node),
+    /// selectors with `gjson` patterns or modifiers are left to `flql`
+    Flql(BoxedExpression),
+}
+
+impl Predicate {
+    pub fn compile(condition: &str) -> Result<Self, String> {
+        let compiled = match expr::parse(condition) {
+            Ok(parsed) => match Node::compile(&parsed) {
+                Ok(node) => Compiled::Native(node),
+                Err(Unsupported::Selector(_)) if !has_calls(&parsed) => {
+                    Compiled::Flql(flql::expr_parse(condition).map_err(|e| e.to_string())?)
+                }
+                Err(Unsupported::Selector(path)) => return Err(format!("unsupported selector .{} with functions", path)),
+                Err(Unsupported::Call(error)) => return Err(error),
+            },
+            Err(error) => Compiled::Flql(flql::expr_parse(condition).map_err(|_| error)?),
+        };
+        Ok(Self { compiled })
+    }
+
+    /// whether the document satisfies the condition. evaluation errors, such as
+    /// comparing a string with a number, never match
+    pub fn matches(&self, doc: &Value) -> bool {
+        match &self.compiled {
+            Compiled::Native(node) => matches!(node.eval(doc), Some(Val::Bool(true))),
+            Compiled::Flql(expression) => {
+                let json = serde_json::to_vec(doc).unwrap_or_default();
+                matches!(expression.calculate(&json), Ok(flql::exp_parser::Value::Bool(true)))
+            }
+        }
+    }
+}
+
+enum Unsupported {
+    Selector(String),
+    Call(String),
+}
+
+/// a step of a selector path. `#` counts an array, or maps the rest of the path over it
+enum Step {
+    Key(String),
+    Each,
+}
+
+enum Coerce {
+    DateTime,
+    String,
+    Number,
+    Lowercase,
+    Uppercase,
+}
+
+enum Node {
+    Path(Vec<Step>),
+    Const(Val<'static>),
+    Arr(Vec<Node>),
+    Coerce(Box<Node>, Vec<Coerce>),
+    Not(Box<Node>),
+    Op(Op, Box<Node>, Box<Node>),
+    Between(Box<Node>, Box<Node>, Box<Node>),
+    Exists(Vec<Step>),
+    In(Box<Node>, Box<Node>),
+    Matches(Box<Node>, Regex),
+    Lower(Box<Node>),
+    Upper(Box<Node>),
+    Date(Box<Node>),
+    Now,
+}
+
+/// a value while evaluating. strings and objects are borrowed from the document
+#[derive(Clone, Debug)]
+enum Val<'a> {
+    Null,
+    Bool(bool),
+    Num(f64),
+    Str(Cow<'a, str>),
+    Date(DateTime<Utc>),
+    Arr(Vec<Val<'a>>),
+    Obj(&'a Map<String, Value>),
+}
+
+impl<'a> Val<'a> {
+    fn from_json(v: &'a Value) -> Self {
+        match v {
+            Value::Null => Val::Null,
+            Value::Bool(b) => Val::Bool(*b),
+            Value::Number(n) => Val::Num(n.as_f64().unwrap_or(f64::NAN)),
+            Value::String(s) => Val::Str(Cow::Borrowed(s.as_str())),
+            Value::Array(arr) => Val::Arr(arr.iter().map(Val::from_json).collect()),
+            Value::Object(obj) => Val::Obj(obj),
+        }
+    }
+
+    /// the value borrowing its strings
+    fn shallow(&self) -> Val<'_> {
+        match self {
+            Val::Str(s) => Val::Str(Cow::Borrowed(s.as_ref())),
+            Val::Arr(arr) => Val::Arr(arr.iter().map(|v| v.shallow()).collect()),
+            Val::Null => Val::Null,
+            Val::Bool(b) => Val::Bool(*b),
+            Val::Num(n) => Val::Num(*n),
+            Val::Date(dt) => Val::Date(*dt),
+            Val::Obj(obj) => Val::Obj(obj),
+        }
+    }
+}
+
+impl PartialEq for Val<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Val::Null, Val::Null) => true,
+            (Val::Bool(a), Val::Bool(b)) => a == b,
+            (Val::Num(a), Val::Num(b)) => a == b,
+            (Val::Str(a), Val::Str(b)) => a == b,
+            (Val::Date(a), Val::Date(b)) => a == b,
+            (Val::Arr(a), Val::Arr(b)) => a == b,
+            (Val::Obj(a), Val::Obj(b)) => {
+                a.len() == b.len() && a.iter().all(|(k, v)| {
+                    b.get(k).is_some_and(|w| Val::from_json(v) == Val::from_json(w))
+                })
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Node {
+    fn compile(e: &Expr) -> Result<Node, Unsupported> {
+        let boxed = |e: &Expr| Node::compile(e).map(Box::new);
+        Ok(match e {
+            Expr::Path(path) => Node::Path(steps(path)?),
+            Expr::Str(s) => Node::Const(Val::Str(Cow::Owned(s.to_string()))),
+            Expr::Num(n) => Node::Const(Val::Num(*n)),
+            Expr::Bool(b) => Node::Const(Val::Bool(*b)),
+            Expr::Null => Node::Const(Val::Null),
+            Expr::Arr(arr) => Node::Arr(arr.iter().map(Node::compile).collect::<Result<Vec<Node>, Unsupported>>()?),
+            Expr::Coerce(value, idents) => {
+                let coerce = idents.iter().map(|ident| match ident.as_str() {
+                    "_datetime_" => Coerce::DateTime,
+                    "_string_" => Coerce::String,
+                    "_number_" => Coerce::Number,
+                    "_lowercase_" => Coerce::Lowercase,
+                    _ => Coerce::Uppercase,
+                }).collect();
+                Node::Coerce(boxed(value)?, coerce)
+            }
+            Expr::Not(value) => Node::Not(boxed(value)?),
+            Expr::Op(op, left, right) => Node::Op(*op, boxed(left)?, boxed(right)?),
+            Expr::Between(value, lower, upper) => Node::Between(boxed(value)?, boxed(lower)?, boxed(upper)?),
+            Expr::Call(name, args) => {
+                let arity = |n: usize| match args.len() == n {
+                    true => Ok(()),
+                    false => Err(Unsupported::Call(format!("{} takes {} arguments, found {}", name, n, args.len()))),
+                };
+                match name.as_str() {
+                    "exists" => {
+                        arity(1)?;
+                        match &args[0] {
+                            Expr::Path(path) => Node::Exists(steps(path)?),
+                            _ => return Err(Unsupported::Call("exists takes a selector".to_string())),
+                        }
+                    }
+                    "in" => {
+                        arity(2)?;
+                        Node::In(boxed(&args[0])?, boxed(&args[1])?)
+                    }
+                    "matches" => {
+                        arity(2)?;
+                        match &args[1] {
+                            Expr::Str(pattern) => {
+                                let regex = Regex::new(pattern).map_err(|e| Unsupported::Call(e.to_string()))?;
+                                Node::Matches(boxed(&args[0])?, regex)
+                            }
+                            _ => return Err(Unsupported::Call("matches takes a quoted pattern".to_string())),
+                        }
+                    }
+                    "lower" => {
+                        arity(1)?;
+                        Node::Lower(boxed(&args[0])?)
+                    }
+                    "upper" => {
+                        arity(1)?;
+                        Node::Upper(boxed(&args[0])?)
+                    }
+                    "date" => {
+                        arity(1)?;
+                        Node::Date(boxed(&args[0])?)
+                    }
+                    _ => {
+                        arity(0)?;
+                        Node::Now
+                    }
+                }
+            }
+        })
+    }
+
+    /// `None` is an evaluation error, e.g. `"a" > 1`
+    fn eval<'a>(&'a self, doc: &'a Value) -> Option<Val<'a>> {
+        Some(match self {
+            Node::Path(steps) => lookup(doc, steps).unwrap_or(Val::Null),
+            Node::Const(v) => v.shallow(),
+            Node::Arr(arr) => Val::Arr(arr.iter().map(|n| n.eval(doc)).collect::<Option<Vec<Val>>>()?),
+            Node::Coerce(value, coerce) => {
+                let mut v = value.eval(doc)?;
+                for c in coerce {
+                    v = coerced(v, c)?;
+                }
+                v
+            }
+            Node::Not(value) => match value.eval(doc)? {
+                Val::Bool(b) => Val::Bool(!b),
+                _ => return None,
+            },
+            Node::Op(op, left, right) => operation(*op, left.eval(doc)?, right.eval(doc)?)?,
+            Node::Between(value, lower, upper) => {
+                let (lower, upper, value) = (lower.eval(doc)?, upper.eval(doc)?, value.eval(doc)?);
+                match (value, lower, upper) {
+                    (Val::Str(v), Val::Str(l), Val::Str(u)) => Val::Bool(v > l && v < u),
+                    (Val::Num(v), Val::Num(l), Val::Num(u)) => Val::Bool(v > l && v < u),
+                    (Val::Date(v), Val::Date(l), Val::Date(u)) => Val::Bool(v > l && v < u),
+                    (Val::Null, _, _) | (_, Val::Null, _) | (_, _, Val::Null) => Val::Bool(false),
+                    _ => return None,
+                }
+            }
+            Node::Exists(steps) => Val::Bool(lookup(doc, steps).is_some()),
+            Node::In(value, list) => match (value.eval(doc)?, list.eval(doc)?) {
+                (v, Val::Arr(list)) => Val::Bool(
+                    list.contains(&v) || matches!(&v, Val::Arr(values) if values.iter().any(|v| list.contains(v)))
+                ),
+                (_, Val::Null) => Val::Bool(false),
+                _ => return None,
+            },
+            Node::Matches(value, regex) => match value.eval(doc)? {
+                Val::Str(s) => Val::Bool(regex.is_match(&s)),
+                Val::Arr(arr) => Val::Bool(arr.iter().any(|v| matches!(v, Val::Str(s) if regex.is_match(s)))),
+                Val::Null => Val::Bool(false),
+                _ => return None,
+            },
+            Node::Lower(value) => match value.eval(doc)? {
+                Val::Str(s) => Val::Str(Cow::Owned(s.to_lowercase())),
+                Val::Null => Val::Null,
+                _ => return None,
+            },
+            Node::Upper(value) => match value.eval(doc)? {
+                Val::Str(s) => Val::Str(Cow::Owned(s.to_uppercase())),
+                Val::Null => Val::Null,
+                _ => return None,
+            },
+            Node::Date(value) => match value.eval(doc)? {
+                Val::Str(s) => anydate::parse_utc(&s).map_or(Val::Null, Val::Date),
+                Val::Num(n) => {
+                    let nanos = (n.fract() * 1e9).round() as i64;
+                    Utc.timestamp_opt(n.trunc() as i64, 0).single().map_or(Val::Null, |dt| Val::Date(dt + chrono::Duration::nanoseconds(nanos)))
+                }
+                Val::Date(dt) => Val::Date(dt),
+                Val::Null => Val::Null,
+                _ => return None,
+            },
+            Node::Now => Val::Date(Utc::now()),
+        })
+    }
+}
+
+/// binary operations, typed as `flql` types them
+fn operation<'a>(op: Op, left: Val<'a>, right: Val<'a>) -> Option<Val<'a>> {
+    Some(match (op, left, right) {
+        (Op::Add, Val::Str(a), Val::Str(b)) => Val::Str(Cow::Owned(format!("{}{}", a, b))),
+        (Op::Add, Val::Str(s), Val::Null) | (Op::Add, Val::Null, Val::Str(s)) => Val::Str(s),
+        (Op::Add, Val::Num(a), Val::Num(b)) => Val::Num(a + b),
+        (Op::Add, Val::Num(n), Val::Null) | (Op::Add, Val::Null, Val::Num(n)) => Val::Num(n),
+        (Op::Sub, Val::Num(a), Val::Num(b)) => Val::Num(a - b),
+        (Op::Mul, Val::Num(a), Val::Num(b)) => Val::Num(a * b),
+        (Op::Div, Val::Num(a), Val::Num(b)) => Val::Num(a / b),
+        (Op::Eq, a, b) => Val::Bool(a == b),
+        (Op::Gt | Op::Gte | Op::Lt | Op::Lte, a, b) => {
+            let ordering = match (a, b) {
+                (Val::Str(a), Val::Str(b)) => a.partial_cmp(&b),
+                (Val::Num(a), Val::Num(b)) => a.partial_cmp(&b),
+                (Val::Date(a), Val::Date(b)) => a.partial_cmp(&b),
+                _ => return None,
+            };
+            Val::Bool(match (op, ordering) {
+                (_, None) => false,
+                (Op::Gt, Some(o)) => o.is_gt(),
+                (Op::Gte, Some(o)) => o.is_ge(),
+                (Op::Lt, Some(o)) => o.is_lt(),
+                (_, Some(o)) => o.is_le(),
+            })
+        }
+        (Op::Or, Val::Bool(a), Val::Bool(b)) => Val::Bool(a || b),
+        (Op::And, Val::Bool(a), Val::Bool(b)) => Val::Bool(a && b),
+        (Op::StartsWith, Val::Str(a), Val::Str(b)) => Val::Bool(a.starts_with(b.as_ref())),
+        (Op::EndsWith, Val::Str(a), Val::Str(b)) => Val::Bool(a.ends_with(b.as_ref())),
+        (Op::In, v, Val::Arr(arr)) => Val::Bool(arr.contains(&v)),
+        (Op::Contains, Val::Str(a), Val::Str(b)) => Val::Bool(a.contains(b.as_ref())),
+        (Op::Contains, Val::Arr(arr), v) => Val::Bool(arr.contains(&v)),
+        (Op::ContainsAny, Val::Str(a), Val::Str(b)) => Val::Bool(b.chars().any(|c| a.contains(c))),
+        (Op::ContainsAny, Val::Arr(a), Val::Arr(b)) => Val::Bool(b.iter().any(|v| a.contains(v))),
+        (Op::ContainsAny, Val::Arr(a), Val::Str(s)) => Val::Bool(s.chars().any(|c| a.contains(&Val::Str(Cow::Owned(c.to_string()))))),
+        (Op::ContainsAny, Val::Str(s), Val::Arr(a)) => Val::Bool(a.iter().any(|v| matches!(v, Val::Str(t) if s.contains(t.as_ref())))),
+        (Op::ContainsAll, Val::Str(a), Val::Str(b)) => Val::Bool(b.chars().all(|c| a.contains(c))),
+        (Op::ContainsAll, Val::Arr(a), Val::Arr(b)) => Val::Bool(b.iter().all(|v| a.contains(v))),
+        (Op::ContainsAll, Val::Arr(a), Val::Str(s)) => Val::Bool(s.chars().all(|c| a.contains(&Val::Str(Cow::Owned(c.to_string()))))),
+        (Op::ContainsAll, Val::Str(s), Val::Arr(a)) => Val::Bool(a.iter().all(|v| matches!(v, Val::Str(t) if s.contains(t.as_ref())))),
+        _ => return None,
+    })
+}
+
+fn coerced<'a>(v: Val<'a>, coerce: &Coerce) -> Option<Val<'a>> {
+    Some(match (coerce, v) {
+        (Coerce::DateTime, Val::Str(s)) => anydate::parse_utc(&s).map_or(Val::Null, Val::Date),
+        (Coerce::DateTime, Val::Null) => Val::Null,
+        (Coerce::String, Val::Null) => Val::Str(Cow::Borrowed("null")),
+        (Coerce::String, Val::Str(s)) => Val::Str(s),
+        (Coerce::String, Val::Num(n)) => Val::Str(Cow::Owned(n.to_string())),
+        (Coerce::String, Val::Bool(b)) => Val::Str(Cow::Owned(b.to_string())),
+        (Coerce::String, Val::Date(dt)) => Val::Str(Cow::Owned(dt.to_rfc3339_opts(SecondsFormat::AutoSi, true))),
+        (Coerce::Number, Val::Str(s)) => Val::Num(s.parse::<f64>().ok()?),
+        (Coerce::Number, Val::Num(n)) => Val::Num(n),
+        (Coerce::Number, Val::Bool(b)) => Val::Num(if b { 1.0 } else { 0.0 }),
+        (Coerce::Number, Val::Date(dt)) => Val::Num(dt.timestamp() as f64 * 1e9 + dt.timestamp_subsec_nanos() as f64),
+        (Coerce::Lowercase, Val::Str(s)) => Val::Str(Cow::Owned(s.to_lowercase())),
+        (Coerce::Uppercase, Val::Str(s)) => Val::Str(Cow::Owned(s.to_uppercase())),
+        _ => return None,
+    })
+}
+
+/// value at the path. `None` when the path is not in the document
+fn lookup<'a>(doc: &'a Value, steps: &[Step]) -> Option<Val<'a>> {
+    let mut current = doc;
+    for (i, step) in steps.iter().enumerate() {
+        current = match (step, current) {
+            (Step::Key(key), Value::Object(obj)) => obj.get(key)?,
+            (Step::Key(key), Value::Array(arr)) => arr.get(key.parse::<usize>().ok()?)?,
+            (Step::Each, Value::Array(arr)) if i + 1 == steps.len() => return Some(Val::Num(arr.len() as f64)),
+            (Step::Each, Value::Array(arr)) => {
+                return Some(Val::Arr(arr.iter().filter_map(|v| lookup(v, &steps[i + 1..])).collect()));
+            }
+            _ => return None,
+        };
+    }
+    Some(Val::from_json(current))
+}
+
+/// steps of a `gjson` path. wildcards, queries and modifiers are unsupported
+fn steps(path: &str) -> Result<Vec<Step>, Unsupported> {
+    let mut steps = vec![];
+    let mut key = String::new();
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => key.extend(chars.next()),
+            '.' => steps.push(step(std::mem::take(&mut key))),
+            '*' | '?' | '|' | '@' | '[' | '{' | '(' | '!' | '=' | '<' | '>' | '%' => {
+                return Err(Unsupported::Selector(path.to_string()));
+            }
+            _ => key.push(c),
+        }
+    }
+    steps.push(step(key));
+    Ok(steps)
+}
+
+fn step(key: String) -> Step {
+    if key == "#" { Step::Each } else { Step::Key(key) }
+}
+
+fn has_calls(e: &Expr) -> bool {
+    match e {
+        Expr::Call(_, _) => true,
+        Expr::Arr(arr) => arr.iter().any(has_calls),
+        Expr::Coerce(v, _) | Expr::Not(v) => has_calls(v),
+        Expr::Op(_, l, r) => has_calls(l) || has_calls(r),
+        Expr::Between(v, l, u) => has_calls(v) || has_calls(l) || has_calls(u),
+        _ => false,
+    }
+}
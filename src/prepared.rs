@@ -0,0 +1,233 @@
+use flql::Flql;
+use regex::{Captures, Regex};
+use serde_json::Value;
+
+use crate::headers::{ClipSet, ClipSets};
+use crate::statement::{self, Statement};
+
+/// marks a placeholder in a parsed statement
+const MARK: char = '\u{1}';
+
+lazy_static::lazy_static! {
+    /// a placeholder standing for a whole document, one standing for a json string, and a bare one
+    static ref PLACEHOLDER: Regex = Regex::new("\\{\"\u{1}(\\d+)\u{1}\":0\\}|\"\u{1}(\\d+)\u{1}\"|\u{1}(\\d+)\u{1}").unwrap();
+}
+
+/// `Prepared` is a statement with `$1`, `$2` placeholders, parsed once and bound to values on every run.
+/// values are bound into the parsed statement, so they are never read as statement text.
+/// `$$` is a literal `$`, placeholders are not read inside json strings
+#[derive(Debug, Clone, PartialEq)]
+pub struct Prepared {
+    statement: Statement,
+    params: usize,
+}
+
+/// where a bound value goes
+#[derive(Clone, Copy)]
+enum Role {
+    /// collections, pointers, fields and other quoted names, as text
+    Name,
+    /// a literal of a condition
+    Condition,
+    /// json of a document
+    Document,
+}
+
+impl Prepared {
+    pub fn new(stmt: &str) -> Result<Self, String> {
+        let (text, params) = placeholders(stmt)?;
+        let statement = statement::parse(text.as_str())?;
+        // placeholders in arguments flinch reads while parsing are never bound
+        if format!("{:?}", bind(&statement, &|_, _, _| Ok(String::new()))?).contains("\\u{1}") {
+            return Err("placeholders are only bound in documents, conditions and quoted names".to_string());
+        }
+        Ok(Prepared { statement, params })
+    }
+
+    /// number of placeholders
+    pub fn params(&self) -> usize {
+        self.params
+    }
+
+    /// the statement with its placeholders replaced by `params`, `$1` being the first
+    pub fn bind(&self, params: &[Value]) -> Result<Statement, String> {
+        if params.len() != self.params {
+            return Err(format!("prepared statement expects {} parameters, got {}", self.params, params.len()));
+        }
+        bind(&self.statement, &|n, role, form| value(&params[n - 1], n, role, form))
+    }
+}
+
+/// the statement with a marked placeholder for every `$n`. placeholders outside quotes
+/// become quoted names, json strings inside documents, or documents when they are one
+fn placeholders(stmt: &str) -> Result<(String, usize), String> {
+    let chars = stmt.chars().collect::<Vec<char>>();
+    let mut text = String::new();
+    let mut params = 0;
+    let (mut quoted, mut string, mut escaped, mut depth) = (false, false, false, 0);
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+        if string {
+            string = escaped || c != '"';
+            escaped = !escaped && c == '\\';
+            text.push(c);
+            continue;
+        }
+        if c == '$' && chars.get(i) == Some(&'$') {
+            text.push('$');
+            i += 1;
+            continue;
+        }
+        if c != '$' || !chars.get(i).is_some_and(|d| d.is_ascii_digit()) {
+            match c {
+                '\'' => quoted = !quoted,
+                '"' if !quoted => string = true,
+                '{' if !quoted => depth += 1,
+                '}' if !quoted => depth -= 1,
+                _ => {}
+            }
+            text.push(c);
+            continue;
+        }
+        let digits = chars[i..].iter().take_while(|d| d.is_ascii_digit()).collect::<String>();
+        i += digits.len();
+        let n = digits.parse::<usize>().ok().filter(|n| *n > 0).ok_or_else(|| format!("invalid placeholder `${}`", digits))?;
+        params = params.max(n);
+        let mark = format!("{}{}{}", MARK, n, MARK);
+        let document = ["put(", "new(", "permit("].iter().any(|call| text.trim_end().ends_with(call));
+        match (quoted, depth) {
+            (true, _) => text.push_str(&mark),
+            (false, 0) if document => text.push_str(&format!("{{\"{}\":0}}", mark)),
+            (false, 0) => text.push_str(&format!("'{}'", mark)),
+            (false, _) => text.push_str(&format!("\"{}\"", mark)),
+        }
+    }
+    Ok((text, params))
+}
+
+/// what the `n`th placeholder of a role is bound to. `form` is 0 for a document, 1 for a json string
+type Binder<'a> = dyn Fn(usize, Role, u8) -> Result<String, String> + 'a;
+
+fn bind(statement: &Statement, binder: &Binder) -> Result<Statement, String> {
+    let name = |s: &String| fill(s, binder, Role::Name);
+    let condition = |s: &String| fill(s, binder, Role::Condition);
+    let document = |s: &String| fill(s, binder, Role::Document);
+    let option = |s: &Option<String>| s.as_ref().map(name).transpose();
+    Ok(match statement {
+        Statement::Flql(flql) => Statement::Flql(match flql {
+            Flql::DbNew(options) => Flql::DbNew(document(options)?),
+            Flql::DbPerm(permit) => Flql::DbPerm(document(permit)?),
+            Flql::DbDrop(db) => Flql::DbDrop(name(db)?),
+            Flql::New(options) => Flql::New(document(options)?),
+            Flql::Drop(c) => Flql::Drop(name(c)?),
+            Flql::DropUser(db, user) => Flql::DropUser(name(db)?, name(user)?),
+            Flql::Exists(p, c) => Flql::Exists(name(p)?, name(c)?),
+            Flql::Length(c) => Flql::Length(name(c)?),
+            Flql::Flush(c) => Flql::Flush(name(c)?),
+            Flql::Ttl(duration, cond, c) => Flql::Ttl(duration.to_string(), condition(cond)?, name(c)?),
+            Flql::Put(d, c) => Flql::Put(document(d)?, name(c)?),
+            Flql::PutWhen(d, cond, c) => Flql::PutWhen(document(d)?, condition(cond)?, name(c)?),
+            Flql::PutPointer(d, p, c) => Flql::PutPointer(document(d)?, name(p)?, name(c)?),
+            Flql::SearchTyping(q, c) => Flql::SearchTyping(name(q)?, name(c)?),
+            Flql::Get(c, sort, page) => Flql::Get(name(c)?, option(sort)?, option(page)?),
+            Flql::GetWhen(cond, c, sort, page) => Flql::GetWhen(condition(cond)?, name(c)?, option(sort)?, option(page)?),
+            Flql::GetPointer(p, c) => Flql::GetPointer(name(p)?, name(c)?),
+            Flql::GetView(v, c) => Flql::GetView(name(v)?, name(c)?),
+            Flql::GetClip(clip, c) => Flql::GetClip(name(clip)?, name(c)?),
+            Flql::GetIndex(i, c) => Flql::GetIndex(name(i)?, name(c)?),
+            Flql::GetRange(start, end, on, c) => Flql::GetRange(name(start)?, name(end)?, name(on)?, name(c)?),
+            Flql::Delete(c) => Flql::Delete(name(c)?),
+            Flql::DeleteWhen(cond, c) => Flql::DeleteWhen(condition(cond)?, name(c)?),
+            Flql::DeletePointer(p, c) => Flql::DeletePointer(name(p)?, name(c)?),
+            Flql::DeleteClip(clip, c) => Flql::DeleteClip(name(clip)?, name(c)?),
+            Flql::None => Flql::None,
+        }),
+        Statement::Contains(text, field, c) => Statement::Contains(name(text)?, option(field)?, name(c)?),
+        Statement::Near(field, point, radius, limit, c) => Statement::Near(name(field)?, *point, *radius, *limit, name(c)?),
+        Statement::WithinBox(field, sw, ne, limit, c) => Statement::WithinBox(name(field)?, *sw, *ne, *limit, name(c)?),
+        Statement::Knn(field, vector, k, cond, c) => {
+            Statement::Knn(name(field)?, vector.clone(), *k, cond.as_ref().map(condition).transpose()?, name(c)?)
+        }
+        Statement::Project(inner, projection) => Statement::Project(Box::new(bind(inner, binder)?), projection.clone()),
+        Statement::Lookup(inner, lookups) => Statement::Lookup(Box::new(bind(inner, binder)?), lookups.clone()),
+        Statement::Aggregate(aggregation, cond, c) => {
+            Statement::Aggregate(aggregation.clone(), cond.as_ref().map(condition).transpose()?, name(c)?)
+        }
+        Statement::Clips(sets, c, sort, page) => {
+            let named = |sets: &[ClipSet]| sets.iter().map(|set| match set {
+                ClipSet::Clip(clip) => name(clip).map(ClipSet::Clip),
+                ClipSet::View(view) => name(view).map(ClipSet::View),
+            }).collect::<Result<Vec<ClipSet>, String>>();
+            let sets = ClipSets { all: named(&sets.all)?, any: named(&sets.any)?, none: named(&sets.none)? };
+            Statement::Clips(sets, name(c)?, option(sort)?, option(page)?)
+        }
+        Statement::Views(c) => Statement::Views(name(c)?),
+        Statement::RebuildViews(c) => Statement::RebuildViews(name(c)?),
+        Statement::DropView(v, c) => Statement::DropView(name(v)?, name(c)?),
+        Statement::DeleteView(v, c) => Statement::DeleteView(name(v)?, name(c)?),
+        Statement::PutIndex(d, i, c) => Statement::PutIndex(document(d)?, name(i)?, name(c)?),
+        Statement::DeleteIndex(i, c) => Statement::DeleteIndex(name(i)?, name(c)?),
+        Statement::ExistsIndex(i, c) => Statement::ExistsIndex(name(i)?, name(c)?),
+        Statement::TtlIndex(duration, i, c) => Statement::TtlIndex(duration.to_string(), name(i)?, name(c)?),
+        Statement::Count(cond, c) => Statement::Count(cond.as_ref().map(condition).transpose()?, name(c)?),
+        Statement::ExistsWhen(cond, c) => Statement::ExistsWhen(condition(cond)?, name(c)?),
+        Statement::Distinct(field, cond, c) => Statement::Distinct(name(field)?, cond.as_ref().map(condition).transpose()?, name(c)?),
+        Statement::Facets(fields, cond, c, limit) => {
+            let fields = fields.iter().map(name).collect::<Result<Vec<String>, String>>()?;
+            Statement::Facets(fields, cond.as_ref().map(condition).transpose()?, name(c)?, *limit)
+        }
+        Statement::OnConflict(inner, conflict) => Statement::OnConflict(Box::new(bind(inner, binder)?), *conflict),
+        Statement::Explain(_) | Statement::Profile(_) => statement.clone(),
+    })
+}
+
+/// replaces the placeholders of a part of a statement in a single pass, so bound values are never bound again
+fn fill(text: &str, binder: &Binder, role: Role) -> Result<String, String> {
+    let mut error = None;
+    let filled = PLACEHOLDER.replace_all(text, |caps: &Captures| {
+        let (n, form) = match (caps.get(1), caps.get(2), caps.get(3)) {
+            (Some(n), _, _) => (n, 0),
+            (_, Some(n), _) => (n, 1),
+            (_, _, n) => (n.unwrap(), 2),
+        };
+        binder(n.as_str().parse::<usize>().unwrap_or_default(), role, form).unwrap_or_else(|e| {
+            error.get_or_insert(e);
+            String::new()
+        })
+    });
+    match error {
+        Some(error) => Err(error),
+        None => Ok(filled.to_string()),
+    }
+}
+
+/// the `n`th parameter written for its role
+fn value(value: &Value, n: usize, role: Role, form: u8) -> Result<String, String> {
+    match (role, form) {
+        (Role::Document, 0) if !value.is_object() => Err(format!("parameter ${} must be a document", n)),
+        (Role::Document, 0 | 1) => Ok(value.to_string()),
+        (Role::Document, _) | (Role::Name, _) => text_of(value).ok_or_else(|| format!("parameter ${} must be a string, number or boolean", n)),
+        (Role::Condition, _) => literal(value).ok_or_else(|| format!("parameter ${} cannot be written in a condition", n)),
+    }
+}
+
+fn text_of(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.to_string()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// a condition literal of a value. strings are quoted, their quotes and backslashes escaped
+fn literal(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))),
+        Value::Object(_) => None,
+        Value::Array(arr) => Some(format!("[{}]", arr.iter().map(literal).collect::<Option<Vec<String>>>()?.join(", "))),
+        _ => Some(value.to_string()),
+    }
+}
@@ -0,0 +1,59 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+/// how a statement reaches its documents
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Access {
+    /// reads no documents
+    #[default]
+    None,
+    /// every document of the collection
+    Scan,
+    /// the index candidates of a condition
+    Planned,
+    Pointer,
+    HashIndex,
+    Clip,
+    View,
+    Range,
+    Search,
+    Geo,
+    Vector,
+}
+
+/// time spent in a stage of a statement
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Stage {
+    pub name: String,
+    pub micros: u64,
+}
+
+/// `Profile` of a statement. `explain` plans it, `profile` runs it as well.
+/// `candidates` are the documents the access path reads, `results` the documents it returns
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Profile {
+    pub statement: String,
+    pub collection: Option<String>,
+    pub access: Access,
+    pub candidates: Option<usize>,
+    pub results: Option<usize>,
+    /// stages in the order they ran. `filter` is the time spent on the condition
+    /// summed over every thread of the `scan`
+    pub stages: Vec<Stage>,
+    /// times the condition of every document, a clock read per document
+    #[serde(skip)]
+    pub(crate) detailed: bool,
+}
+
+impl Profile {
+    /// records the time since `start` as a stage and restarts the clock
+    pub(crate) fn stage(&mut self, name: &str, start: &mut Instant) {
+        self.add(name, start.elapsed());
+        *start = Instant::now();
+    }
+
+    pub(crate) fn add(&mut self, name: &str, duration: Duration) {
+        self.stages.push(Stage { name: name.to_string(), micros: duration.as_micros() as u64 });
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// `Projection` picks the fields of the documents a query returns.
+/// `include` keeps only its paths, `exclude` drops paths afterwards.
+/// an empty projection keeps the whole document
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Projection {
+    pub include: Vec<Selection>,
+    pub exclude: Vec<String>,
+}
+
+/// `Selection` is a dotted `path` of a document, like `address.city`.
+/// an `alias` returns the value as a top level field with that name
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Selection {
+    pub path: String,
+    pub alias: Option<String>,
+}
+
+/// paths sharing their first steps. `all` keeps the whole value
+#[derive(Default)]
+struct Tree<'a> {
+    all: bool,
+    children: HashMap<&'a str, Tree<'a>>,
+}
+
+impl Projection {
+    /// parses `path` or `path AS alias` selections and excluded paths
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self, String> {
+        let mut projection = Projection::default();
+        for selection in include {
+            let words = selection.split_whitespace().collect::<Vec<&str>>();
+            let (path, alias) = match words.as_slice() {
+                [path] => (path.to_string(), None),
+                [path, as_, alias] if as_.eq_ignore_ascii_case("as") => (path.to_string(), Some(alias.to_string())),
+                _ => return Err(format!("invalid selection `{}`. expected `path` or `path AS alias`", selection)),
+            };
+            valid(&path)?;
+            projection.include.push(Selection { path, alias });
+        }
+        for path in exclude {
+            valid(path.trim())?;
+            projection.exclude.push(path.trim().to_string());
+        }
+        Ok(projection)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// the projected document. only the picked values are cloned
+    pub fn apply(&self, doc: &Value) -> Value {
+        let excluded = Tree::of(self.exclude.iter().map(|p| p.as_str()));
+        if self.include.is_empty() {
+            return without(doc, &excluded).unwrap_or_else(|| Value::Object(Map::new()));
+        }
+        let included = Tree::of(self.include.iter().filter(|s| s.alias.is_none()).map(|s| s.path.as_str()));
+        let mut obj = match select(doc, &included) {
+            Some(Value::Object(obj)) => obj,
+            _ => Map::new(),
+        };
+        for selection in self.include.iter() {
+            if let Some(alias) = &selection.alias {
+                if let Some(value) = pick(doc, &selection.path.split('.').collect::<Vec<&str>>()) {
+                    obj.insert(alias.to_string(), value);
+                }
+            }
+        }
+        let doc = Value::Object(obj);
+        if self.exclude.is_empty() {
+            return doc;
+        }
+        without(&doc, &excluded).unwrap_or_else(|| Value::Object(Map::new()))
+    }
+
+    /// the projected document with its `_pointer`, like `Document::make`
+    pub fn make(&self, key: String, doc: &Value) -> Value {
+        let mut doc = self.apply(doc);
+        if let Some(obj) = doc.as_object_mut() {
+            obj.insert("_pointer".to_owned(), Value::String(key));
+        }
+        doc
+    }
+}
+
+impl<'a> Tree<'a> {
+    fn of(paths: impl Iterator<Item=&'a str>) -> Self {
+        let mut root = Tree::default();
+        for path in paths {
+            let mut node = &mut root;
+            for step in path.split('.') {
+                node = node.children.entry(step).or_default();
+            }
+            node.all = true;
+        }
+        root
+    }
+}
+
+fn valid(path: &str) -> Result<(), String> {
+    if path.is_empty() || path.split('.').any(|step| step.is_empty()) {
+        return Err(format!("invalid field path `{}`", path));
+    }
+    Ok(())
+}
+
+/// the values of `tree` kept in their place. array elements are selected one by one,
+/// or by position when the step is an index. `None` when nothing is selected
+fn select(value: &Value, tree: &Tree) -> Option<Value> {
+    if tree.all {
+        return Some(value.clone());
+    }
+    match value {
+        Value::Object(obj) => {
+            let res = obj
+                .iter()
+                .filter_map(|(k, v)| tree.children.get(k.as_str()).and_then(|child| select(v, child)).map(|v| (k.to_string(), v)))
+                .collect::<Map<String, Value>>();
+            (!res.is_empty()).then_some(Value::Object(res))
+        }
+        Value::Array(arr) => {
+            let res = arr
+                .iter()
+                .enumerate()
+                .filter_map(|(i, v)| match tree.children.get(i.to_string().as_str()) {
+                    Some(child) => select(v, child),
+                    None => select(v, tree),
+                })
+                .collect::<Vec<Value>>();
+            (!res.is_empty()).then_some(Value::Array(res))
+        }
+        _ => None,
+    }
+}
+
+/// the value without the paths of `tree`. `None` when it is dropped
+fn without(value: &Value, tree: &Tree) -> Option<Value> {
+    if tree.all {
+        return None;
+    }
+    if tree.children.is_empty() {
+        return Some(value.clone());
+    }
+    match value {
+        Value::Object(obj) => Some(Value::Object(obj
+            .iter()
+            .filter_map(|(k, v)| match tree.children.get(k.as_str()) {
+                Some(child) => without(v, child).map(|v| (k.to_string(), v)),
+                None => Some((k.to_string(), v.clone())),
+            })
+            .collect())),
+        Value::Array(arr) => Some(Value::Array(arr
+            .iter()
+            .enumerate()
+            .filter_map(|(i, v)| match tree.children.get(i.to_string().as_str()) {
+                Some(child) => without(v, child),
+                None => without(v, tree),
+            })
+            .collect())),
+        _ => Some(value.clone()),
+    }
+}
+
+/// the value at `steps`. a step that is not an index maps over arrays
+fn pick(value: &Value, steps: &[&str]) -> Option<Value> {
+    let Some((step, rest)) = steps.split_first() else {
+        return Some(value.clone());
+    };
+    match value {
+        Value::Object(obj) => obj.get(*step).and_then(|v| pick(v, rest)),
+        Value::Array(arr) => match step.parse::<usize>() {
+            Ok(i) => arr.get(i).and_then(|v| pick(v, rest)),
+            Err(_) => Some(Value::Array(arr.iter().filter_map(|v| pick(v, steps)).collect())),
+        },
+        _ => None,
+    }
+}
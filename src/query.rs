@@ -1,44 +1,75 @@
-use std::cmp::Ordering;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use crossbeam_queue::SegQueue;
 use flql::Flql;
-use futures::executor::block_on;
 use log::{debug, trace};
 use rayon::prelude::*;
 use serde_json::{Number, Value};
 use tokio::sync::mpsc::Sender;
 
-use crate::database::{CollectionOptions, Database};
+use crate::aggregate::Aggregation;
+use crate::cursor::{Cursor, Page};
+use crate::collection::Collection;
+use crate::database::{CollectionOptions, Conflict, Database};
 use crate::doc::QueryBased;
-use crate::doc_trait::Document;
+use crate::doc_trait::{Document, Point};
 use crate::errors::{CollectionError, DocumentError, IndexError, QueryError};
-use crate::headers::{FlinchError, PubSubEvent, QueryResult, Sort, SortDirection};
-use crate::utils::{ExecTime, parse_limit, parse_sort, trim_apos};
+use crate::guard::{self, Cancel, Guard, Limits};
+use crate::headers::{ClipSet, ClipSets, Conflicted, FlinchError, Plan, PubSubEvent, QueryResult, Sort};
+use crate::journal::{self, Journal};
+use crate::lookup::Lookup;
+use crate::predicate::Predicate;
+use crate::prepared::Prepared;
+use crate::profile::{Access, Profile};
+use crate::projection::Projection;
+use crate::sort;
+use crate::statement::{self, Statement};
+use crate::utils::{ExecTime, parse_page, parse_sort, trim_apos};
+
+/// views as `{"view": name, "size": documents}`
+fn sized(views: Vec<(String, usize)>) -> Vec<Value> {
+    views.into_iter().map(|(view, size)| serde_json::json!({"view": view, "size": size})).collect()
+}
+
+/// fields a query adds to its results, kept by projections of lookups
+const META: [&str; 3] = ["_pointer", "_score", "_distance"];
 
 /// creates a `Query` session for
 /// executing `flql`
 pub struct Query {
     db: Database<QueryBased>,
     current: String,
+    limits: Limits,
 }
 
 impl Query {
     /// creates an instance of `Flinch` database
     pub async fn new() -> Self {
         let db = Database::<QueryBased>::init().await;
-        Self { db, current: "".to_string() }
+        Self { db, current: "".to_string(), limits: Limits::default() }
     }
 
     /// creates an instance of `Flinch` database with name
     pub async fn new_with_name(name: &str) -> Self {
         let db = Database::<QueryBased>::init_with_name(name).await;
-        Self { db, current: "".to_string() }
+        Self { db, current: "".to_string(), limits: Limits::default() }
     }
 
     pub fn underlying_db(&self) -> &Database<QueryBased> {
         &self.db
     }
 
+    /// limits every statement runs with, unless it is given a `Guard` of its own
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
     /// `pubsub` for new documents or remove document event
     pub async fn subscribe(&self, name: &str, sx: Sender<PubSubEvent<String, QueryBased>>) -> Result<(), FlinchError> {
         let col = self.db.using(name);
@@ -55,21 +86,197 @@ impl Query {
 
         self.current = format!("{}", &stmt);
 
-        let parsed = flql::parse(stmt);
+        let parsed = statement::parse(stmt);
         if parsed.is_err() {
             return QueryResult {
                 data: vec![],
                 error: FlinchError::CustomError(parsed.err().unwrap()),
                 time_taken: "".to_string(),
+                ..Default::default()
             };
         }
 
         let parsed = parsed.unwrap();
-        self.exec_with_flql(parsed).await
+        self.exec_with_statement(parsed).await
+    }
+
+    /// parses a statement with `$1`, `$2` placeholders once. run it with `execute`
+    pub fn prepare(&self, stmt: &str) -> Result<Prepared, FlinchError> {
+        Prepared::new(stmt).map_err(FlinchError::CustomError)
+    }
+
+    /// runs a prepared statement with its placeholders bound to `params`
+    pub async fn execute(&self, prepared: &Prepared, params: &[Value]) -> QueryResult {
+        match prepared.bind(params) {
+            Ok(parsed) => self.exec_with_statement(parsed).await,
+            Err(err) => QueryResult {
+                data: vec![],
+                error: FlinchError::CustomError(err),
+                time_taken: "".to_string(),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// runs the `;` separated statements of a script in order, a result for every statement.
+    /// an `atomic` script stops at its first failed statement and undoes the writes of the statements before it
+    pub async fn script(&self, script: &str, atomic: bool) -> Vec<QueryResult> {
+        let statements = statement::split(script).iter()
+            .map(|stmt| statement::parse(stmt).map_err(FlinchError::CustomError))
+            .collect::<Vec<Result<Statement, FlinchError>>>();
+        self.exec_script(statements, atomic).await
+    }
+
+    /// expect the parsed statements of a script. a statement that did not parse fails with its error.
+    /// an `atomic` script holds the write locks of the collections it writes to until it is done
+    pub async fn exec_script(&self, statements: Vec<Result<Statement, FlinchError>>, atomic: bool) -> Vec<QueryResult> {
+        if !atomic {
+            return self.run_script(statements, None).await;
+        }
+        let journal = Journal::new();
+        Arc::clone(&journal).scope(self.run_script(statements, Some(&journal))).await
+    }
+
+    /// runs the statements in order. with a `journal`, the first failed statement stops the script
+    /// and the writes of the statements before it are undone
+    async fn run_script(&self, statements: Vec<Result<Statement, FlinchError>>, journal: Option<&Journal>) -> Vec<QueryResult> {
+        if let Some(journal) = journal {
+            journal.lock(&self.db, &statements).await;
+        }
+        let mut results = vec![];
+        let mut failed = false;
+        for parsed in statements {
+            let res = match parsed {
+                _ if failed && journal.is_some() => QueryResult {
+                    data: vec![],
+                    error: FlinchError::CustomError("not executed, the script failed before".to_string()),
+                    time_taken: "".to_string(),
+                    ..Default::default()
+                },
+                Ok(parsed) => self.exec_with_statement(parsed).await,
+                Err(error) => QueryResult {
+                    data: vec![],
+                    error,
+                    time_taken: "".to_string(),
+                    ..Default::default()
+                },
+            };
+            failed = failed || res.error.ne(&FlinchError::None);
+            results.push(res);
+        }
+        if let Some(journal) = journal.filter(|_| failed) {
+            debug!("rolling back script of {} statements", results.len());
+            journal.rollback(&self.db).await;
+        }
+        results
+    }
+
+    /// expect a parsed `Statement`. it runs with the limits of the query
+    pub async fn exec_with_statement(&self, parsed: Statement) -> QueryResult {
+        if guard::guarded() {
+            return self.run(parsed).await;
+        }
+        self.exec_guarded(parsed, Guard::new(self.limits, Cancel::new())).await
+    }
+
+    /// runs a parsed `Statement` until its `guard` is cancelled or past its deadline.
+    /// a statement stopped or over the result limits of the guard fails. a read returns nothing,
+    /// a write returns what it wrote before it stopped and is not held to the result limits
+    pub async fn exec_guarded(&self, parsed: Statement, guard: Guard) -> QueryResult {
+        let writes = journal::writes(&parsed).is_some();
+        let mut res = match guard.check() {
+            Ok(_) => guard::scope(guard.clone(), Box::pin(self.run(parsed))).await,
+            Err(_) => QueryResult {
+                data: vec![],
+                error: FlinchError::None,
+                time_taken: "".to_string(),
+                ..Default::default()
+            },
+        };
+        let exceeded = || if writes || guard.measured() { None } else { guard.size(&res.data).err() };
+        if let Some(err) = guard.stopped().or_else(exceeded) {
+            trace!("statement stopped {}", &err);
+            res.error = FlinchError::QueryError(err);
+            if !writes {
+                res.data = vec![];
+                res.total = None;
+                res.cursor = None;
+            }
+        }
+        res
+    }
+
+    async fn run(&self, parsed: Statement) -> QueryResult {
+        match parsed {
+            Statement::Flql(flql) => self.run_flql(flql).await,
+            Statement::Contains(text, field, collection) => self.search_contains(text, field, collection),
+            Statement::Near(field, point, radius, limit, collection) => self.get_near(field, point, radius, limit, collection),
+            Statement::WithinBox(field, sw, ne, limit, collection) => self.get_within_box(field, sw, ne, limit, collection),
+            Statement::Knn(field, vector, k, condition, collection) => self.get_knn(field, vector, k, condition, collection),
+            Statement::Project(parsed, projection) => self.exec_projected(*parsed, &projection),
+            Statement::Lookup(parsed, lookups) => self.lookup(*parsed, &lookups, &Projection::default()),
+            Statement::Explain(stmt) => self.explain(stmt),
+            Statement::Profile(stmt) => Box::pin(self.profile(stmt)).await,
+            Statement::Aggregate(aggregation, condition, collection) => self.aggregate(aggregation, condition, collection),
+            Statement::Count(condition, collection) => self.count_when(condition, collection),
+            Statement::ExistsWhen(condition, collection) => self.exists_when(condition, collection),
+            Statement::Distinct(field, condition, collection) => self.distinct(field, condition, collection),
+            Statement::Facets(fields, condition, collection, limit) => self.facets(fields, condition, limit.unwrap_or(10), collection),
+            Statement::Clips(sets, collection, sort, limit) => self.get_clips(sets, collection, sort, limit),
+            Statement::Views(collection) => self.list_views(collection),
+            Statement::RebuildViews(collection) => self.rebuild_views(collection).await,
+            Statement::DropView(view, collection) => self.drop_view(view, collection).await,
+            Statement::DeleteView(view, collection) => self.delete_view(view, collection).await,
+            Statement::PutIndex(data, index, collection) => self.put_index_with(data, index, collection, None).await,
+            Statement::DeleteIndex(index, collection) => self.delete_index(index, collection).await,
+            Statement::ExistsIndex(index, collection) => self.index_exi(index, collection),
+            Statement::TtlIndex(duration, index, collection) => self.index_ttl(duration, index, collection).await,
+            Statement::OnConflict(parsed, conflict) => match *parsed {
+                Statement::Flql(Flql::Put(data, collection)) => self.put_data_with(data, collection, Some(conflict)).await,
+                Statement::Flql(Flql::PutWhen(data, condition, collection)) => self.put_data_when_with(data, condition, collection, Some(conflict)).await,
+                Statement::Flql(Flql::PutPointer(data, pointer, collection)) => self.put_pointer_with(data, pointer, collection, Some(conflict)).await,
+                Statement::PutIndex(data, index, collection) => self.put_index_with(data, index, collection, Some(conflict)).await,
+                _ => QueryResult {
+                    data: vec![],
+                    error: FlinchError::CustomError("on_conflict only follows a put statement".to_string()),
+                    time_taken: "".to_string(),
+                    ..Default::default()
+                },
+            },
+        }
     }
 
-    /// expect a parsed `FLQL`
+    /// expect a parsed `get` statement. only the projected fields are returned,
+    /// documents are still sorted by their stored fields
+    pub fn exec_projected(&self, parsed: Statement, projection: &Projection) -> QueryResult {
+        match parsed {
+            Statement::Flql(Flql::Get(collection, sort, limit)) => self.fetch_all_projected(collection, sort, limit, projection),
+            Statement::Flql(Flql::GetWhen(condition, collection, sort, limit)) => self.fetch_when_projected(condition, collection, sort, limit, projection),
+            Statement::Flql(Flql::GetPointer(pointer, collection)) => self.get_pointer_projected(pointer, collection, projection),
+            Statement::Flql(Flql::GetView(view, collection)) => self.get_view_projected(view, collection, projection),
+            Statement::Flql(Flql::GetClip(clip, collection)) => self.get_clip_projected(clip, collection, projection),
+            Statement::Flql(Flql::GetIndex(index, collection)) => self.get_index_projected(index, collection, projection),
+            Statement::Flql(Flql::GetRange(start, end, on, collection)) => self.get_range_projected(start, end, on, collection, projection),
+            Statement::Near(field, point, radius, limit, collection) => self.get_near_projected(field, point, radius, limit, collection, projection),
+            Statement::WithinBox(field, sw, ne, limit, collection) => self.get_within_box_projected(field, sw, ne, limit, collection, projection),
+            Statement::Knn(field, vector, k, condition, collection) => self.get_knn_projected(field, vector, k, condition, collection, projection),
+            Statement::Clips(sets, collection, sort, limit) => self.get_clips_projected(sets, collection, sort, limit, projection),
+            Statement::Lookup(parsed, lookups) => self.lookup(*parsed, &lookups, projection),
+            _ => QueryResult {
+                data: vec![],
+                error: FlinchError::CustomError("projection is only supported on get statements".to_string()),
+                time_taken: "".to_string(),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// expect a parsed `FLQL`. it runs with the limits of the query
     pub async fn exec_with_flql(&self, parsed: Flql) -> QueryResult {
+        self.exec_with_statement(Statement::Flql(parsed)).await
+    }
+
+    async fn run_flql(&self, parsed: Flql) -> QueryResult {
         match parsed {
             Flql::New(options) => self.col_new(options).await,
             Flql::Drop(collection) => self.col_drop(collection).await,
@@ -97,6 +304,7 @@ impl Query {
                     data: vec![],
                     error: FlinchError::None,
                     time_taken: "".to_string(),
+                    ..Default::default()
                 }
             }
         }
@@ -113,6 +321,7 @@ impl Query {
                     data: vec![],
                     error: self.err_q(Some(QueryError::CollectionError(x.err().unwrap()))),
                     time_taken: ttk.done(),
+                    ..Default::default()
                 };
             }
         } else {
@@ -120,12 +329,14 @@ impl Query {
                 data: vec![],
                 error: self.err_q(Some(QueryError::ConfigureParseError(parsed.err().unwrap().to_string()))),
                 time_taken: ttk.done(),
+                ..Default::default()
             };
         }
         QueryResult {
             data: vec![],
             error: FlinchError::None,
             time_taken: ttk.done(),
+            ..Default::default()
         }
     }
 
@@ -136,6 +347,7 @@ impl Query {
             data: vec![],
             error: self.err_c(x.err()),
             time_taken: ttk.done(),
+            ..Default::default()
         }
     }
 
@@ -147,6 +359,7 @@ impl Query {
                 data: vec![],
                 error: self.err_c(col.err()),
                 time_taken: ttk.done(),
+                ..Default::default()
             };
         }
         let col = col.unwrap();
@@ -155,6 +368,7 @@ impl Query {
             data: vec![],
             error: FlinchError::None,
             time_taken: ttk.done(),
+            ..Default::default()
         }
     }
 
@@ -166,14 +380,15 @@ impl Query {
                 data: vec![],
                 error: self.err_c(col.err()),
                 time_taken: ttk.done(),
+                ..Default::default()
             };
         }
         let col = col.unwrap();
-        let ttk = ExecTime::new();
         QueryResult {
             data: vec![Value::Number(Number::from(col.len()))],
             error: FlinchError::None,
             time_taken: ttk.done(),
+            ..Default::default()
         }
     }
 
@@ -185,10 +400,10 @@ impl Query {
                 data: vec![],
                 error: self.err_c(col.err()),
                 time_taken: ttk.done(),
+                ..Default::default()
             };
         }
         let col = col.unwrap();
-        let ttk = ExecTime::new();
         let exi = col.get(&pointer);
         let res = if exi.data.is_some() {
             vec![Value::Bool(true)]
@@ -199,6 +414,29 @@ impl Query {
             data: res,
             error: FlinchError::None,
             time_taken: ttk.done(),
+            ..Default::default()
+        }
+    }
+
+    /// whether a document holds an `index` value
+    pub fn index_exi(&self, index: String, collection: String) -> QueryResult {
+        let ttk = ExecTime::new();
+        let col = self.db.using(trim_apos(&collection).as_str());
+        if col.is_err() {
+            return QueryResult {
+                data: vec![],
+                error: self.err_c(col.err()),
+                time_taken: ttk.done(),
+                ..Default::default()
+            };
+        }
+        let col = col.unwrap();
+        let exi = col.index_pointer(trim_apos(&index).as_str());
+        QueryResult {
+            data: vec![Value::Bool(exi.is_some())],
+            error: FlinchError::None,
+            time_taken: ttk.done(),
+            ..Default::default()
         }
     }
 
@@ -210,14 +448,16 @@ impl Query {
                 data: vec![],
                 error: self.err_s(format!("{} is malformed as a TTL value", duration)),
                 time_taken: ttk.done(),
+                ..Default::default()
             };
         }
-        let expression = flql::expr_parse(trim_apos(&condition).as_str());
+        let expression = Predicate::compile(trim_apos(&condition).as_str());
         if expression.is_err() {
             return QueryResult {
                 data: vec![],
                 error: self.err_s(expression.err().unwrap().to_string()),
                 time_taken: ttk.done(),
+                ..Default::default()
             };
         }
         let col = self.db.using(trim_apos(&collection).as_str());
@@ -226,44 +466,74 @@ impl Query {
                 data: vec![],
                 error: self.err_c(col.err()),
                 time_taken: ttk.done(),
+                ..Default::default()
             };
         }
         let col = col.unwrap();
         let expression = expression.unwrap();
         let timestamp = chrono::Local::now() + chrono::Duration::seconds(timestamp.unwrap());
         let timestamp = timestamp.timestamp();
-        let ttk = ExecTime::new();
-        let data = col.iter().filter(|kv| {
-            let pair = kv.pair();
-            let d = pair.1;
-            let x = expression.calculate(d.string().as_bytes());
-            if x.is_ok() {
-                let x = x.unwrap();
-                if x == flql::exp_parser::Value::Bool(true) {
-                    return true;
-                }
-            }
-            false
-        })
-            .map(|kv| {
-                let key = kv.key();
-                key.to_string()
-            })
-            .collect::<Vec<String>>();
+        let data = col.when(trim_apos(&condition).as_str(), |k, d| {
+            expression.matches(d.document()).then(|| k.to_string())
+        });
         // FIXME: do in par_iter
+        let guard = guard::current();
+        let mut applied = 0;
         for key in &data {
+            if guard.check().is_err() {
+                break;
+            }
             col.put_ttl(key.to_string(), timestamp).await;
+            applied += 1;
         }
-        let message = format!("TTL was set for {} keys", data.len());
+        let message = format!("TTL was set for {} keys", applied);
         let data = vec![Value::String(message)];
         QueryResult {
             data,
             error: FlinchError::None,
             time_taken: ttk.done(),
+            ..Default::default()
+        }
+    }
+
+    /// sets a TTL for the document holding an `index` value, resolved and set atomically
+    pub async fn index_ttl(&self, duration: String, index: String, collection: String) -> QueryResult {
+        let ttk = ExecTime::new();
+        let timestamp = duration.parse::<i64>();
+        if timestamp.is_err() {
+            return QueryResult {
+                data: vec![],
+                error: self.err_s(format!("{} is malformed as a TTL value", duration)),
+                time_taken: ttk.done(),
+                ..Default::default()
+            };
+        }
+        let col = self.db.using(trim_apos(&collection).as_str());
+        if col.is_err() {
+            return QueryResult {
+                data: vec![],
+                error: self.err_c(col.err()),
+                time_taken: ttk.done(),
+                ..Default::default()
+            };
+        }
+        let col = col.unwrap();
+        let timestamp = chrono::Local::now() + chrono::Duration::seconds(timestamp.unwrap());
+        let pointer = col.ttl_index(trim_apos(&index).as_str(), timestamp.timestamp()).await;
+        QueryResult {
+            data: pointer.into_iter().map(Value::String).collect(),
+            error: FlinchError::None,
+            time_taken: ttk.done(),
+            ..Default::default()
         }
     }
 
     pub async fn put_data(&self, data: String, collection: String) -> QueryResult {
+        self.put_data_with(data, collection, None).await
+    }
+
+    /// puts a document resolving collisions on unique indexes by `conflict`, by the collection's when `None`
+    pub async fn put_data_with(&self, data: String, collection: String, conflict: Option<Conflict>) -> QueryResult {
         let ttk = ExecTime::new();
         let qdata = QueryBased::from_str(data.as_str());
         if qdata.is_err() {
@@ -271,6 +541,7 @@ impl Query {
                 data: vec![],
                 error: self.err_d(qdata.err()),
                 time_taken: ttk.done(),
+                ..Default::default()
             };
         }
         let col = self.db.using(trim_apos(&collection).as_str());
@@ -279,27 +550,35 @@ impl Query {
                 data: vec![],
                 error: self.err_c(col.err()),
                 time_taken: ttk.done(),
+                ..Default::default()
             };
         }
         let col = col.unwrap();
-        let ttk = ExecTime::new();
         let id = col.id();
-        let x = col.put(id.clone(), qdata.unwrap()).await;
+        let (x, conflicts) = self.put_resolved(&col, id.clone(), qdata.unwrap(), conflict).await;
         QueryResult {
-            data: vec![Value::String(id)],
+            data: vec![Value::String(x.as_ref().map_or(id, String::to_string))],
             error: self.err_i(x.err()),
             time_taken: ttk.done(),
+            conflicts: conflicts.into_iter().collect(),
+            ..Default::default()
         }
     }
 
     pub async fn put_data_when(&self, data: String, condition: String, collection: String) -> QueryResult {
+        self.put_data_when_with(data, condition, collection, None).await
+    }
+
+    /// puts a document in place of the matching ones resolving collisions on unique indexes by `conflict`
+    pub async fn put_data_when_with(&self, data: String, condition: String, collection: String, conflict: Option<Conflict>) -> QueryResult {
         let ttk = ExecTime::new();
-        let expression = flql::expr_parse(trim_apos(&condition).as_str());
+        let expression = Predicate::compile(trim_apos(&condition).as_str());
         if expression.is_err() {
             return QueryResult {
                 data: vec![],
                 error: self.err_s(expression.err().unwrap().to_string()),
                 time_taken: ttk.done(),
+                ..Default::default()
             };
         }
         let qdata = QueryBased::from_str(data.as_str());
@@ -308,6 +587,7 @@ impl Query {
                 data: vec![],
                 error: self.err_d(qdata.err()),
                 time_taken: ttk.done(),
+                ..Default::default()
             };
         }
         let col = self.db.using(trim_apos(&collection).as_str());
@@ -316,39 +596,43 @@ impl Query {
                 data: vec![],
                 error: self.err_c(col.err()),
                 time_taken: ttk.done(),
+                ..Default::default()
             };
         }
         let expression = expression.unwrap();
         let qdata = qdata.unwrap();
         let col = col.unwrap();
-        let ttk = ExecTime::new();
-        let sg = SegQueue::new();
-        col.iter().for_each(|kv| {
-            let pair = kv.pair();
-            let v = pair.1;
-            let k = pair.0;
-            let d = expression.calculate(v.string().as_bytes());
-            if d.is_ok() {
-                let d = d.unwrap();
-                if d == flql::exp_parser::Value::Bool(true) {
-                    let x = block_on(async {
-                        col.put(k.clone(), qdata.clone()).await
-                    });
-                    if x.is_ok() {
-                        sg.push(Value::String(k.clone()));
-                    }
-                }
-            }
+        let keys = col.when(trim_apos(&condition).as_str(), |k, v| {
+            expression.matches(v.document()).then(|| k.clone())
         });
-        let ids = sg.into_iter().collect();
+        let mut ids = vec![];
+        let mut conflicts = vec![];
+        let guard = guard::current();
+        for k in keys {
+            if guard.check().is_err() {
+                break;
+            }
+            let (x, conflicted) = self.put_resolved(&col, k, qdata.clone(), conflict).await;
+            if let Ok(pointer) = x {
+                ids.push(Value::String(pointer));
+            }
+            conflicts.extend(conflicted);
+        }
         QueryResult {
             data: ids,
             error: FlinchError::None,
             time_taken: ttk.done(),
+            conflicts,
+            ..Default::default()
         }
     }
 
     pub async fn put_pointer(&self, data: String, pointer: String, collection: String) -> QueryResult {
+        self.put_pointer_with(data, pointer, collection, None).await
+    }
+
+    /// puts a document under a pointer resolving collisions on unique indexes by `conflict`
+    pub async fn put_pointer_with(&self, data: String, pointer: String, collection: String, conflict: Option<Conflict>) -> QueryResult {
         let ttk = ExecTime::new();
         let qdata = QueryBased::from_str(data.as_str());
         if qdata.is_err() {
@@ -356,6 +640,7 @@ impl Query {
                 data: vec![],
                 error: self.err_d(qdata.err()),
                 time_taken: ttk.done(),
+                ..Default::default()
             };
         }
         let col = self.db.using(trim_apos(&collection).as_str());
@@ -364,20 +649,78 @@ impl Query {
                 data: vec![],
                 error: self.err_c(col.err()),
                 time_taken: ttk.done(),
+                ..Default::default()
             };
         }
         let qdata = qdata.unwrap();
         let col = col.unwrap();
+        let (x, conflicts) = self.put_resolved(&col, pointer, qdata, conflict).await;
+        QueryResult {
+            data: vec![],
+            error: self.err_i(x.err()),
+            time_taken: ttk.done(),
+            conflicts: conflicts.into_iter().collect(),
+            ..Default::default()
+        }
+    }
+
+    /// puts a document in place of the one holding an `index` value, resolved and written atomically.
+    /// nothing is written when no document holds it
+    pub async fn put_index_with(&self, data: String, index: String, collection: String, conflict: Option<Conflict>) -> QueryResult {
         let ttk = ExecTime::new();
-        let x = col.put(pointer, qdata).await;
-        let mut time_taken = ttk.done();
-        if x.is_ok() {
-            time_taken = x.unwrap();
+        let qdata = QueryBased::from_str(data.as_str());
+        if qdata.is_err() {
+            return QueryResult {
+                data: vec![],
+                error: self.err_d(qdata.err()),
+                time_taken: ttk.done(),
+                ..Default::default()
+            };
+        }
+        let col = self.db.using(trim_apos(&collection).as_str());
+        if col.is_err() {
+            return QueryResult {
+                data: vec![],
+                error: self.err_c(col.err()),
+                time_taken: ttk.done(),
+                ..Default::default()
+            };
         }
+        let col = col.unwrap();
+        let qdata = qdata.unwrap();
+        let index = trim_apos(&index);
+        let conflict = conflict.unwrap_or(col.opts().conflict_opts);
+        let (data, error, conflicts) = match col.put_index(index.as_str(), qdata.clone(), conflict).await {
+            Ok(Some((pointer, conflicted))) => (vec![Value::String(pointer)], FlinchError::None, conflicted),
+            Ok(None) => (vec![], FlinchError::None, None),
+            Err(IndexError::DuplicateDocument) => {
+                let pointers = col.index_pointer(index.as_str()).map(|k| col.collisions(&k, &qdata)).unwrap_or_default();
+                let conflicted = (!pointers.is_empty()).then_some(Conflicted { resolution: conflict, pointers });
+                (vec![], self.err_i(Some(IndexError::DuplicateDocument)), conflicted)
+            }
+            Err(err) => (vec![], self.err_i(Some(err)), None),
+        };
         QueryResult {
-            data: vec![],
-            error: FlinchError::None,
-            time_taken,
+            data,
+            error,
+            time_taken: ttk.done(),
+            conflicts: conflicts.into_iter().collect(),
+            ..Default::default()
+        }
+    }
+
+    /// puts a document by `conflict` or the collection's `conflict_opts`. returns the pointer it is stored under
+    /// and the collision it resolved, or the documents it collided with when rejected
+    async fn put_resolved(&self, col: &Collection<QueryBased>, k: String, d: QueryBased, conflict: Option<Conflict>) -> (Result<String, IndexError>, Option<Conflicted>) {
+        let conflict = conflict.unwrap_or(col.opts().conflict_opts);
+        match col.put_with(k.clone(), d.clone(), conflict).await {
+            Ok((pointer, conflicted)) => (Ok(pointer), conflicted),
+            Err(IndexError::DuplicateDocument) => {
+                let pointers = col.collisions(&k, &d);
+                let conflicted = (!pointers.is_empty()).then_some(Conflicted { resolution: conflict, pointers });
+                (Err(IndexError::DuplicateDocument), conflicted)
+            }
+            Err(err) => (Err(err), None),
         }
     }
 
@@ -389,10 +732,10 @@ impl Query {
                 data: vec![],
                 error: self.err_c(col.err()),
                 time_taken: ttk.done(),
+                ..Default::default()
             };
         }
         let col = col.unwrap();
-        let ttk = ExecTime::new();
         let res = col.search(trim_apos(&query).as_str());
         let time_taken = ttk.done();
         let data = res.data.into_iter().map(|kv| kv.1.document().clone()).collect::<Vec<Value>>();
@@ -400,10 +743,32 @@ impl Query {
             data,
             error: FlinchError::None,
             time_taken,
+            ..Default::default()
         }
     }
 
-    pub fn fetch_all(&self, collection: String, sort: Option<String>, limit: Option<String>) -> QueryResult {
+    /// top `size` values of every clip field among the documents matching `condition`
+    pub fn facets_when(&self, condition: String, fields: Vec<String>, size: usize, collection: String) -> QueryResult {
+        self.facets(fields, Some(condition), size, collection)
+    }
+
+    /// top `size` values of every clip field among the documents matching `condition`, or all of them
+    pub fn facets(&self, fields: Vec<String>, condition: Option<String>, size: usize, collection: String) -> QueryResult {
+        let fields = fields.iter().map(trim_apos).collect::<Vec<String>>();
+        let fields = fields.iter().map(|f| f.as_str()).collect::<Vec<&str>>();
+        self.tally(condition, collection, |col, condition, filter| {
+            let keys = match condition {
+                Some(condition) => col.when(condition, |k, d| filter(k, d).then(|| k.to_owned())),
+                None => col.scan(Plan::Scan, |k, _| Some(k.to_owned())),
+            };
+            col.facets(&keys, &fields, size).data
+                .into_iter()
+                .map(|facet| serde_json::to_value(facet).unwrap())
+                .collect::<Vec<Value>>()
+        })
+    }
+
+    pub fn search_contains(&self, text: String, field: Option<String>, collection: String) -> QueryResult {
         let ttk = ExecTime::new();
         let col = self.db.using(trim_apos(&collection).as_str());
         if col.is_err() {
@@ -411,93 +776,528 @@ impl Query {
                 data: vec![],
                 error: self.err_c(col.err()),
                 time_taken: ttk.done(),
+                ..Default::default()
             };
         }
-        let sort = parse_sort(sort);
-        let limit = parse_limit(limit);
-
         let col = col.unwrap();
+        let field = field.map(|f| trim_apos(&f));
+        let fields = field.iter().map(|f| f.as_str()).collect::<Vec<&str>>();
+        let res = col.contains(trim_apos(&text).as_str(), &fields);
+        let data = res.data.into_iter().map(|kv| kv.1.make(kv.0)).collect::<Vec<Value>>();
+        QueryResult {
+            data,
+            error: FlinchError::None,
+            time_taken: ttk.done(),
+            ..Default::default()
+        }
+    }
+
+    pub fn get_near(&self, field: String, point: Point, radius: f64, limit: Option<usize>, collection: String) -> QueryResult {
+        self.get_near_projected(field, point, radius, limit, collection, &Projection::default())
+    }
+
+    pub fn get_near_projected(&self, field: String, point: Point, radius: f64, limit: Option<usize>, collection: String, projection: &Projection) -> QueryResult {
         let ttk = ExecTime::new();
-        let mut data = col.iter().map(|kv| {
-            let k = kv.key();
-            let v = kv.value();
-            v.make(k.clone())
-        }).collect::<Vec<Value>>();
+        let col = self.db.using(trim_apos(&collection).as_str());
+        if col.is_err() {
+            return QueryResult {
+                data: vec![],
+                error: self.err_c(col.err()),
+                time_taken: ttk.done(),
+                ..Default::default()
+            };
+        }
+        let col = col.unwrap();
+        let res = col.near(trim_apos(&field).as_str(), point, radius, limit);
+        QueryResult {
+            data: self.with_distance(res.data, projection),
+            error: FlinchError::None,
+            time_taken: ttk.done(),
+            ..Default::default()
+        }
+    }
 
-        self.proc(
-            &mut data,
-            sort,
-        );
+    pub fn get_within_box(&self, field: String, sw: Point, ne: Point, limit: Option<usize>, collection: String) -> QueryResult {
+        self.get_within_box_projected(field, sw, ne, limit, collection, &Projection::default())
+    }
 
+    pub fn get_within_box_projected(&self, field: String, sw: Point, ne: Point, limit: Option<usize>, collection: String, projection: &Projection) -> QueryResult {
+        let ttk = ExecTime::new();
+        let col = self.db.using(trim_apos(&collection).as_str());
+        if col.is_err() {
+            return QueryResult {
+                data: vec![],
+                error: self.err_c(col.err()),
+                time_taken: ttk.done(),
+                ..Default::default()
+            };
+        }
+        let col = col.unwrap();
+        let res = col.within_box(trim_apos(&field).as_str(), sw, ne, limit);
         QueryResult {
-            data: if let Some((offset, limit)) = limit {
-                data[offset..(offset + limit)].to_owned()
-            } else {
-                data
+            data: self.with_distance(res.data, projection),
+            error: FlinchError::None,
+            time_taken: ttk.done(),
+            ..Default::default()
+        }
+    }
+
+    pub fn get_knn(&self, field: String, vector: Vec<f32>, k: usize, condition: Option<String>, collection: String) -> QueryResult {
+        self.get_knn_projected(field, vector, k, condition, collection, &Projection::default())
+    }
+
+    pub fn get_knn_projected(&self, field: String, vector: Vec<f32>, k: usize, condition: Option<String>, collection: String, projection: &Projection) -> QueryResult {
+        let ttk = ExecTime::new();
+        let expression = match condition.map(|c| Predicate::compile(trim_apos(&c).as_str())).transpose() {
+            Ok(expression) => expression,
+            Err(err) => {
+                return QueryResult {
+                    data: vec![],
+                    error: self.err_s(err.to_string()),
+                    time_taken: ttk.done(),
+                    ..Default::default()
+                };
+            }
+        };
+        let col = self.db.using(trim_apos(&collection).as_str());
+        if col.is_err() {
+            return QueryResult {
+                data: vec![],
+                error: self.err_c(col.err()),
+                time_taken: ttk.done(),
+                ..Default::default()
+            };
+        }
+        let col = col.unwrap();
+        let res = col.knn(trim_apos(&field).as_str(), &vector, k, |_, d| match &expression {
+            Some(expression) => expression.matches(d.document()),
+            None => true,
+        });
+        match res {
+            Ok(res) => QueryResult {
+                data: res.data.into_iter().map(|(k, d, score)| {
+                    let mut doc = projection.make(k, d.document());
+                    if let (Some(obj), Some(score)) = (doc.as_object_mut(), Number::from_f64(score as f64)) {
+                        obj.insert("_score".to_owned(), Value::Number(score));
+                    }
+                    doc
+                }).collect(),
+                error: FlinchError::None,
+                time_taken: ttk.done(),
+                ..Default::default()
+            },
+            Err(err) => QueryResult {
+                data: vec![],
+                error: self.err_i(Some(err)),
+                time_taken: ttk.done(),
+                ..Default::default()
             },
+        }
+    }
+
+    /// documents with `_pointer` and `_distance` in meters
+    fn with_distance(&self, res: Vec<(String, QueryBased, f64)>, projection: &Projection) -> Vec<Value> {
+        res.into_iter().map(|(k, d, distance)| {
+            let mut doc = projection.make(k, d.document());
+            if let (Some(obj), Some(distance)) = (doc.as_object_mut(), Number::from_f64(distance)) {
+                obj.insert("_distance".to_owned(), Value::Number(distance));
+            }
+            doc
+        }).collect()
+    }
+
+    /// plans a statement without running it. reports its access path and the documents it reads
+    pub fn explain(&self, stmt: String) -> QueryResult {
+        let ttk = ExecTime::new();
+        let mut at = Instant::now();
+        let mut profile = Profile::default();
+        let parsed = statement::parse(format!("{};", stmt).as_str());
+        profile.stage("parse", &mut at);
+        if parsed.is_err() {
+            return QueryResult {
+                data: vec![],
+                error: FlinchError::CustomError(parsed.err().unwrap()),
+                time_taken: ttk.done(),
+                ..Default::default()
+            };
+        }
+        self.plan(&parsed.unwrap(), &mut profile);
+        profile.stage("plan", &mut at);
+        QueryResult {
+            data: vec![serde_json::to_value(&profile).unwrap_or_default()],
             error: FlinchError::None,
             time_taken: ttk.done(),
+            ..Default::default()
         }
     }
 
-    pub fn fetch_when(&self, condition: String, collection: String, sort: Option<String>, limit: Option<String>) -> QueryResult {
+    /// runs a statement and reports its access path, the documents it reads and returns
+    /// and the time of every stage. the error of the statement is kept
+    pub async fn profile(&self, stmt: String) -> QueryResult {
         let ttk = ExecTime::new();
-        let expression = flql::expr_parse(trim_apos(&condition).as_str());
-        if expression.is_err() {
+        let mut at = Instant::now();
+        let mut profile = Profile { detailed: true, ..Default::default() };
+        let parsed = statement::parse(format!("{};", stmt).as_str());
+        profile.stage("parse", &mut at);
+        if parsed.is_err() {
             return QueryResult {
                 data: vec![],
-                error: self.err_s(expression.err().unwrap().to_string()),
+                error: FlinchError::CustomError(parsed.err().unwrap()),
+                time_taken: ttk.done(),
+                ..Default::default()
+            };
+        }
+        let (parsed, projection) = match parsed.unwrap() {
+            Statement::Project(parsed, projection) => (*parsed, projection),
+            parsed => (parsed, Projection::default()),
+        };
+        let res = match parsed {
+            Statement::Flql(Flql::Get(collection, sort, limit)) => self.fetch(None, collection, sort, limit, &projection, &mut profile),
+            Statement::Flql(Flql::GetWhen(condition, collection, sort, limit)) => self.fetch(Some(condition), collection, sort, limit, &projection, &mut profile),
+            parsed => {
+                self.plan(&parsed, &mut profile);
+                profile.stage("plan", &mut at);
+                let res = if projection.is_empty() {
+                    Box::pin(self.exec_with_statement(parsed)).await
+                } else {
+                    self.exec_projected(parsed, &projection)
+                };
+                profile.stage("execute", &mut at);
+                res
+            }
+        };
+        let mut at = Instant::now();
+        let _ = serde_json::to_vec(&res.data);
+        profile.stage("serialize", &mut at);
+        profile.results = Some(res.data.len());
+        QueryResult {
+            data: vec![serde_json::to_value(&profile).unwrap_or_default()],
+            error: res.error,
+            time_taken: ttk.done(),
+            ..Default::default()
+        }
+    }
+
+    /// the name, collection and access path of a statement. documents read
+    /// are counted for scans and planned conditions
+    fn plan(&self, parsed: &Statement, profile: &mut Profile) {
+        let (name, collection, access, condition) = match parsed {
+            Statement::Flql(flql) => match flql {
+                Flql::New(_) => ("new", None, Access::None, None),
+                Flql::Drop(c) => ("drop", Some(c), Access::None, None),
+                Flql::Flush(c) => ("flush", Some(c), Access::None, None),
+                Flql::Exists(_, c) => ("exists", Some(c), Access::Pointer, None),
+                Flql::Length(c) => ("length", Some(c), Access::None, None),
+                Flql::Ttl(_, condition, c) => ("ttl", Some(c), Access::Scan, Some(condition)),
+                Flql::Put(_, c) => ("put", Some(c), Access::None, None),
+                Flql::PutWhen(_, condition, c) => ("put.when", Some(c), Access::Scan, Some(condition)),
+                Flql::PutPointer(_, _, c) => ("put.pointer", Some(c), Access::Pointer, None),
+                Flql::SearchTyping(_, c) => ("search.query", Some(c), Access::Search, None),
+                Flql::Get(c, _, _) => ("get", Some(c), Access::Scan, None),
+                Flql::GetWhen(condition, c, _, _) => ("get.when", Some(c), Access::Scan, Some(condition)),
+                Flql::GetPointer(_, c) => ("get.pointer", Some(c), Access::Pointer, None),
+                Flql::GetView(_, c) => ("get.view", Some(c), Access::View, None),
+                Flql::GetClip(_, c) => ("get.clip", Some(c), Access::Clip, None),
+                Flql::GetIndex(_, c) => ("get.index", Some(c), Access::HashIndex, None),
+                Flql::GetRange(_, _, _, c) => ("get.range", Some(c), Access::Range, None),
+                Flql::Delete(c) => ("delete", Some(c), Access::Scan, None),
+                Flql::DeleteWhen(condition, c) => ("delete.when", Some(c), Access::Scan, Some(condition)),
+                Flql::DeletePointer(_, c) => ("delete.pointer", Some(c), Access::Pointer, None),
+                Flql::DeleteClip(_, c) => ("delete.clip", Some(c), Access::Clip, None),
+                _ => ("flql", None, Access::None, None),
+            },
+            Statement::Contains(_, _, c) => ("search.contains", Some(c), Access::Search, None),
+            Statement::Near(_, _, _, _, c) => ("get.near", Some(c), Access::Geo, None),
+            Statement::WithinBox(_, _, _, _, c) => ("get.within_box", Some(c), Access::Geo, None),
+            Statement::Knn(_, _, _, _, c) => ("get.knn", Some(c), Access::Vector, None),
+            Statement::Project(parsed, _) | Statement::Lookup(parsed, _) | Statement::OnConflict(parsed, _) => return self.plan(parsed, profile),
+            Statement::Aggregate(_, condition, c) => ("aggregate", Some(c), Access::Scan, condition.as_ref()),
+            Statement::Clips(_, c, _, _) => ("get.clips", Some(c), Access::Clip, None),
+            Statement::Views(c) => ("views", Some(c), Access::None, None),
+            Statement::RebuildViews(c) => ("rebuild.views", Some(c), Access::Scan, None),
+            Statement::DropView(_, c) => ("drop.view", Some(c), Access::None, None),
+            Statement::DeleteView(_, c) => ("delete.view", Some(c), Access::View, None),
+            Statement::PutIndex(_, _, c) => ("put.index", Some(c), Access::HashIndex, None),
+            Statement::DeleteIndex(_, c) => ("delete.index", Some(c), Access::HashIndex, None),
+            Statement::ExistsIndex(_, c) => ("exists.index", Some(c), Access::HashIndex, None),
+            Statement::TtlIndex(_, _, c) => ("ttl.index", Some(c), Access::HashIndex, None),
+            Statement::Count(condition, c) => ("count", Some(c), Access::Scan, condition.as_ref()),
+            Statement::ExistsWhen(condition, c) => ("exists.when", Some(c), Access::Scan, Some(condition)),
+            Statement::Distinct(_, condition, c) => ("distinct", Some(c), Access::Scan, condition.as_ref()),
+            Statement::Facets(_, condition, c, _) => ("facets", Some(c), Access::Scan, condition.as_ref()),
+            Statement::Explain(_) => ("explain", None, Access::None, None),
+            Statement::Profile(_) => ("profile", None, Access::None, None),
+        };
+        profile.statement = name.to_string();
+        profile.collection = collection.map(trim_apos);
+        profile.access = access;
+        let col = match &profile.collection {
+            Some(collection) if profile.access == Access::Scan => self.db.using(collection.as_str()).ok(),
+            _ => None,
+        };
+        if let Some(col) = col {
+            profile.candidates = match condition.map(|c| col.plan(trim_apos(c).as_str())) {
+                Some(Plan::Candidates(keys)) => {
+                    profile.access = Access::Planned;
+                    Some(keys.len())
+                }
+                _ => Some(col.len()),
+            };
+        }
+    }
+
+    /// embeds the documents of other collections in the results of a `get` statement, then projects them.
+    /// every lookup joins all results at once
+    pub fn lookup(&self, parsed: Statement, lookups: &[Lookup], projection: &Projection) -> QueryResult {
+        let ttk = ExecTime::new();
+        let mut res = self.exec_projected(parsed, &Projection::default());
+        if res.error != FlinchError::None {
+            return res;
+        }
+        for lookup in lookups {
+            let col = self.db.using(lookup.collection.as_str());
+            if col.is_err() {
+                return QueryResult {
+                    data: vec![],
+                    error: self.err_c(col.err()),
+                    time_taken: ttk.done(),
+                    ..Default::default()
+                };
+            }
+            let col = col.unwrap();
+            let matches = {
+                let values = res.data.iter().flat_map(|d| lookup.values(d)).collect::<Vec<&Value>>();
+                col.join(&lookup.foreign, &values).data
+            };
+            res.data.par_iter_mut().for_each(|d| lookup.embed(d, &matches));
+        }
+        if !projection.is_empty() {
+            res.data = res.data.par_iter().map(|d| {
+                let mut doc = projection.apply(d);
+                if let (Some(obj), Some(meta)) = (doc.as_object_mut(), d.as_object()) {
+                    for field in META {
+                        if let Some(v) = meta.get(field) {
+                            obj.insert(field.to_string(), v.clone());
+                        }
+                    }
+                }
+                doc
+            }).collect();
+        }
+        res.time_taken = ttk.done();
+        res
+    }
+
+    /// groups the documents matching `condition`, or all of them, and computes the metrics of every group
+    pub fn aggregate(&self, aggregation: Aggregation, condition: Option<String>, collection: String) -> QueryResult {
+        let ttk = ExecTime::new();
+        let expression = match condition.as_ref().map(|c| Predicate::compile(trim_apos(c).as_str())).transpose() {
+            Ok(expression) => expression,
+            Err(err) => {
+                return QueryResult {
+                    data: vec![],
+                    error: self.err_s(err.to_string()),
+                    time_taken: ttk.done(),
+                    ..Default::default()
+                };
+            }
+        };
+        let col = self.db.using(trim_apos(&collection).as_str());
+        if col.is_err() {
+            return QueryResult {
+                data: vec![],
+                error: self.err_c(col.err()),
                 time_taken: ttk.done(),
+                ..Default::default()
             };
         }
+        let col = col.unwrap();
+        let condition = condition.map(|c| trim_apos(&c));
+        let res = col.aggregate(condition.as_deref(), &aggregation, |_, d| match &expression {
+            Some(expression) => expression.matches(d.document()),
+            None => true,
+        });
+        QueryResult {
+            data: res.data,
+            error: FlinchError::None,
+            time_taken: ttk.done(),
+            ..Default::default()
+        }
+    }
+
+    /// number of documents matching `condition`, or of all of them, without copying any
+    pub fn count_when(&self, condition: Option<String>, collection: String) -> QueryResult {
+        self.tally(condition, collection, |col, condition, filter| {
+            vec![Value::from(col.count(condition, filter).data)]
+        })
+    }
+
+    /// whether a document matches `condition`
+    pub fn exists_when(&self, condition: String, collection: String) -> QueryResult {
+        self.tally(Some(condition), collection, |col, condition, filter| {
+            vec![Value::Bool(col.exists_when(condition, filter).data)]
+        })
+    }
+
+    /// distinct values of `field` in the documents matching `condition`, or in all of them
+    pub fn distinct(&self, field: String, condition: Option<String>, collection: String) -> QueryResult {
+        let field = trim_apos(&field);
+        self.tally(condition, collection, |col, condition, filter| {
+            col.distinct(field.as_str(), condition, filter).data
+        })
+    }
+
+    /// runs `f` with the plan condition and the compiled filter of `condition` on `collection`
+    fn tally<F>(&self, condition: Option<String>, collection: String, f: F) -> QueryResult
+        where F: FnOnce(&Collection<QueryBased>, Option<&str>, &(dyn Fn(&String, &QueryBased) -> bool + Send + Sync)) -> Vec<Value>
+    {
+        let ttk = ExecTime::new();
+        let expression = match condition.as_ref().map(|c| Predicate::compile(trim_apos(c).as_str())).transpose() {
+            Ok(expression) => expression,
+            Err(err) => {
+                return QueryResult {
+                    data: vec![],
+                    error: self.err_s(err.to_string()),
+                    time_taken: ttk.done(),
+                    ..Default::default()
+                };
+            }
+        };
+        let col = match self.db.using(trim_apos(&collection).as_str()) {
+            Ok(col) => col,
+            Err(err) => {
+                return QueryResult {
+                    data: vec![],
+                    error: self.err_c(Some(err)),
+                    time_taken: ttk.done(),
+                    ..Default::default()
+                };
+            }
+        };
+        let condition = condition.map(|c| trim_apos(&c));
+        let data = f(&col, condition.as_deref(), &|_, d| match &expression {
+            Some(expression) => expression.matches(d.document()),
+            None => true,
+        });
+        QueryResult {
+            data,
+            error: FlinchError::None,
+            time_taken: ttk.done(),
+            ..Default::default()
+        }
+    }
+
+    pub fn fetch_all(&self, collection: String, sort: Option<String>, limit: Option<String>) -> QueryResult {
+        self.fetch_all_projected(collection, sort, limit, &Projection::default())
+    }
+
+    pub fn fetch_all_projected(&self, collection: String, sort: Option<String>, limit: Option<String>, projection: &Projection) -> QueryResult {
+        self.fetch(None, collection, sort, limit, projection, &mut Profile::default())
+    }
+
+    pub fn fetch_when(&self, condition: String, collection: String, sort: Option<String>, limit: Option<String>) -> QueryResult {
+        self.fetch_when_projected(condition, collection, sort, limit, &Projection::default())
+    }
+
+    pub fn fetch_when_projected(&self, condition: String, collection: String, sort: Option<String>, limit: Option<String>, projection: &Projection) -> QueryResult {
+        self.fetch(Some(condition), collection, sort, limit, projection, &mut Profile::default())
+    }
+
+    /// documents matching `condition`, or all of them, sorted and paged.
+    /// records the plan, scan, filter, sort and paginate stages in `profile`
+    fn fetch(&self, condition: Option<String>, collection: String, sort: Option<String>, limit: Option<String>, projection: &Projection, profile: &mut Profile) -> QueryResult {
+        let ttk = ExecTime::new();
+        let mut at = Instant::now();
+        let expression = match condition.as_ref().map(|c| Predicate::compile(trim_apos(c).as_str())).transpose() {
+            Ok(expression) => expression,
+            Err(err) => {
+                return QueryResult {
+                    data: vec![],
+                    error: self.err_s(err.to_string()),
+                    time_taken: ttk.done(),
+                    ..Default::default()
+                };
+            }
+        };
         let col = self.db.using(trim_apos(&collection).as_str());
         if col.is_err() {
             return QueryResult {
                 data: vec![],
                 error: self.err_c(col.err()),
                 time_taken: ttk.done(),
+                ..Default::default()
             };
         }
-        let expression = expression.unwrap();
         let sort = parse_sort(sort);
-        let limit = parse_limit(limit);
+        if sort.is_err() {
+            return QueryResult {
+                data: vec![],
+                error: FlinchError::CustomError(sort.err().unwrap()),
+                time_taken: ttk.done(),
+                ..Default::default()
+            };
+        }
+        let sort = sort.unwrap();
+        let page = parse_page(limit, &sort);
+        if page.is_err() {
+            return QueryResult {
+                data: vec![],
+                error: FlinchError::CustomError(page.err().unwrap()),
+                time_taken: ttk.done(),
+                ..Default::default()
+            };
+        }
+        let page = page.unwrap();
 
         let col = col.unwrap();
-        let ttk = ExecTime::new();
-        let mut data = col.iter().filter(|kv| {
-            let pair = kv.pair();
-            let d = pair.1;
-            let x = expression.calculate(d.string().as_bytes());
-            if x.is_ok() {
-                let x = x.unwrap();
-                if x == flql::exp_parser::Value::Bool(true) {
-                    return true;
+        let plan = match &condition {
+            Some(condition) => col.plan(trim_apos(condition).as_str()),
+            None => Plan::Scan,
+        };
+        profile.statement = if condition.is_some() { "get.when" } else { "get" }.to_string();
+        profile.collection = Some(trim_apos(&collection));
+        (profile.access, profile.candidates) = match &plan {
+            Plan::Scan => (Access::Scan, Some(col.len())),
+            Plan::Candidates(keys) => (Access::Planned, Some(keys.len())),
+        };
+        profile.stage("plan", &mut at);
+
+        let filtered = AtomicU64::new(0);
+        let detailed = profile.detailed;
+        // only pointers and sort values are kept, documents are projected once paged
+        let data = col.scan(plan, |k, d| {
+            let matched = match &expression {
+                None => true,
+                Some(expression) if detailed => {
+                    let start = Instant::now();
+                    let matched = expression.matches(d.document());
+                    filtered.fetch_add(start.elapsed().as_nanos() as u64, AtomicOrdering::Relaxed);
+                    matched
                 }
-            }
-            false
-        })
-            .map(|kv| {
-                let key = kv.key();
-                let doc = kv.value();
-                doc.make(key.to_owned())
-            })
-            .collect::<Vec<Value>>();
-        self.proc(
-            &mut data,
-            sort,
-        );
-        QueryResult {
-            data: if let Some((offset, limit)) = limit {
-                data[offset..(offset + limit)].to_owned()
-            } else {
-                data
-            },
+                Some(expression) => expression.matches(d.document()),
+            };
+            matched.then(|| (k.to_owned(), sort::values(d.document(), &sort)))
+        });
+        profile.stage("scan", &mut at);
+        if detailed && expression.is_some() {
+            profile.add("filter", Duration::from_nanos(filtered.into_inner()));
+        }
+        let (data, total, cursor) = self.proc(data, &sort, page, profile, &col, projection);
+        QueryResult {
+            data,
             error: FlinchError::None,
             time_taken: ttk.done(),
+            total: Some(total),
+            cursor,
+            ..Default::default()
         }
     }
 
     pub fn get_pointer(&self, pointer: String, collection: String) -> QueryResult {
+        self.get_pointer_projected(pointer, collection, &Projection::default())
+    }
+
+    pub fn get_pointer_projected(&self, pointer: String, collection: String, projection: &Projection) -> QueryResult {
         let ttk = ExecTime::new();
         let col = self.db.using(trim_apos(&collection).as_str());
         if col.is_err() {
@@ -505,27 +1305,31 @@ impl Query {
                 data: vec![],
                 error: self.err_c(col.err()),
                 time_taken: ttk.done(),
+                ..Default::default()
             };
         }
         let col = col.unwrap();
 
-        let v = col.get(&pointer);
+        let v = col.project(&pointer, projection);
         let mut time_taken = ttk.done();
         let mut res = vec![];
-        if v.data.is_some() {
+        if let Some(doc) = v.data {
             time_taken = v.time_taken;
-
-            let d = v.data.unwrap();
-            res = vec![d.1.make(d.0)];
+            res = vec![doc];
         }
         QueryResult {
             data: res,
             error: FlinchError::None,
             time_taken,
+            ..Default::default()
         }
     }
 
     pub fn get_view(&self, view: String, collection: String) -> QueryResult {
+        self.get_view_projected(view, collection, &Projection::default())
+    }
+
+    pub fn get_view_projected(&self, view: String, collection: String, projection: &Projection) -> QueryResult {
         let ttk = ExecTime::new();
         let col = self.db.using(trim_apos(&collection).as_str());
         if col.is_err() {
@@ -533,31 +1337,51 @@ impl Query {
                 data: vec![],
                 error: self.err_c(col.err()),
                 time_taken: ttk.done(),
+                ..Default::default()
             };
         }
         let col = col.unwrap();
 
-        let v = col.fetch_view(&view);
+        let v = col.fetch_view(trim_apos(&view).as_str());
 
         let mut time_taken = ttk.done();
         let mut res = vec![];
-        if v.data.len() > 0 {
+        if !v.data.is_empty() {
             time_taken = v.time_taken;
-
-            let sg = SegQueue::new();
-            v.data.par_iter().for_each(|tuple| {
-                sg.push(tuple.1.clone());
-            });
-            res = sg.into_iter().collect();
+            // views may be sorted, their order is kept
+            res = v.data.par_iter().map(|tuple| projection.apply(&tuple.1)).collect();
         }
         QueryResult {
             data: res,
             error: FlinchError::None,
             time_taken,
+            ..Default::default()
         }
     }
 
-    pub fn get_clip(&self, clip: String, collection: String) -> QueryResult {
+    /// every view of a collection as `{"view": name, "size": documents}`
+    pub fn list_views(&self, collection: String) -> QueryResult {
+        let ttk = ExecTime::new();
+        let col = self.db.using(trim_apos(&collection).as_str());
+        if col.is_err() {
+            return QueryResult {
+                data: vec![],
+                error: self.err_c(col.err()),
+                time_taken: ttk.done(),
+                ..Default::default()
+            };
+        }
+        let col = col.unwrap();
+        QueryResult {
+            data: sized(col.views()),
+            error: FlinchError::None,
+            time_taken: ttk.done(),
+            ..Default::default()
+        }
+    }
+
+    /// puts every document in the views it belongs to now, gives the views like `list_views`
+    pub async fn rebuild_views(&self, collection: String) -> QueryResult {
         let ttk = ExecTime::new();
         let col = self.db.using(trim_apos(&collection).as_str());
         if col.is_err() {
@@ -565,27 +1389,71 @@ impl Query {
                 data: vec![],
                 error: self.err_c(col.err()),
                 time_taken: ttk.done(),
+                ..Default::default()
             };
         }
         let col = col.unwrap();
+        let res = col.rebuild_views().await;
+        QueryResult {
+            data: sized(res.data),
+            error: FlinchError::None,
+            time_taken: ttk.done(),
+            ..Default::default()
+        }
+    }
+
+    /// removes a view from a collection, which is created again with its documents
+    pub async fn drop_view(&self, view: String, collection: String) -> QueryResult {
+        let ttk = ExecTime::new();
+        let view = trim_apos(&view);
+        let res = self.db.drop_view(trim_apos(&collection).as_str(), view.as_str()).await;
+        QueryResult {
+            data: if res.is_ok() { vec![Value::String(view)] } else { vec![] },
+            error: self.err_c(res.err()),
+            time_taken: ttk.done(),
+            ..Default::default()
+        }
+    }
+
+    pub fn get_clip(&self, clip: String, collection: String) -> QueryResult {
+        self.get_clip_projected(clip, collection, &Projection::default())
+    }
+
+    pub fn get_clip_projected(&self, clip: String, collection: String, projection: &Projection) -> QueryResult {
         let ttk = ExecTime::new();
+        let col = self.db.using(trim_apos(&collection).as_str());
+        if col.is_err() {
+            return QueryResult {
+                data: vec![],
+                error: self.err_c(col.err()),
+                time_taken: ttk.done(),
+                ..Default::default()
+            };
+        }
+        let col = col.unwrap();
         let c = col.fetch_clip(clip.as_str());
         let time_taken = ttk.done();
 
         let data = c.data;
         let sg = SegQueue::new();
         data.par_iter().for_each(|kv| {
-            sg.push(kv.1.make(kv.0.to_owned()));
+            sg.push(projection.make(kv.0.to_owned(), kv.1.document()));
         });
         let res = sg.into_iter().collect();
         QueryResult {
             data: res,
             error: FlinchError::None,
             time_taken,
+            ..Default::default()
         }
     }
 
-    pub fn get_index(&self, index: String, collection: String) -> QueryResult {
+    pub fn get_clips(&self, sets: ClipSets, collection: String, sort: Option<String>, limit: Option<String>) -> QueryResult {
+        self.get_clips_projected(sets, collection, sort, limit, &Projection::default())
+    }
+
+    /// documents combined from clips and views, sorted and paged like `get`
+    pub fn get_clips_projected(&self, sets: ClipSets, collection: String, sort: Option<String>, limit: Option<String>, projection: &Projection) -> QueryResult {
         let ttk = ExecTime::new();
         let col = self.db.using(trim_apos(&collection).as_str());
         if col.is_err() {
@@ -593,24 +1461,82 @@ impl Query {
                 data: vec![],
                 error: self.err_c(col.err()),
                 time_taken: ttk.done(),
+                ..Default::default()
+            };
+        }
+        let sort = parse_sort(sort);
+        if sort.is_err() {
+            return QueryResult {
+                data: vec![],
+                error: FlinchError::CustomError(sort.err().unwrap()),
+                time_taken: ttk.done(),
+                ..Default::default()
+            };
+        }
+        let sort = sort.unwrap();
+        let page = parse_page(limit, &sort);
+        if page.is_err() {
+            return QueryResult {
+                data: vec![],
+                error: FlinchError::CustomError(page.err().unwrap()),
+                time_taken: ttk.done(),
+                ..Default::default()
             };
         }
+        let page = page.unwrap();
         let col = col.unwrap();
+        let named = |sets: Vec<ClipSet>| sets.into_iter().map(|set| match set {
+            ClipSet::Clip(clip) => ClipSet::Clip(trim_apos(&clip)),
+            ClipSet::View(view) => ClipSet::View(trim_apos(&view)),
+        }).collect::<Vec<ClipSet>>();
+        let sets = ClipSets { all: named(sets.all), any: named(sets.any), none: named(sets.none) };
+        let keys = col.clip_keys(&sets);
+        let data = col.scan(Plan::Candidates(keys), |k, d| Some((k.to_owned(), sort::values(d.document(), &sort))));
+        let (data, total, cursor) = self.proc(data, &sort, page, &mut Profile::default(), &col, projection);
+        QueryResult {
+            data,
+            error: FlinchError::None,
+            time_taken: ttk.done(),
+            total: Some(total),
+            cursor,
+            ..Default::default()
+        }
+    }
+
+    pub fn get_index(&self, index: String, collection: String) -> QueryResult {
+        self.get_index_projected(index, collection, &Projection::default())
+    }
+
+    pub fn get_index_projected(&self, index: String, collection: String, projection: &Projection) -> QueryResult {
         let ttk = ExecTime::new();
+        let col = self.db.using(trim_apos(&collection).as_str());
+        if col.is_err() {
+            return QueryResult {
+                data: vec![],
+                error: self.err_c(col.err()),
+                time_taken: ttk.done(),
+                ..Default::default()
+            };
+        }
+        let col = col.unwrap();
         let x = col.get_index(trim_apos(&index).as_str());
         let mut data = vec![];
-        if x.data.is_some() {
-            let y = x.data.unwrap();
-            data.push(y.1.make(y.0));
+        if let Some((k, d)) = x.data {
+            data.push(projection.make(k, d.document()));
         }
         QueryResult {
             data,
             error: FlinchError::None,
             time_taken: ttk.done(),
+            ..Default::default()
         }
     }
 
     pub fn get_range(&self, start: String, end: String, on: String, collection: String) -> QueryResult {
+        self.get_range_projected(start, end, on, collection, &Projection::default())
+    }
+
+    pub fn get_range_projected(&self, start: String, end: String, on: String, collection: String, projection: &Projection) -> QueryResult {
         let ttk = ExecTime::new();
         let col = self.db.using(trim_apos(&collection).as_str());
         if col.is_err() {
@@ -618,16 +1544,17 @@ impl Query {
                 data: vec![],
                 error: self.err_c(col.err()),
                 time_taken: ttk.done(),
+                ..Default::default()
             };
         }
         let col = col.unwrap();
-        let ttk = ExecTime::new();
         let res = col.fetch_range(trim_apos(&on).as_str(), trim_apos(&start), trim_apos(&end));
-        let data = res.data.iter().map(|kv| kv.1.make(kv.0.to_owned())).collect::<Vec<Value>>();
+        let data = res.data.iter().map(|kv| projection.make(kv.0.to_owned(), kv.1.document())).collect::<Vec<Value>>();
         QueryResult {
             data,
             error: FlinchError::None,
             time_taken: ttk.done(),
+            ..Default::default()
         }
     }
 
@@ -639,26 +1566,38 @@ impl Query {
                 data: vec![],
                 error: self.err_c(col.err()),
                 time_taken: ttk.done(),
+                ..Default::default()
             };
         }
         let col = col.unwrap();
-        let ttk = ExecTime::new();
-        let _ = col.empty().await;
+        let guard = guard::current();
+        let keys = col.iter().map(|kv| kv.key().to_string()).collect::<Vec<String>>();
+        for k in keys {
+            if guard.check().is_err() {
+                break;
+            }
+            col.delete(k).await;
+        }
+        if guard.check().is_ok() {
+            col.empty().await;
+        }
         QueryResult {
             data: vec![],
             error: FlinchError::None,
             time_taken: ttk.done(),
+            ..Default::default()
         }
     }
 
     pub async fn delete_when(&self, condition: String, collection: String) -> QueryResult {
         let ttk = ExecTime::new();
-        let expression = flql::expr_parse(trim_apos(&condition).as_str());
+        let expression = Predicate::compile(trim_apos(&condition).as_str());
         if expression.is_err() {
             return QueryResult {
                 data: vec![],
                 error: self.err_s(expression.err().unwrap().to_string()),
                 time_taken: ttk.done(),
+                ..Default::default()
             };
         }
         let col = self.db.using(trim_apos(&collection).as_str());
@@ -667,32 +1606,28 @@ impl Query {
                 data: vec![],
                 error: self.err_c(col.err()),
                 time_taken: ttk.done(),
+                ..Default::default()
             };
         }
         let expression = expression.unwrap();
         let col = col.unwrap();
-        let sg = SegQueue::new();
-        let ttk = ExecTime::new();
-        col.iter().for_each(|kv| {
-            let pair = kv.pair();
-            let v = pair.1;
-            let d = expression.calculate(v.string().as_bytes());
-            if d.is_ok() {
-                let d = d.unwrap();
-                if d == flql::exp_parser::Value::Bool(true) {
-                    let k = pair.0;
-                    let _ = block_on(async {
-                        col.delete(k.to_string()).await;
-                        sg.push(k.to_string());
-                    });
-                }
-            }
+        let keys = col.when(trim_apos(&condition).as_str(), |k, v| {
+            expression.matches(v.document()).then(|| k.to_string())
         });
-        let res = sg.into_iter().map(|s| Value::String(s)).collect::<Vec<Value>>();
+        let mut res = Vec::with_capacity(keys.len());
+        let guard = guard::current();
+        for k in keys {
+            if guard.check().is_err() {
+                break;
+            }
+            col.delete(k.clone()).await;
+            res.push(Value::String(k));
+        }
         QueryResult {
             data: res,
             error: FlinchError::None,
             time_taken: ttk.done(),
+            ..Default::default()
         }
     }
 
@@ -704,6 +1639,7 @@ impl Query {
                 data: vec![],
                 error: self.err_c(col.err()),
                 time_taken: ttk.done(),
+                ..Default::default()
             };
         }
         let col = col.unwrap();
@@ -712,6 +1648,51 @@ impl Query {
             data: vec![Value::String(pointer)],
             error: FlinchError::None,
             time_taken: x,
+            ..Default::default()
+        }
+    }
+
+    /// deletes the document holding an `index` value, resolved and deleted atomically
+    pub async fn delete_index(&self, index: String, collection: String) -> QueryResult {
+        let ttk = ExecTime::new();
+        let col = self.db.using(trim_apos(&collection).as_str());
+        if col.is_err() {
+            return QueryResult {
+                data: vec![],
+                error: self.err_c(col.err()),
+                time_taken: ttk.done(),
+                ..Default::default()
+            };
+        }
+        let col = col.unwrap();
+        let pointer = col.delete_index(trim_apos(&index).as_str()).await;
+        QueryResult {
+            data: pointer.into_iter().map(Value::String).collect(),
+            error: FlinchError::None,
+            time_taken: ttk.done(),
+            ..Default::default()
+        }
+    }
+
+    /// deletes the documents of a view
+    pub async fn delete_view(&self, view: String, collection: String) -> QueryResult {
+        let ttk = ExecTime::new();
+        let col = self.db.using(trim_apos(&collection).as_str());
+        if col.is_err() {
+            return QueryResult {
+                data: vec![],
+                error: self.err_c(col.err()),
+                time_taken: ttk.done(),
+                ..Default::default()
+            };
+        }
+        let col = col.unwrap();
+        let deleted = col.delete_by_view(trim_apos(&view).as_str()).await;
+        QueryResult {
+            data: deleted.into_iter().map(Value::String).collect(),
+            error: FlinchError::None,
+            time_taken: ttk.done(),
+            ..Default::default()
         }
     }
 
@@ -723,6 +1704,7 @@ impl Query {
                 data: vec![],
                 error: self.err_c(col.err()),
                 time_taken: ttk.done(),
+                ..Default::default()
             };
         }
         let col = col.unwrap();
@@ -731,45 +1713,62 @@ impl Query {
             data: vec![Value::String(clip)],
             error: FlinchError::None,
             time_taken: x,
+            ..Default::default()
         }
     }
 
-    fn proc(&self, data: &mut Vec<Value>, sort: Option<Sort>) {
-        if let Some(option) = sort {
-            data.par_sort_unstable_by(|kv1, kv2| {
-                let k1 = kv1.get(option.field.as_str());
-                if k1.is_some() {
-                    let k1 = k1.unwrap().clone();
-                    let k2 = kv2.get(option.field.as_str()).unwrap().clone();
-                    let k1 = k1.as_str().unwrap();
-                    let k2 = k2.as_str().unwrap();
-                    return match option.direction {
-                        SortDirection::Asc => k1.cmp(k2),
-                        SortDirection::Desc => k2.cmp(k1),
-                    };
-                }
-                Ordering::Equal
-            });
+    /// pointers sorted by the values of their sort keys then by pointer, so pages never overlap.
+    /// gives the page, the number of documents and the cursor of the next page.
+    /// only the documents of the page are projected, those deleted since the scan are left out
+    fn proc(&self, mut data: Vec<(String, Vec<Value>)>, sort: &[Sort], page: Option<Page>, profile: &mut Profile, col: &Collection<QueryBased>, projection: &Projection) -> (Vec<Value>, usize, Option<String>) {
+        let mut at = Instant::now();
+        if guard::current().check().is_err() {
+            return (vec![], 0, None);
+        }
+        data.par_sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        sort::sort_by(&mut data, sort, |(_, values), i| &values[i]);
+        profile.stage("sort", &mut at);
+        let total = data.len();
+        let (start, limit) = match page {
+            None => (0, total),
+            Some(Page::Offset(offset, limit)) => (offset.min(total), limit),
+            Some(Page::After(cursor, limit)) => {
+                (data.partition_point(|(k, values)| cursor.compare(sort, values, k).is_le()), limit)
+            }
+        };
+        let end = start.saturating_add(limit).min(total);
+        let cursor = (end > start && end < total).then(|| {
+            let (k, values) = &data[end - 1];
+            Cursor::new(sort, values, k).encode()
+        });
+        // the page is held to the limits by the sizes of the stored documents, before any is projected
+        let guard = guard::current();
+        if let Err(err) = guard.fits(end - start, data[start..end].iter().map(|(k, _)| col.size(k))) {
+            guard.stop(err);
+            return (vec![], total, None);
         }
+        let data = data[start..end].par_iter().filter_map(|(k, _)| col.project(k, projection).data).collect();
+        profile.stage("paginate", &mut at);
+        (data, total, cursor)
     }
 
     fn err_c(&self, error: Option<CollectionError>) -> FlinchError {
-        if error.is_some() {
-            let err = error.unwrap();
-            debug!("collection error {} for query {}", &err, &self.current);
-            FlinchError::CollectionError(err)
-        } else {
-            FlinchError::None
+        match error {
+            Some(err) => {
+                debug!("collection error {} for query {}", &err, &self.current);
+                FlinchError::CollectionError(err)
+            }
+            None => FlinchError::None,
         }
     }
 
     fn err_q(&self, error: Option<QueryError>) -> FlinchError {
-        if error.is_some() {
-            let err = error.unwrap();
-            debug!("query error {} for query {}", &err, &self.current);
-            FlinchError::QueryError(err)
-        } else {
-            FlinchError::None
+        match error {
+            Some(err) => {
+                debug!("query error {} for query {}", &err, &self.current);
+                FlinchError::QueryError(err)
+            }
+            None => FlinchError::None,
         }
     }
 
@@ -779,22 +1778,22 @@ impl Query {
     }
 
     fn err_d(&self, error: Option<DocumentError>) -> FlinchError {
-        if error.is_some() {
-            let err = error.unwrap();
-            debug!("document error {} for query {}", &err, &self.current);
-            FlinchError::DocumentError(err)
-        } else {
-            FlinchError::None
+        match error {
+            Some(err) => {
+                debug!("document error {} for query {}", &err, &self.current);
+                FlinchError::DocumentError(err)
+            }
+            None => FlinchError::None,
         }
     }
 
     fn err_i(&self, error: Option<IndexError>) -> FlinchError {
-        if error.is_some() {
-            let err = error.unwrap();
-            debug!("index error {} for query {}", &err, &self.current);
-            FlinchError::IndexError(err)
-        } else {
-            FlinchError::None
+        match error {
+            Some(err) => {
+                debug!("index error {} for query {}", &err, &self.current);
+                FlinchError::IndexError(err)
+            }
+            None => FlinchError::None,
         }
     }
 }
\ No newline at end of file
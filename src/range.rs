@@ -1,4 +1,5 @@
-use std::collections::{BTreeMap, BTreeSet};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::fmt::{Debug, Display};
 use std::hash::Hash;
 use std::ops::Bound;
@@ -10,8 +11,49 @@ use serde::Serialize;
 
 use crate::doc_trait::Document;
 
+/// `RangeKey` orders numbers numerically, before any text
+#[derive(Clone, Debug)]
+pub enum RangeKey {
+    Number(f64),
+    Text(String),
+}
+
+impl From<&str> for RangeKey {
+    fn from(value: &str) -> Self {
+        match value.parse::<f64>() {
+            Ok(n) if n.is_finite() => RangeKey::Number(n),
+            _ => RangeKey::Text(value.to_string()),
+        }
+    }
+}
+
+impl Ord for RangeKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (RangeKey::Number(a), RangeKey::Number(b)) => a.total_cmp(b),
+            (RangeKey::Number(_), RangeKey::Text(_)) => Ordering::Less,
+            (RangeKey::Text(_), RangeKey::Number(_)) => Ordering::Greater,
+            (RangeKey::Text(a), RangeKey::Text(b)) => a.cmp(b),
+        }
+    }
+}
+
+impl PartialOrd for RangeKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for RangeKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for RangeKey {}
+
 pub struct Range<K> {
-    pub tree: DashMap<String, BTreeMap<String, DashSet<K>>>,
+    pub tree: DashMap<String, BTreeMap<RangeKey, DashSet<K>>>,
 }
 
 impl<K> Range<K>
@@ -36,7 +78,7 @@ impl<K> Range<K>
 
     pub fn put<D>(&self, k: &K, v: &D) where D: Document {
         v.fields().into_iter().for_each(|f| {
-            let val = f.value;
+            let val = RangeKey::from(f.value.as_str());
             match self.tree.get_mut(&f.key) {
                 None => {
                     let mut tree = BTreeMap::new();
@@ -63,8 +105,16 @@ impl<K> Range<K>
         trace!("deleting range for key - {}",&k);
         d.fields().into_iter().for_each(|f| {
             if let Some(mut tree) = self.tree.get_mut(&f.key) {
-                if let Some(set) = tree.value_mut().get_mut(&f.value) {
-                    set.insert(k.clone());
+                let val = RangeKey::from(f.value.as_str());
+                let empty = match tree.value_mut().get_mut(&val) {
+                    Some(set) => {
+                        set.remove(k);
+                        set.is_empty()
+                    }
+                    None => false,
+                };
+                if empty {
+                    tree.value_mut().remove(&val);
                 }
             }
         });
@@ -82,7 +132,14 @@ impl<K> Range<K>
         }
     }
 
+    /// keys of `f` values between `from` and `to`, both included.
+    /// numeric bounds compare numerically
     pub fn range(&self, f: &str, from: String, to: String) -> Vec<K> {
+        let from = RangeKey::from(from.as_str());
+        let to = RangeKey::from(to.as_str());
+        if from > to {
+            return vec![];
+        }
         match self.tree.get(f) {
             None => BTreeSet::new(),
             Some(tree) => {
@@ -96,4 +153,21 @@ impl<K> Range<K>
             }
         }.into_iter().collect::<Vec<K>>()
     }
+
+    /// keys of `f` values within the bounds
+    pub fn keys(&self, f: &str, from: Bound<RangeKey>, to: Bound<RangeKey>) -> HashSet<K> {
+        if let (Bound::Included(a) | Bound::Excluded(a), Bound::Included(b) | Bound::Excluded(b)) = (&from, &to) {
+            let inclusive = matches!((&from, &to), (Bound::Included(_), Bound::Included(_)));
+            if a > b || (a == b && !inclusive) {
+                return HashSet::new();
+            }
+        }
+        match self.tree.get(f) {
+            None => HashSet::new(),
+            Some(tree) => tree
+                .range((from, to))
+                .flat_map(|(_, set)| set.iter().map(|k| k.key().clone()).collect::<Vec<K>>())
+                .collect(),
+        }
+    }
 }
@@ -7,6 +7,7 @@ use anyhow::anyhow;
 use dashmap::DashMap;
 use flql::Flql;
 use log::{error, LevelFilter, trace};
+use serde_json::Value;
 use simplelog::{ColorChoice, CombinedLogger, Config, TerminalMode, TermLogger, WriteLogger};
 use size::{Base, Size};
 use sled::Db;
@@ -17,8 +18,10 @@ use crate::doc::QueryBased;
 use crate::errors::DbError;
 use crate::headers::{DbName, DbUser, FlinchCnf, FlinchError, PubSubEvent, QueryResult, SessionId};
 use crate::persistent::Persistent;
+use crate::prepared::Prepared;
 use crate::pri_headers::{FLINCH, INTERNAL_TREE, MAGIC_DB, MAX_DBNAME_LEN, MAX_USERNAME_LEN, MIN_DBNAME_LEN, MIN_PW_LEN, MIN_USERNAME_LEN, PermissionTypes};
 use crate::query::Query;
+use crate::statement::{self, Statement};
 use crate::utils::{cnf_content, database_path, db_name_ok, DBLIST_PREFIX, DBUSER_PREFIX, ExecTime, make_log_path, trim_apos, uuid};
 use crate::zalloc::Zalloc;
 
@@ -100,6 +103,51 @@ impl Schemas {
     }
 
     pub async fn flql(&self, stmt: &str, session_id: SessionId) -> QueryResult {
+        self.run(statement::parse(stmt), session_id).await
+    }
+
+    /// parses a statement with `$1`, `$2` placeholders once. run it with `execute`
+    pub fn prepare(&self, stmt: &str) -> Result<Prepared, FlinchError> {
+        Prepared::new(stmt).map_err(FlinchError::CustomError)
+    }
+
+    /// runs a prepared statement with its placeholders bound to `params`, like `flql`
+    pub async fn execute(&self, prepared: &Prepared, params: &[Value], session_id: SessionId) -> QueryResult {
+        self.run(prepared.bind(params), session_id).await
+    }
+
+    /// runs the `;` separated statements of a script on the database of a session, a result for every statement.
+    /// an `atomic` script stops at its first failed statement and undoes the writes of the statements before it.
+    /// database and user statements cannot run in a script
+    pub async fn script(&self, script: &str, atomic: bool, session_id: SessionId) -> Vec<QueryResult> {
+        let ttk = ExecTime::new();
+        let statements = statement::split(script);
+        let failed = |error: FlinchError| statements.iter().map(|_| QueryResult {
+            data: vec![],
+            error: error.clone(),
+            time_taken: ttk.done(),
+            ..Default::default()
+        }).collect::<Vec<QueryResult>>();
+        let Some(user) = self.auth.user(session_id.clone()) else {
+            return failed(FlinchError::SchemaError(DbError::NoSession));
+        };
+        let Some(db) = self.dbs.get(trim_apos(&user.db).as_str()) else {
+            return failed(FlinchError::SchemaError(DbError::DbNotExists(user.clone().db)));
+        };
+        let parsed = statements.iter().map(|stmt| {
+            let parsed = statement::parse(stmt).map_err(FlinchError::CustomError)?;
+            match parsed {
+                Statement::Flql(Flql::DbNew(_) | Flql::DbDrop(_) | Flql::DbPerm(_) | Flql::DropUser(_, _)) => {
+                    Err(FlinchError::CustomError("database and user statements cannot run in a script".to_string()))
+                }
+                _ if !self.permitted(&session_id, &user, &parsed) => Err(FlinchError::SchemaError(DbError::UserNoPermission)),
+                _ => Ok(parsed),
+            }
+        }).collect::<Vec<Result<Statement, FlinchError>>>();
+        db.value().exec_script(parsed, atomic).await
+    }
+
+    async fn run(&self, parsed: Result<Statement, String>, session_id: SessionId) -> QueryResult {
         let ttk = ExecTime::new();
         let user = self.auth.user(session_id.clone());
         if user.is_none() {
@@ -107,16 +155,17 @@ impl Schemas {
                 data: vec![],
                 error: FlinchError::SchemaError(DbError::NoSession),
                 time_taken: ttk.done(),
+                ..Default::default()
             };
         }
         let user = user.unwrap();
 
-        let parsed = flql::parse(stmt);
         if parsed.is_err() {
             return QueryResult {
                 data: vec![],
                 error: FlinchError::CustomError(parsed.err().unwrap()),
                 time_taken: ttk.done(),
+                ..Default::default()
             };
         }
 
@@ -126,47 +175,22 @@ impl Schemas {
                 data: vec![],
                 error: FlinchError::SchemaError(DbError::DbNotExists(user.clone().db)),
                 time_taken: ttk.done(),
+                ..Default::default()
             };
         }
 
         let parsed = parsed.unwrap();
-        let permitted = match parsed {
-            Flql::DbNew(_) => user.db.eq(MAGIC_DB),
-            Flql::DbPerm(_) => self.auth.chk_permission(session_id, PermissionTypes::AssignUser),
-            Flql::DbDrop(_) => user.db.eq(MAGIC_DB),
-            Flql::New(_) => self.auth.chk_permission(session_id, PermissionTypes::CreateCollection),
-            Flql::Drop(_) => self.auth.chk_permission(session_id, PermissionTypes::DropCollection),
-            Flql::DropUser(_, _) => self.auth.chk_permission(session_id, PermissionTypes::AssignUser),
-            Flql::Exists(_, _) => self.auth.chk_permission(session_id, PermissionTypes::Read),
-            Flql::Length(_) => self.auth.chk_permission(session_id, PermissionTypes::Read),
-            Flql::Flush(_) => self.auth.chk_permission(session_id, PermissionTypes::Flush),
-            Flql::Ttl(_, _, _) => self.auth.chk_permission(session_id, PermissionTypes::Write),
-            Flql::Put(_, _) => self.auth.chk_permission(session_id, PermissionTypes::Write),
-            Flql::PutWhen(_, _, _) => self.auth.chk_permission(session_id, PermissionTypes::Write),
-            Flql::PutPointer(_, _, _) => self.auth.chk_permission(session_id, PermissionTypes::Write),
-            Flql::SearchTyping(_, _) => self.auth.chk_permission(session_id, PermissionTypes::Read),
-            Flql::Get(_, _, _) => self.auth.chk_permission(session_id, PermissionTypes::Read),
-            Flql::GetWhen(_, _, _, _) => self.auth.chk_permission(session_id, PermissionTypes::Read),
-            Flql::GetPointer(_, _) => self.auth.chk_permission(session_id, PermissionTypes::Read),
-            Flql::GetView(_, _) => self.auth.chk_permission(session_id, PermissionTypes::Read),
-            Flql::GetClip(_, _) => self.auth.chk_permission(session_id, PermissionTypes::Read),
-            Flql::GetIndex(_, _) => self.auth.chk_permission(session_id, PermissionTypes::Read),
-            Flql::GetRange(_, _, _, _) => self.auth.chk_permission(session_id, PermissionTypes::Read),
-            Flql::Delete(_) => self.auth.chk_permission(session_id, PermissionTypes::Write),
-            Flql::DeleteWhen(_, _) => self.auth.chk_permission(session_id, PermissionTypes::Write),
-            Flql::DeletePointer(_, _) => self.auth.chk_permission(session_id, PermissionTypes::Write),
-            Flql::DeleteClip(_, _) => self.auth.chk_permission(session_id, PermissionTypes::Write),
-            Flql::None => false
-        };
+        let permitted = self.permitted(&session_id, &user, &parsed);
         if !permitted {
             return QueryResult {
                 data: vec![],
                 error: FlinchError::SchemaError(DbError::UserNoPermission),
                 time_taken: ttk.done(),
+                ..Default::default()
             };
         }
         match parsed {
-            Flql::DbNew(permit) => {
+            Statement::Flql(Flql::DbNew(permit)) => {
                 trace!("creating new database {:?}",&permit);
                 let permit = self.convert_permit(permit.as_str());
                 if permit.is_err() {
@@ -174,6 +198,7 @@ impl Schemas {
                         data: vec![],
                         error: FlinchError::SchemaError(permit.err().unwrap()),
                         time_taken: ttk.done(),
+                        ..Default::default()
                     };
                 }
                 let permit = permit.unwrap();
@@ -188,9 +213,10 @@ impl Schemas {
                         Err(_) => FlinchError::SchemaError(res.err().unwrap()),
                     },
                     time_taken: ttk.done(),
+                    ..Default::default()
                 }
             }
-            Flql::DbDrop(db) => {
+            Statement::Flql(Flql::DbDrop(db)) => {
                 let res = self.drop(trim_apos(&db).as_str()).await;
                 QueryResult {
                     data: vec![],
@@ -199,9 +225,10 @@ impl Schemas {
                         Err(_) => FlinchError::SchemaError(res.err().unwrap()),
                     },
                     time_taken: ttk.done(),
+                    ..Default::default()
                 }
             }
-            Flql::DbPerm(permit) => {
+            Statement::Flql(Flql::DbPerm(permit)) => {
                 let res = self.new_user(permit.as_str());
                 QueryResult {
                     data: vec![],
@@ -210,9 +237,10 @@ impl Schemas {
                         Err(_) => FlinchError::SchemaError(res.err().unwrap()),
                     },
                     time_taken: ttk.done(),
+                    ..Default::default()
                 }
             }
-            Flql::DropUser(db, user) => {
+            Statement::Flql(Flql::DropUser(db, user)) => {
                 let res = self.auth.drop_user(trim_apos(&db).as_str(), trim_apos(&user).as_str());
                 QueryResult {
                     data: vec![],
@@ -221,13 +249,73 @@ impl Schemas {
                         Err(_) => FlinchError::SchemaError(res.err().unwrap()),
                     },
                     time_taken: ttk.done(),
+                    ..Default::default()
                 }
             }
             _ => {
                 let db = db.unwrap();
                 let db = db.value();
-                db.exec_with_flql(parsed).await
+                db.exec_with_statement(parsed).await
+            }
+        }
+    }
+
+    /// whether the user of a session may run a statement. explained and profiled
+    /// statements need the permission of the statement they wrap
+    fn permitted(&self, session_id: &SessionId, user: &DbUser, parsed: &Statement) -> bool {
+        match parsed {
+            Statement::Contains(_, _, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Read),
+            Statement::Near(_, _, _, _, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Read),
+            Statement::WithinBox(_, _, _, _, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Read),
+            Statement::Knn(_, _, _, _, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Read),
+            Statement::Project(_, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Read),
+            Statement::Lookup(_, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Read),
+            Statement::Aggregate(_, _, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Read),
+            Statement::Clips(_, _, _, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Read),
+            Statement::Views(_) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Read),
+            Statement::RebuildViews(_) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Write),
+            Statement::DropView(_, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::DropCollection),
+            Statement::DeleteView(_, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Write),
+            Statement::PutIndex(_, _, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Write),
+            Statement::DeleteIndex(_, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Write),
+            Statement::ExistsIndex(_, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Read),
+            Statement::TtlIndex(_, _, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Write),
+            Statement::Count(_, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Read),
+            Statement::ExistsWhen(_, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Read),
+            Statement::Distinct(_, _, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Read),
+            Statement::Facets(_, _, _, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Read),
+            Statement::OnConflict(parsed, _) => self.permitted(session_id, user, parsed),
+            Statement::Explain(stmt) | Statement::Profile(stmt) => {
+                statement::parse(format!("{};", stmt).as_str()).is_ok_and(|parsed| self.permitted(session_id, user, &parsed))
             }
+            Statement::Flql(flql) => match flql {
+                Flql::DbNew(_) => user.db.eq(MAGIC_DB),
+                Flql::DbPerm(_) => self.auth.chk_permission(session_id.clone(), PermissionTypes::AssignUser),
+                Flql::DbDrop(_) => user.db.eq(MAGIC_DB),
+                Flql::New(_) => self.auth.chk_permission(session_id.clone(), PermissionTypes::CreateCollection),
+                Flql::Drop(_) => self.auth.chk_permission(session_id.clone(), PermissionTypes::DropCollection),
+                Flql::DropUser(_, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::AssignUser),
+                Flql::Exists(_, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Read),
+                Flql::Length(_) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Read),
+                Flql::Flush(_) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Flush),
+                Flql::Ttl(_, _, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Write),
+                Flql::Put(_, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Write),
+                Flql::PutWhen(_, _, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Write),
+                Flql::PutPointer(_, _, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Write),
+                Flql::SearchTyping(_, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Read),
+                Flql::Get(_, _, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Read),
+                Flql::GetWhen(_, _, _, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Read),
+                Flql::GetPointer(_, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Read),
+                Flql::GetView(_, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Read),
+                Flql::GetClip(_, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Read),
+                Flql::GetIndex(_, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Read),
+                Flql::GetRange(_, _, _, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Read),
+                Flql::Delete(_) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Write),
+                Flql::DeleteWhen(_, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Write),
+                Flql::DeletePointer(_, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Write),
+                Flql::DeleteClip(_, _) => self.auth.chk_permission(session_id.clone(), PermissionTypes::Write),
+                Flql::None => false
+            },
         }
     }
 
@@ -329,6 +417,7 @@ impl Schemas {
                 data: vec![],
                 error: FlinchError::SchemaError(DbError::NoSession),
                 time_taken: ttk.done(),
+                ..Default::default()
             });
         }
         let user = user.unwrap();
@@ -339,6 +428,7 @@ impl Schemas {
                 data: vec![],
                 error: FlinchError::SchemaError(DbError::DbNotExists(user.clone().db)),
                 time_taken: ttk.done(),
+                ..Default::default()
             });
         }
         let db = db.unwrap();
@@ -349,6 +439,7 @@ impl Schemas {
                 data: vec![],
                 error: res.err().unwrap(),
                 time_taken: ttk.done(),
+                ..Default::default()
             });
         }
         Ok(())
@@ -0,0 +1,152 @@
+use std::cmp::Ordering;
+
+use chrono::{DateTime, Utc};
+use rayon::prelude::*;
+use serde_json::Value;
+
+use crate::headers::{Nulls, Sort, SortDirection};
+
+/// a sortable value. types order as they are listed, nulls are placed by the sort
+enum Key<'a> {
+    Null,
+    Bool(bool),
+    Num(f64),
+    Date(DateTime<Utc>),
+    Str(&'a str),
+    Array,
+    Object,
+}
+
+impl<'a> Key<'a> {
+    fn of(v: &'a Value) -> Self {
+        match v {
+            Value::Null => Key::Null,
+            Value::Bool(b) => Key::Bool(*b),
+            Value::Number(n) => Key::Num(n.as_f64().unwrap_or_default()),
+            // strings holding only a number are not taken for timestamps
+            Value::String(s) if s.parse::<f64>().is_err() => match anydate::parse_utc(s) {
+                Ok(dt) => Key::Date(dt),
+                Err(_) => Key::Str(s),
+            },
+            Value::String(s) => Key::Str(s),
+            Value::Array(_) => Key::Array,
+            Value::Object(_) => Key::Object,
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            Key::Null => 0,
+            Key::Bool(_) => 1,
+            Key::Num(_) => 2,
+            Key::Date(_) => 3,
+            Key::Str(_) => 4,
+            Key::Array => 5,
+            Key::Object => 6,
+        }
+    }
+
+    /// a total order. values of different types order by type
+    fn order(&self, other: &Key) -> Ordering {
+        match (self, other) {
+            (Key::Bool(a), Key::Bool(b)) => a.cmp(b),
+            (Key::Num(a), Key::Num(b)) => a.total_cmp(b),
+            (Key::Date(a), Key::Date(b)) => a.cmp(b),
+            (Key::Str(a), Key::Str(b)) => a.cmp(b),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+/// sorts documents by every sort key in turn. documents missing a key count as null.
+/// equal documents keep their order
+pub(crate) fn sort(data: &mut Vec<Value>, sort: &[Sort]) {
+    sort_by(data, sort, |d, i| lookup(d, &sort[i].field));
+}
+
+/// sorts by the value of every sort key, `value` gives the value of the `i`th key of an item
+pub(crate) fn sort_by<T, F>(data: &mut Vec<T>, sort: &[Sort], value: F)
+    where T: Send + Sync, F: Fn(&T, usize) -> &Value + Sync
+{
+    if sort.is_empty() || data.len() < 2 {
+        return;
+    }
+    let mut order = {
+        let keys = data
+            .par_iter()
+            .map(|d| (0..sort.len()).map(|i| Key::of(value(d, i))).collect::<Vec<Key>>())
+            .collect::<Vec<Vec<Key>>>();
+        let mut order = (0..data.len()).collect::<Vec<usize>>();
+        order.par_sort_by(|a, b| compare_keys(&keys[*a], &keys[*b], sort));
+        order
+    };
+    let mut docs = std::mem::take(data).into_iter().map(Some).collect::<Vec<Option<T>>>();
+    data.extend(order.drain(..).filter_map(|i| docs[i].take()));
+}
+
+/// the values of the sort keys of a document
+pub(crate) fn values(doc: &Value, sort: &[Sort]) -> Vec<Value> {
+    sort.iter().map(|s| lookup(doc, &s.field).clone()).collect()
+}
+
+/// order of two documents by the values of their sort keys
+pub(crate) fn compare_values(a: &[Value], b: &[Value], sort: &[Sort]) -> Ordering {
+    let (a, b) = (a.iter().map(Key::of).collect::<Vec<Key>>(), b.iter().map(Key::of).collect::<Vec<Key>>());
+    compare_keys(&a, &b, sort)
+}
+
+/// typed order of two values, nulls first
+pub(crate) fn compare(a: &Value, b: &Value) -> Ordering {
+    Key::of(a).order(&Key::of(b))
+}
+
+/// the value at a dotted path, null when missing. a key holding dots is matched as a whole first,
+/// numeric steps index arrays
+pub(crate) fn lookup<'a>(doc: &'a Value, path: &str) -> &'a Value {
+    let next = match doc {
+        Value::Object(obj) => match obj.get(path) {
+            Some(v) => return v,
+            None => path.split_once('.').and_then(|(step, rest)| obj.get(step).map(|v| (v, rest))),
+        },
+        Value::Array(arr) => {
+            let (step, rest) = path.split_once('.').unwrap_or((path, ""));
+            match step.parse::<usize>().ok().and_then(|i| arr.get(i)) {
+                Some(v) if rest.is_empty() => return v,
+                Some(v) => Some((v, rest)),
+                None => None,
+            }
+        }
+        _ => None,
+    };
+    match next {
+        Some((v, rest)) => lookup(v, rest),
+        None => &Value::Null,
+    }
+}
+
+fn compare_keys(a: &[Key], b: &[Key], sort: &[Sort]) -> Ordering {
+    sort.iter()
+        .enumerate()
+        .map(|(i, s)| compare_key(&a[i], &b[i], s))
+        .find(|o| o.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+fn compare_key(a: &Key, b: &Key, sort: &Sort) -> Ordering {
+    let nulls_first = match sort.nulls {
+        Some(Nulls::First) => true,
+        Some(Nulls::Last) => false,
+        None => sort.direction == SortDirection::Asc,
+    };
+    match (a, b) {
+        (Key::Null, Key::Null) => Ordering::Equal,
+        (Key::Null, _) if nulls_first => Ordering::Less,
+        (Key::Null, _) => Ordering::Greater,
+        (_, Key::Null) if nulls_first => Ordering::Greater,
+        (_, Key::Null) => Ordering::Less,
+        _ => match sort.direction {
+            SortDirection::Asc => a.order(b),
+            SortDirection::Desc => a.order(b).reverse(),
+        },
+    }
+}
@@ -0,0 +1,490 @@
+use flql::Flql;
+
+use crate::aggregate::Aggregation;
+use crate::database::Conflict;
+use crate::doc_trait::Point;
+use crate::headers::{ClipSet, ClipSets};
+use crate::lookup::Lookup;
+use crate::projection::Projection;
+use crate::utils::{parse_sort, trim_apos};
+
+/// `Statement` is a parsed statement.
+/// `flql` parses the core language. statements it does not know are parsed by flinch.
+/// arguments keep their quotes, like `Flql` does
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Flql(Flql),
+    /// `search.contains('x42').from('col')` or `search.contains('x42').on('sku').from('col')`
+    Contains(String, Option<String>, String),
+    /// `get.near('field', lat, lon, meters).from('col')` optionally followed by `.limit(n)`
+    Near(String, Point, f64, Option<usize>, String),
+    /// `get.within_box('field', sw_lat, sw_lon, ne_lat, ne_lon).from('col')` optionally followed by `.limit(n)`
+    WithinBox(String, Point, Point, Option<usize>, String),
+    /// `get.knn('field', [0.1, 0.2], k).from('col')` optionally filtered by `.when('condition')` before `.from`
+    Knn(String, Vec<f32>, usize, Option<String>, String),
+    /// a `get` statement followed by `.select('path', 'path AS alias')` and/or `.exclude('path')`
+    Project(Box<Statement>, Projection),
+    /// a `get` statement followed by `.lookup('col', 'local', 'foreign', 'alias')` embedding every match
+    /// and/or `.lookup_one(...)` embedding the first. `foreign` is `_pointer` to match pointers,
+    /// the alias defaults to the collection. projections apply after the lookups
+    Lookup(Box<Statement>, Vec<Lookup>),
+    /// `explain(statement)` plans a statement without running it. the statement is kept as text
+    Explain(String),
+    /// `profile(statement)` runs a statement and reports the time of every stage
+    Profile(String),
+    /// `aggregate('count', 'sum(total) AS revenue').by('status', 'day(created)').when('condition').from('col')`.
+    /// `by` and `when` are optional, `.sort('revenue', 'DESC')` and `.limit(n)` may follow `.from`
+    Aggregate(Aggregation, Option<String>, String),
+    /// `count.when('condition').from('col')` counts the matching documents, `count.from('col')` all of them
+    Count(Option<String>, String),
+    /// `exists.when('condition').from('col')` whether a document matches
+    ExistsWhen(String, String),
+    /// `distinct('field').when('condition').from('col')` the distinct values of a field. `when` is optional
+    Distinct(String, Option<String>, String),
+    /// `facets('brand', 'color').when('condition').from('col')` the top values of clip fields with their counts.
+    /// `when` is optional, `.limit(n)` may follow `.from`, ten values a field without it
+    Facets(Vec<String>, Option<String>, String, Option<usize>),
+    /// `put(doc).index('value').into('col')` puts a document in place of the one holding an index value
+    PutIndex(String, String, String),
+    /// `delete.index('value').from('col')`
+    DeleteIndex(String, String),
+    /// `exists.index('value').from('col')`
+    ExistsIndex(String, String),
+    /// `ttl(seconds).index('value').into('col')`
+    TtlIndex(String, String, String),
+    /// `views('col')` lists the views of a collection with their sizes
+    Views(String),
+    /// `rebuild.views('col')` puts every document in the views it belongs to now
+    RebuildViews(String),
+    /// `drop.view('name').from('col')` removes a view from the collection
+    DropView(String, String),
+    /// `delete.view('name').from('col')` deletes the documents of a view
+    DeleteView(String, String),
+    /// `get.clips.all('acme', view('SALE')).any('red', 'blue').none('used').from('col')` the documents
+    /// in every clip of `all`, in a clip of `any` and in no clip of `none`. `view('name')` is a view.
+    /// every link but `from` is optional, `.sort(...)` and `.page(...)` may follow it like on `get`
+    Clips(ClipSets, String, Option<String>, Option<String>),
+    /// a `put` statement followed by `.on_conflict('reject')`, `.on_conflict('replace')` or `.on_conflict('merge')`,
+    /// resolving collisions on unique indexes other than the collection does
+    OnConflict(Box<Statement>, Conflict),
+}
+
+/// a `name(args)` link of a statement chain. `args` is empty when there are no parenthesis
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Call {
+    pub name: String,
+    pub args: Vec<String>,
+    /// char offset of `name` in the trimmed statement
+    pub start: usize,
+}
+
+/// parses a single statement
+pub fn parse(stmt: &str) -> Result<Statement, String> {
+    for (name, wrap) in [("explain", Statement::Explain as fn(String) -> Statement), ("profile", Statement::Profile)] {
+        let inner = trimmed(stmt).strip_prefix(name).map(str::trim_start).and_then(|s| s.strip_prefix('(')?.strip_suffix(')'));
+        if let Some(inner) = inner {
+            let inner = inner.trim().trim_end_matches(';').trim_end();
+            parse(format!("{};", inner).as_str())?;
+            return Ok(wrap(inner.to_string()));
+        }
+    }
+    if let Some(calls) = chain(stmt) {
+        let names = calls.iter().map(|c| c.name.as_str()).collect::<Vec<&str>>();
+        let projected = names.iter().rev().take_while(|n| matches!(**n, "select" | "exclude")).count();
+        if names.first() == Some(&"get") && projected > 0 && projected < names.len() {
+            let links = &calls[calls.len() - projected..];
+            let include = links.iter().filter(|c| c.name == "select").flat_map(|c| c.args.iter().map(trim_apos)).collect::<Vec<String>>();
+            let exclude = links.iter().filter(|c| c.name == "exclude").flat_map(|c| c.args.iter().map(trim_apos)).collect::<Vec<String>>();
+            let stmt = trimmed(stmt).chars().take(links[0].start).collect::<String>();
+            let inner = parse(format!("{};", stmt.trim_end().trim_end_matches('.')).as_str())?;
+            return Ok(Statement::Project(Box::new(inner), Projection::new(&include, &exclude)?));
+        }
+        let joined = names.iter().rev().take_while(|n| matches!(**n, "lookup" | "lookup_one")).count();
+        if names.first() == Some(&"get") && joined > 0 && joined < names.len() {
+            let links = &calls[calls.len() - joined..];
+            let lookups = links.iter().map(join).collect::<Result<Vec<Lookup>, String>>()?;
+            let stmt = trimmed(stmt).chars().take(links[0].start).collect::<String>();
+            let inner = parse(format!("{};", stmt.trim_end().trim_end_matches('.')).as_str())?;
+            return Ok(Statement::Lookup(Box::new(inner), lookups));
+        }
+        if names.first() == Some(&"put") && names.len() > 1 && names.last() == Some(&"on_conflict") {
+            let link = &calls[calls.len() - 1];
+            let stmt = trimmed(stmt).chars().take(link.start).collect::<String>();
+            let inner = parse(format!("{};", stmt.trim_end().trim_end_matches('.')).as_str())?;
+            if !matches!(inner, Statement::Flql(Flql::Put(_, _) | Flql::PutWhen(_, _, _) | Flql::PutPointer(_, _, _)) | Statement::PutIndex(_, _, _)) {
+                return Err("on_conflict only follows a put statement".to_string());
+            }
+            return Ok(Statement::OnConflict(Box::new(inner), conflict(link)?));
+        }
+        if names.first() == Some(&"aggregate") {
+            return aggregate(&calls);
+        }
+        if names.starts_with(&["get", "clips"]) && calls[1].args.is_empty() {
+            return clip_sets(&calls);
+        }
+        match names.as_slice() {
+            // sort clauses flql does not know, with several keys or nulls placement, and pages after a cursor
+            ["get", "from", "sort", "page"] if !flql_sort(&calls[2]) || after(&calls[3]) => {
+                return Ok(Statement::Flql(Flql::Get(arg(&calls[1], 0)?, sorting(&calls[2])?, paging(&calls[3])?)));
+            }
+            ["get", "when", "from", "sort", "page"] if !flql_sort(&calls[3]) || after(&calls[4]) => {
+                let (condition, collection) = (arg(&calls[1], 0)?, arg(&calls[2], 0)?);
+                return Ok(Statement::Flql(Flql::GetWhen(condition, collection, sorting(&calls[3])?, paging(&calls[4])?)));
+            }
+            ["count", "from"] => {
+                return Ok(Statement::Count(None, arg(&calls[1], 0)?));
+            }
+            ["count", "when", "from"] => {
+                return Ok(Statement::Count(Some(arg(&calls[1], 0)?), arg(&calls[2], 0)?));
+            }
+            ["exists", "when", "from"] => {
+                return Ok(Statement::ExistsWhen(arg(&calls[1], 0)?, arg(&calls[2], 0)?));
+            }
+            ["distinct", "from"] => {
+                return Ok(Statement::Distinct(arg(&calls[0], 0)?, None, arg(&calls[1], 0)?));
+            }
+            ["distinct", "when", "from"] => {
+                return Ok(Statement::Distinct(arg(&calls[0], 0)?, Some(arg(&calls[1], 0)?), arg(&calls[2], 0)?));
+            }
+            ["facets", "from"] | ["facets", "from", "limit"] => {
+                let limit = calls.get(2).map(|c| count(c, 0)).transpose()?;
+                return Ok(Statement::Facets(fields(&calls[0])?, None, arg(&calls[1], 0)?, limit));
+            }
+            ["facets", "when", "from"] | ["facets", "when", "from", "limit"] => {
+                let limit = calls.get(3).map(|c| count(c, 0)).transpose()?;
+                return Ok(Statement::Facets(fields(&calls[0])?, Some(arg(&calls[1], 0)?), arg(&calls[2], 0)?, limit));
+            }
+            ["views"] => {
+                return Ok(Statement::Views(arg(&calls[0], 0)?));
+            }
+            ["rebuild", "views"] => {
+                return Ok(Statement::RebuildViews(arg(&calls[1], 0)?));
+            }
+            ["drop", "view", "from"] => {
+                return Ok(Statement::DropView(arg(&calls[1], 0)?, arg(&calls[2], 0)?));
+            }
+            ["delete", "view", "from"] => {
+                return Ok(Statement::DeleteView(arg(&calls[1], 0)?, arg(&calls[2], 0)?));
+            }
+            ["put", "index", "into"] => {
+                return Ok(Statement::PutIndex(arg(&calls[0], 0)?, arg(&calls[1], 0)?, arg(&calls[2], 0)?));
+            }
+            ["delete", "index", "from"] => {
+                return Ok(Statement::DeleteIndex(arg(&calls[1], 0)?, arg(&calls[2], 0)?));
+            }
+            ["exists", "index", "from"] => {
+                return Ok(Statement::ExistsIndex(arg(&calls[1], 0)?, arg(&calls[2], 0)?));
+            }
+            ["ttl", "index", "into"] => {
+                return Ok(Statement::TtlIndex(arg(&calls[0], 0)?, arg(&calls[1], 0)?, arg(&calls[2], 0)?));
+            }
+            ["search", "contains", "from"] => {
+                return Ok(Statement::Contains(arg(&calls[1], 0)?, None, arg(&calls[2], 0)?));
+            }
+            ["search", "contains", "on", "from"] => {
+                return Ok(Statement::Contains(arg(&calls[1], 0)?, Some(arg(&calls[2], 0)?), arg(&calls[3], 0)?));
+            }
+            ["get", "near", "from"] | ["get", "near", "from", "limit"] => {
+                let near = &calls[1];
+                let point = Point { lat: num(near, 1)?, lon: num(near, 2)? };
+                let limit = calls.get(3).map(|c| count(c, 0)).transpose()?;
+                return Ok(Statement::Near(arg(near, 0)?, point, num(near, 3)?, limit, arg(&calls[2], 0)?));
+            }
+            ["get", "within_box", "from"] | ["get", "within_box", "from", "limit"] => {
+                let within = &calls[1];
+                let sw = Point { lat: num(within, 1)?, lon: num(within, 2)? };
+                let ne = Point { lat: num(within, 3)?, lon: num(within, 4)? };
+                let limit = calls.get(3).map(|c| count(c, 0)).transpose()?;
+                return Ok(Statement::WithinBox(arg(within, 0)?, sw, ne, limit, arg(&calls[2], 0)?));
+            }
+            ["get", "knn", "from"] => {
+                let knn = &calls[1];
+                return Ok(Statement::Knn(arg(knn, 0)?, vector(knn, 1)?, count(knn, 2)?, None, arg(&calls[2], 0)?));
+            }
+            ["get", "knn", "when", "from"] => {
+                let knn = &calls[1];
+                return Ok(Statement::Knn(arg(knn, 0)?, vector(knn, 1)?, count(knn, 2)?, Some(arg(&calls[2], 0)?), arg(&calls[3], 0)?));
+            }
+            _ => {}
+        }
+    }
+    flql::parse(stmt).map(Statement::Flql)
+}
+
+/// splits a script into its statements on the `;` outside quotes and brackets. every statement keeps its `;`
+pub fn split(script: &str) -> Vec<String> {
+    let chars = script.chars().collect::<Vec<char>>();
+    let mut statements = vec![];
+    let (mut start, mut depth, mut i) = (0, 0, 0);
+    while i < chars.len() {
+        match chars[i] {
+            '\'' | '"' => i = skip_quoted(&chars, i).unwrap_or(chars.len()),
+            '(' | '{' | '[' => depth += 1,
+            ')' | '}' | ']' => depth -= 1,
+            ';' if depth == 0 => {
+                statements.push(chars[start..i].iter().collect::<String>());
+                start = i + 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    statements.push(chars[start.min(chars.len())..].iter().collect::<String>());
+    statements.iter().map(|s| s.trim()).filter(|s| !s.is_empty()).map(|s| format!("{};", s)).collect()
+}
+
+/// the links of an aggregate statement, in their order
+fn aggregate(calls: &[Call]) -> Result<Statement, String> {
+    let args = |call: &Call| call.args.iter().map(trim_apos).collect::<Vec<String>>();
+    let mut links = calls[1..].iter().peekable();
+    let group_by = links.next_if(|c| c.name == "by").map(args).unwrap_or_default();
+    let condition = links.next_if(|c| c.name == "when").map(|c| arg(c, 0)).transpose()?;
+    let collection = match links.next() {
+        Some(call) if call.name == "from" => arg(call, 0)?,
+        _ => return Err("aggregate expects `.from('collection')`".to_string()),
+    };
+    let mut aggregation = Aggregation::new(&args(&calls[0]), &group_by)?;
+    if let Some(sort) = links.next_if(|c| c.name == "sort") {
+        aggregation.sort = parse_sort(Some(sort.args.join(",")))?;
+    }
+    if let Some(limit) = links.next_if(|c| c.name == "limit") {
+        aggregation.limit = Some(count(limit, 0)?);
+    }
+    match links.next() {
+        Some(call) => Err(format!("unexpected `{}` in aggregate", call.name)),
+        None => Ok(Statement::Aggregate(aggregation, condition, collection)),
+    }
+}
+
+/// `get.clips` followed by `all`, `any` and `none` links, `from`, and an optional sort and page
+fn clip_sets(calls: &[Call]) -> Result<Statement, String> {
+    let mut sets = ClipSets::default();
+    let mut links = calls[2..].iter().peekable();
+    while let Some(call) = links.next_if(|c| matches!(c.name.as_str(), "all" | "any" | "none")) {
+        let named = call.args.iter().map(|arg| clip_set(arg)).collect::<Vec<ClipSet>>();
+        if named.is_empty() {
+            return Err(format!("{} expects clip names", call.name));
+        }
+        match call.name.as_str() {
+            "all" => sets.all.extend(named),
+            "any" => sets.any.extend(named),
+            _ => sets.none.extend(named),
+        }
+    }
+    let collection = match links.next() {
+        Some(call) if call.name == "from" => arg(call, 0)?,
+        _ => return Err("get.clips expects `.from('collection')`".to_string()),
+    };
+    let sort = links.next_if(|c| c.name == "sort").map(sorting).transpose()?.flatten();
+    let page = links.next_if(|c| c.name == "page").map(paging).transpose()?.flatten();
+    match links.next() {
+        Some(call) => Err(format!("unexpected `{}` in get.clips", call.name)),
+        None => Ok(Statement::Clips(sets, collection, sort, page)),
+    }
+}
+
+/// `'name'` is a clip, `view('name')` a view. names keep their quotes
+fn clip_set(arg: &str) -> ClipSet {
+    match arg.strip_prefix("view").map(str::trim_start).and_then(|s| s.strip_prefix('(')?.strip_suffix(')')) {
+        Some(view) => ClipSet::View(view.trim().to_string()),
+        None => ClipSet::Clip(arg.to_string()),
+    }
+}
+
+/// `lookup('col', 'local', 'foreign')` with an optional alias
+fn join(call: &Call) -> Result<Lookup, String> {
+    if !(3..=4).contains(&call.args.len()) {
+        return Err(format!("{} expects `'collection', 'local', 'foreign'` and an optional alias", call.name));
+    }
+    let collection = trim_apos(&arg(call, 0)?);
+    let alias = call.args.get(3).map(trim_apos).unwrap_or_else(|| collection.to_string());
+    Lookup::new(&collection, &trim_apos(&arg(call, 1)?), &trim_apos(&arg(call, 2)?), &alias, call.name == "lookup")
+}
+
+/// `on_conflict('reject')`, `on_conflict('replace')` or `on_conflict('merge')`
+fn conflict(call: &Call) -> Result<Conflict, String> {
+    match (call.args.len(), trim_apos(&arg(call, 0)?).to_lowercase().as_str()) {
+        (1, "reject") => Ok(Conflict::Reject),
+        (1, "replace") => Ok(Conflict::Replace),
+        (1, "merge") => Ok(Conflict::Merge),
+        _ => Err("on_conflict expects 'reject', 'replace' or 'merge'".to_string()),
+    }
+}
+
+/// `sort(null)` or `sort('field','ASC')`
+fn flql_sort(call: &Call) -> bool {
+    match call.args.as_slice() {
+        [null] => null == "null",
+        [_, direction] => direction == "'ASC'" || direction == "'DESC'",
+        _ => false,
+    }
+}
+
+/// sort keys, kept as text like flql does
+fn sorting(call: &Call) -> Result<Option<String>, String> {
+    if flql_sort(call) && call.args.len() == 1 {
+        return Ok(None);
+    }
+    let sort = call.args.join(",");
+    parse_sort(Some(sort.to_string()))?;
+    Ok(Some(sort))
+}
+
+/// `page('cursor', limit)`
+fn after(call: &Call) -> bool {
+    call.args.first().is_some_and(|start| start.starts_with('\''))
+}
+
+/// `page(null)`, `page(offset, limit)` or `page('cursor', limit)`, kept as text like flql does
+fn paging(call: &Call) -> Result<Option<String>, String> {
+    match call.args.as_slice() {
+        [null] if null == "null" => Ok(None),
+        [cursor, _] if after(call) => Ok(Some(format!("{},{}", cursor, count(call, 1)?))),
+        [_, _] => Ok(Some(format!("{},{}", count(call, 0)?, count(call, 1)?))),
+        _ => Err("page expects `null`, `offset, limit` or `'cursor', limit`".to_string()),
+    }
+}
+
+/// at least one field, names keep their quotes
+fn fields(call: &Call) -> Result<Vec<String>, String> {
+    match call.args.is_empty() {
+        true => Err(format!("{} expects field names", call.name)),
+        false => Ok(call.args.clone()),
+    }
+}
+
+fn num(call: &Call, i: usize) -> Result<f64, String> {
+    let value = arg(call, i)?;
+    match value.parse::<f64>() {
+        Ok(n) if n.is_finite() => Ok(n),
+        _ => Err(format!("argument {} of {} is not a number: {}", i + 1, call.name, value)),
+    }
+}
+
+fn vector(call: &Call, i: usize) -> Result<Vec<f32>, String> {
+    let value = arg(call, i)?;
+    serde_json::from_str::<Vec<f32>>(value.as_str())
+        .map_err(|_| format!("argument {} of {} is not an array of numbers: {}", i + 1, call.name, value))
+}
+
+fn count(call: &Call, i: usize) -> Result<usize, String> {
+    let value = arg(call, i)?;
+    value.parse::<usize>().map_err(|_| format!("argument {} of {} is not a count: {}", i + 1, call.name, value))
+}
+
+fn arg(call: &Call, i: usize) -> Result<String, String> {
+    match call.args.get(i) {
+        Some(arg) => Ok(arg.to_string()),
+        None => Err(format!("missing argument {} of {}", i + 1, call.name)),
+    }
+}
+
+/// splits `a.b(x).c(y, z);` into calls. `None` when it is not a chain
+pub(crate) fn chain(stmt: &str) -> Option<Vec<Call>> {
+    let chars = trimmed(stmt).chars().collect::<Vec<char>>();
+    let mut calls = vec![];
+    let mut i = 0;
+    loop {
+        while i < chars.len() && chars[i].is_whitespace() {
+            i += 1;
+        }
+        let start = i;
+        while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+            i += 1;
+        }
+        if start == i {
+            return None;
+        }
+        let name = chars[start..i].iter().collect::<String>();
+        let mut args = vec![];
+        if i < chars.len() && chars[i] == '(' {
+            let end = closing(&chars, i)?;
+            args = split_args(&chars[i + 1..end]);
+            i = end + 1;
+        }
+        calls.push(Call { name, args, start });
+        while i < chars.len() && chars[i].is_whitespace() {
+            i += 1;
+        }
+        if i == chars.len() {
+            return Some(calls);
+        }
+        if chars[i] != '.' {
+            return None;
+        }
+        i += 1;
+    }
+}
+
+fn trimmed(stmt: &str) -> &str {
+    stmt.trim().trim_end_matches(';').trim_end()
+}
+
+/// index of the parenthesis closing the one at `open`. quoted text is skipped
+fn closing(chars: &[char], open: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = open;
+    while i < chars.len() {
+        match chars[i] {
+            '\'' | '"' => i = skip_quoted(chars, i)?,
+            '(' | '{' | '[' => depth += 1,
+            ')' | '}' | ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return if chars[i] == ')' { Some(i) } else { None };
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// index of the quote closing the one at `open`.
+/// `''` escapes a single quote, `\` escapes in double quotes
+fn skip_quoted(chars: &[char], open: usize) -> Option<usize> {
+    let quote = chars[open];
+    let mut i = open + 1;
+    while i < chars.len() {
+        if quote == '"' && chars[i] == '\\' {
+            i += 2;
+            continue;
+        }
+        if chars[i] == quote {
+            if quote == '\'' && i + 1 < chars.len() && chars[i + 1] == '\'' {
+                i += 2;
+                continue;
+            }
+            return Some(i);
+        }
+        i += 1;
+    }
+    None
+}
+
+/// splits arguments on top level commas
+fn split_args(chars: &[char]) -> Vec<String> {
+    let mut args = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\'' | '"' => i = skip_quoted(chars, i).unwrap_or(chars.len()),
+            '(' | '{' | '[' => depth += 1,
+            ')' | '}' | ']' => depth -= 1,
+            ',' if depth == 0 => {
+                args.push(chars[start..i].iter().collect::<String>().trim().to_string());
+                start = i + 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    let last = chars[start.min(chars.len())..].iter().collect::<String>().trim().to_string();
+    if !last.is_empty() || !args.is_empty() {
+        args.push(last);
+    }
+    args
+}
@@ -8,7 +8,8 @@ use log::error;
 use regex::Regex;
 use uuid::Uuid;
 
-use crate::headers::{FlinchCnf, FlinchCnfDir, FlinchCnfEnable, FlinchCnfLogin, Sort, SortDirection};
+use crate::cursor::{Cursor, Page};
+use crate::headers::{FlinchCnf, FlinchCnfDir, FlinchCnfEnable, FlinchCnfLogin, Nulls, Sort, SortDirection};
 use crate::pri_headers::FLINCH;
 
 pub struct ExecTime {
@@ -64,61 +65,90 @@ pub fn get_doc_name(name: &str) -> String { name.replace(DOC_PREFIX, "") }
 
 pub fn get_ttl_name(name: &str) -> String { name.replace(TTL_PREFIX, "") }
 
-pub fn tokenize(query: &String) -> Vec<String> {
-    query
-        .trim()
-        .to_lowercase()
-        .replace("(", " ")
-        .replace(")", " ")
-        .replace("+", " ")
-        .replace("-", " ")
-        .replace("/", " ")
-        .replace("\\", " ")
-        .replace("_", " ")
-        .replace("[", " ")
-        .replace("]", " ")
-        .split_whitespace()
-        .into_iter()
-        .filter(|&char| !char.is_empty())
-        .map(|char| char.to_string())
-        .collect::<Vec<_>>()
+/// merges `from` into `into`. objects are merged field by field, any other value is replaced
+pub fn merge(into: &mut serde_json::Value, from: serde_json::Value) {
+    match (into, from) {
+        (serde_json::Value::Object(into), serde_json::Value::Object(from)) => {
+            for (field, value) in from {
+                match into.get_mut(&field) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        into.insert(field, value);
+                    }
+                }
+            }
+        }
+        (into, from) => *into = from,
+    }
 }
 
 pub fn trim_apos(name: &String) -> String {
     name.trim_matches('\'').to_string()
 }
 
-pub fn parse_sort(opt: Option<String>) -> Option<Sort> {
-    match opt {
-        None => {
-            None
-        }
-        Some(sort) => {
-            let tkns = sort.split(',').collect::<Vec<&str>>();
-            let field = tkns.get(0).unwrap().to_owned();
-            let direction = tkns.get(1).unwrap().to_owned();
-            let direction = match direction {
-                "ASC" => SortDirection::Asc,
-                "DESC" => SortDirection::Desc,
-                _ => SortDirection::Asc
-            };
-            Some(Sort { field: trim_apos(&field.to_string()), direction })
+/// parses `'field','ASC'` or keys like `'price DESC NULLS LAST','address.city'`.
+/// a lone `'ASC'` or `'DESC'` sets the direction of the key before it
+pub fn parse_sort(opt: Option<String>) -> Result<Vec<Sort>, String> {
+    let mut keys: Vec<Sort> = vec![];
+    let sort = match opt {
+        None => return Ok(keys),
+        Some(sort) => sort,
+    };
+    let mut quoted = false;
+    let parts = sort.split(|c| {
+        if c == '\'' {
+            quoted = !quoted;
         }
+        c == ',' && !quoted
+    });
+    for part in parts {
+        let part = trim_apos(&part.trim().to_string());
+        let words = part.split_whitespace().map(|w| w.to_uppercase()).collect::<Vec<String>>();
+        let words = words.iter().map(|w| w.as_str()).collect::<Vec<&str>>();
+        let (field, rest) = match (words.as_slice(), keys.last_mut()) {
+            (["ASC"], Some(key)) => {
+                key.direction = SortDirection::Asc;
+                continue;
+            }
+            (["DESC"], Some(key)) => {
+                key.direction = SortDirection::Desc;
+                continue;
+            }
+            ([], _) => return Err(format!("empty sort key in {}", sort)),
+            (_, _) => (part.split_whitespace().next().unwrap_or_default().to_string(), &words[1..]),
+        };
+        let (direction, rest) = match rest {
+            ["ASC", rest @ ..] => (SortDirection::Asc, rest),
+            ["DESC", rest @ ..] => (SortDirection::Desc, rest),
+            rest => (SortDirection::Asc, rest),
+        };
+        let nulls = match rest {
+            [] => None,
+            ["NULLS", "FIRST"] => Some(Nulls::First),
+            ["NULLS", "LAST"] => Some(Nulls::Last),
+            _ => return Err(format!("invalid sort key `{}`. expected `field [ASC|DESC] [NULLS FIRST|LAST]`", part)),
+        };
+        keys.push(Sort { field, direction, nulls });
     }
+    Ok(keys)
 }
 
-pub fn parse_limit(opt: Option<String>) -> Option<(usize, usize)> {
-    match opt {
-        None => { None }
-        Some(ol) => {
-            let tkns = ol.split(',').collect::<Vec<&str>>();
-            let offset = tkns.get(0).unwrap().to_owned();
-            let offset = offset.parse::<usize>().unwrap();
-            let limit = tkns.get(1).unwrap().to_owned();
-            let limit = limit.parse::<usize>().unwrap();
-            Some((offset, limit))
-        }
+/// `offset,limit` or `'cursor',limit`. the cursor must be made for `sort`
+pub fn parse_page(opt: Option<String>, sort: &[Sort]) -> Result<Option<Page>, String> {
+    let page = match opt {
+        None => return Ok(None),
+        Some(page) => page,
+    };
+    let (start, limit) = match page.rsplit_once(',') {
+        Some((start, limit)) => (start.trim(), limit.trim()),
+        None => return Err(format!("invalid page `{}`. expected `offset, limit` or `'cursor', limit`", page)),
+    };
+    let limit = limit.parse::<usize>().map_err(|_| format!("page limit is not a count: {}", limit))?;
+    if start.starts_with('\'') {
+        return Ok(Some(Page::After(Cursor::decode(trim_apos(&start.to_string()).as_str(), sort)?, limit)));
     }
+    let offset = start.parse::<usize>().map_err(|_| format!("page offset is not a count: {}", start))?;
+    Ok(Some(Page::Offset(offset, limit)))
 }
 
 fn cnf_path() -> String {
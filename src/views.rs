@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+
+use crate::doc_trait::{Document, ViewConfig};
+use crate::headers::Sort;
+use crate::predicate::Predicate;
+use crate::projection::Projection;
+use crate::utils::parse_sort;
+
+/// `Views` decides the views a document belongs to. conditions are compiled once,
+/// a document belongs to every view it matches
+pub struct Views {
+    names: Vec<String>,
+    conditions: Vec<(String, Predicate)>,
+    shapes: HashMap<String, Shape>,
+}
+
+/// how the documents of a view are returned
+#[derive(Default)]
+pub struct Shape {
+    pub sort: Vec<Sort>,
+    pub projection: Projection,
+}
+
+impl Views {
+    /// views of `view_opts`. invalid conditions, sort keys and selections are left out, `check` tells them
+    pub fn new(view_opts: &[ViewConfig]) -> Self {
+        let mut names: Vec<String> = vec![];
+        let mut conditions = vec![];
+        let mut shapes = HashMap::new();
+        for cfg in view_opts {
+            if !names.contains(&cfg.view_name) {
+                names.push(cfg.view_name.to_string());
+            }
+            if let Some(condition) = &cfg.condition {
+                if let Ok(predicate) = Predicate::compile(condition) {
+                    conditions.push((cfg.view_name.to_string(), predicate));
+                }
+            }
+            let shape = Shape {
+                sort: parse_sort(cfg.sort.clone()).unwrap_or_default(),
+                projection: Projection::new(&cfg.select, &[]).unwrap_or_default(),
+            };
+            if !shape.sort.is_empty() || !shape.projection.is_empty() {
+                shapes.entry(cfg.view_name.to_string()).or_insert(shape);
+            }
+        }
+        Self { names, conditions, shapes }
+    }
+
+    /// names of every view of the document, once each. views the document was put with
+    /// and that were dropped since are left out
+    pub fn bindings<D: Document>(&self, d: &D) -> Vec<String> {
+        let mut names = d.bindings();
+        names.retain(|name| self.names.contains(name));
+        for (name, predicate) in &self.conditions {
+            if !names.contains(name) && predicate.matches(d.document()) {
+                names.push(name.to_string());
+            }
+        }
+        names
+    }
+
+    /// names of every view, once each
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// sort and projection of a view, `None` when it has neither
+    pub fn shape(&self, name: &str) -> Option<&Shape> {
+        self.shapes.get(name)
+    }
+}
+
+/// whether the conditions, sort keys and selections of every view are valid
+pub fn check(view_opts: &[ViewConfig]) -> Result<(), String> {
+    for cfg in view_opts {
+        if cfg.view_name.is_empty() {
+            return Err("a view has no name".to_string());
+        }
+        if let Some(condition) = &cfg.condition {
+            Predicate::compile(condition).map_err(|err| format!("view {}: {}", cfg.view_name, err))?;
+        }
+        parse_sort(cfg.sort.clone()).map_err(|err| format!("view {}: {}", cfg.view_name, err))?;
+        Projection::new(&cfg.select, &[]).map_err(|err| format!("view {}: {}", cfg.view_name, err))?;
+    }
+    Ok(())
+}
@@ -1,12 +1,18 @@
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
     use std::time::Instant;
     use log::debug;
     use serde::{Deserialize, Serialize};
-    use flinch::database::{CollectionOptions, Database};
+    use serde_json::{json, Value};
+    use flinch::analyzer::{AnalyzerConfig, TextAnalyzer, TokenFilterKind, TokenizerKind};
+    use flinch::database::{CollectionOptions, Conflict, Database, FieldBoost, Metric, VectorConfig, VectorMode};
     use flinch::doc_trait::{Document, ViewConfig};
     use flinch::doc::QueryBased;
-    use flinch::headers::{NotificationType, PubSubEvent};
+    use flinch::errors::IndexError;
+    use flinch::headers::{HighlightOptions, NotificationType, PubSubEvent};
+    use flinch::predicate::Predicate;
+    use flinch::projection::Projection;
 
     const COLLECTION: &str = "demo";
     #[derive(Serialize, Deserialize)]
@@ -25,9 +31,11 @@ mod tests {
                 prop: "age".to_string(),
                 expected: "18".to_string(),
                 view_name: "ADULT".to_string(),
+                ..Default::default()
             }],
             range_opts: vec![format!("age")],
             clips_opts: vec![format!("name")],
+            ..Default::default()
         };
         let database: Database<QueryBased> = Database::init().await;
         let _ = database.add(col_opts).await;
@@ -120,4 +128,484 @@ mod tests {
             }
         }
     }
-}
\ No newline at end of file
+
+    /// a database of its own, named after the test, with a new collection holding `docs` under their pointers
+    async fn fixture(db: &str, col_opts: CollectionOptions, docs: Vec<(String, Value)>) -> Database<QueryBased> {
+        let database: Database<QueryBased> = Database::init_with_name(db).await;
+        let name = col_opts.name.clone();
+        let _ = database.drop(name.as_str()).await;
+        database.add(col_opts).await.expect("created new collection");
+        let instance = database.using(name.as_str()).unwrap();
+        for (k, v) in docs {
+            instance.value().put(k, QueryBased::from_value(&v).unwrap()).await.unwrap();
+        }
+        drop(instance);
+        database
+    }
+
+    /// two items with arrays, nested arrays, numbers and booleans in their indexed fields
+    async fn items(db: &str) -> Database<QueryBased> {
+        let col_opts = CollectionOptions {
+            name: "items".to_string(),
+            index_opts: vec![format!("sku")],
+            search_opts: vec![format!("title"), format!("tags")],
+            view_opts: vec![ViewConfig {
+                prop: "tags".to_string(),
+                expected: "blue".to_string(),
+                view_name: "BLUE".to_string(),
+                ..Default::default()
+            }],
+            range_opts: vec![format!("price")],
+            clips_opts: vec![format!("tags"), format!("active")],
+            ngram_opts: vec![format!("tags")],
+            ..Default::default()
+        };
+        fixture(db, col_opts, vec![
+            ("D_1".to_string(), json!({"sku": 1001, "title": "shoe", "tags": ["red", "sale"], "price": 9, "active": true})),
+            ("D_2".to_string(), json!({"sku": "B-2", "title": 5, "tags": ["blue", ["navy"]], "price": 100, "active": false})),
+        ]).await
+    }
+
+    #[tokio::test]
+    async fn multikey() {
+        let database = items("multikey").await;
+        let instance = database.using("items").unwrap();
+        let collection = instance.value();
+
+        // numbers are index keys
+        assert_eq!(collection.get_index("1001").data.unwrap().0, "D_1");
+        assert_eq!(collection.get_index("B-2").data.unwrap().0, "D_2");
+
+        // every array element is a clip. booleans too
+        assert_eq!(collection.fetch_clip("sale").data[0].0, "D_1");
+        assert_eq!(collection.fetch_clip("navy").data[0].0, "D_2");
+        assert_eq!(collection.fetch_clip("false").data[0].0, "D_2");
+
+        // arrays of strings and numbers are searchable
+        assert_eq!(collection.search("red").data[0].0, "D_1");
+        assert_eq!(collection.search("5").data[0].0, "D_2");
+        assert_eq!(collection.contains("avy", &[]).data[0].0, "D_2");
+
+        // a view matches any array element
+        assert_eq!(collection.fetch_view("BLUE").data[0].0, "D_2");
+    }
+
+    #[tokio::test]
+    async fn multikey_range() {
+        let database = items("multikey_range").await;
+        let instance = database.using("items").unwrap();
+        let collection = instance.value();
+
+        // numeric range compares numbers, not text
+        let res = collection.fetch_range("price", "9".to_string(), "50".to_string());
+        assert_eq!(res.data.len(), 1);
+        assert_eq!(res.data[0].0, "D_1");
+        assert_eq!(collection.fetch_range("price", "10".to_string(), "100".to_string()).data[0].0, "D_2");
+        assert!(collection.fetch_range("price", "50".to_string(), "9".to_string()).data.is_empty());
+    }
+
+    #[tokio::test]
+    async fn multikey_delete() {
+        let database = items("multikey_delete").await;
+        let instance = database.using("items").unwrap();
+        let collection = instance.value();
+
+        // every element is removed with the document
+        collection.delete("D_1".to_string()).await;
+        assert!(collection.get_index("1001").data.is_none());
+        assert!(collection.fetch_clip("sale").data.is_empty());
+        assert!(collection.search("red").data.is_empty());
+        assert!(collection.fetch_range("price", "0".to_string(), "50".to_string()).data.is_empty());
+        assert!(collection.fetch_view("BLUE").data.len() == 1);
+    }
+
+    /// four articles with a stemmed body and a boosted title
+    async fn articles(db: &str) -> Database<QueryBased> {
+        let col_opts = CollectionOptions {
+            name: "articles".to_string(),
+            search_opts: vec![format!("title"), format!("body")],
+            analyzer_opts: vec![AnalyzerConfig {
+                field: "body".to_string(),
+                char_filters: vec![],
+                tokenizer: TokenizerKind::Alphanumeric,
+                token_filters: vec![
+                    TokenFilterKind::Lowercase,
+                    TokenFilterKind::AsciiFolding,
+                    TokenFilterKind::EnglishStopWords,
+                    TokenFilterKind::Stemmer,
+                ],
+            }],
+            boost_opts: vec![FieldBoost { field: "title".to_string(), boost: 3.0 }],
+            ..Default::default()
+        };
+        fixture(db, col_opts, vec![
+            ("A_1".to_string(), json!({"title": "Foo", "body": "Searching the Café, quickly!"})),
+            ("A_2".to_string(), json!({"title": "Bar", "body": "nothing to see"})),
+            ("A_3".to_string(), json!({"title": "Rust", "body": "bar of rust, rust and more rust"})),
+            ("A_4".to_string(), json!({"title": "Notes", "body": "rust"})),
+        ]).await
+    }
+
+    fn keys<V>(data: &[(String, V)]) -> Vec<&str> {
+        data.iter().map(|(k, _)| k.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn search() {
+        let database = articles("search").await;
+        let instance = database.using("articles").unwrap();
+        let collection = instance.value();
+
+        // fields are analyzed separately. `Foo` + `Bar` no longer becomes `foobar`
+        assert_eq!(collection.search("foobar").data.len(), 0);
+        assert_eq!(collection.search("foo").data.len(), 1);
+
+        // query goes through the same stemming and folding as the body
+        assert_eq!(keys(&collection.search("cafe searches").data), vec!["A_1"]);
+
+        // stop words are never indexed
+        assert_eq!(collection.search("the").data.len(), 0);
+
+        // delete uses the same analyzer as insert
+        collection.delete("A_1".to_string()).await;
+        assert_eq!(collection.search("cafe").data.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn search_boosts() {
+        let database = articles("search_boosts").await;
+        let instance = database.using("articles").unwrap();
+        let collection = instance.value();
+
+        // a title match weighs three body matches
+        assert_eq!(keys(&collection.search("bar").data), vec!["A_2", "A_3"]);
+        assert_eq!(keys(&collection.search("rust").data), vec!["A_3", "A_4"]);
+
+        // restricted to fields
+        assert_eq!(keys(&collection.search_in("bar", &["body"]).data), vec!["A_3"]);
+        assert_eq!(collection.search_in("rust", &["title"]).data.len(), 1);
+        assert_eq!(collection.search_in("rust", &["nope"]).data.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn search_highlight() {
+        let database = articles("search_highlight").await;
+        let instance = database.using("articles").unwrap();
+        let collection = instance.value();
+
+        // highlighting keeps the original text around the analyzed match
+        let whole = HighlightOptions { fragment_size: 0, ..Default::default() };
+        let res = collection.search_highlight("cafe", &[], &whole);
+        assert_eq!(res.data.len(), 1);
+        let highlight = &res.data[0].highlights[0];
+        assert_eq!(highlight.field, "body");
+        assert_eq!(highlight.offsets, vec![(14, 18)]);
+        assert_eq!(highlight.fragments, vec!["Searching the <em>Café</em>, quickly!"]);
+
+        let short = HighlightOptions {
+            pre_tag: "[".to_string(),
+            post_tag: "]".to_string(),
+            fragment_size: 12,
+            ..Default::default()
+        };
+        let res = collection.search_highlight("rust", &["body"], &short);
+        assert_eq!(res.data.len(), 2);
+        assert_eq!(res.data[0].key, "A_3");
+        let highlight = &res.data[0].highlights[0];
+        assert_eq!(highlight.offsets.len(), 3);
+        assert_eq!(highlight.fragments[0], " of [rust], ru");
+        assert_eq!(highlight.fragments[2], "nd more [rust]");
+    }
+
+    #[tokio::test]
+    async fn search_analyzer_swap() {
+        let database = articles("search_analyzer_swap").await;
+        let instance = database.using("articles").unwrap();
+        let collection = instance.value();
+
+        // documents put while the analyzer is swapped are indexed with one of them, then re-indexed
+        let puts = async {
+            for i in 0..50 {
+                let doc = QueryBased::from_value(&json!({"title": "Later", "body": "Searching again"})).unwrap();
+                collection.put(format!("B_{}", i), doc).await.unwrap();
+            }
+        };
+        tokio::join!(puts, collection.set_analyzer("body", Arc::new(TextAnalyzer::standard())));
+        // the article put before the swap is re-indexed too
+        assert_eq!(collection.search_in("searching", &["body"]).data.len(), 51);
+        assert_eq!(collection.search_in("search", &["body"]).data.len(), 0);
+    }
+
+    const DIMENSION: usize = 8;
+
+    fn embedding(i: usize) -> Vec<f32> {
+        (0..DIMENSION).map(|j| ((i * 7 + j * 13) as f32 * 0.37).sin()).collect()
+    }
+
+    /// four hundred embeddings, searched exactly and through a graph, and points on a line
+    async fn embeddings(db: &str) -> Database<QueryBased> {
+        let col_opts = CollectionOptions {
+            name: "embeddings".to_string(),
+            vector_opts: vec![
+                VectorConfig {
+                    field: "exact".to_string(),
+                    dimension: DIMENSION,
+                    metric: Metric::Cosine,
+                    mode: VectorMode::Exact,
+                },
+                VectorConfig {
+                    field: "approx".to_string(),
+                    dimension: DIMENSION,
+                    metric: Metric::Cosine,
+                    mode: VectorMode::Hnsw { m: 8, ef_construction: 64, ef_search: 32 },
+                },
+                VectorConfig {
+                    field: "pos".to_string(),
+                    dimension: 2,
+                    metric: Metric::L2,
+                    mode: VectorMode::Exact,
+                },
+            ],
+            ..Default::default()
+        };
+        let docs = (0..400).map(|i| {
+            let v = embedding(i);
+            (format!("V_{}", i), json!({"n": i, "exact": v, "approx": v, "pos": [i, 0]}))
+        }).collect();
+        fixture(db, col_opts, docs).await
+    }
+
+    #[tokio::test]
+    async fn knn() {
+        let database = embeddings("knn").await;
+        let instance = database.using("embeddings").unwrap();
+        let col = instance.value();
+
+        // the approximate graph finds most of the exact neighbours
+        let query = embedding(1000);
+        let exact = col.knn("exact", &query, 10, |_, _| true).unwrap().data;
+        let approx = col.knn("approx", &query, 10, |_, _| true).unwrap().data;
+        assert_eq!((exact.len(), approx.len()), (10, 10));
+        assert!(exact.windows(2).all(|w| w[0].2 >= w[1].2));
+        let found = approx.iter().filter(|a| exact.iter().any(|e| e.0 == a.0)).count();
+        assert!(found >= 8, "recall {}/10", found);
+
+        // a vector is its own nearest neighbour
+        let res = col.knn("approx", &embedding(42), 1, |_, _| true).unwrap();
+        assert_eq!(res.data[0].0, "V_42");
+        assert!((res.data[0].2 - 1.0).abs() < 1e-4);
+
+        // filtered search keeps looking until `k` documents pass
+        let res = col.knn("approx", &query, 5, |_, d| d.object()["n"].as_u64().unwrap() >= 390).unwrap();
+        assert_eq!(res.data.len(), 5);
+
+        // euclidean distance
+        let res = col.knn("pos", &[10.2, 0.0], 3, |_, _| true).unwrap();
+        assert_eq!(res.data.iter().map(|r| r.0.as_str()).collect::<Vec<_>>(), vec!["V_10", "V_11", "V_9"]);
+        assert!((res.data[0].2 - 0.2).abs() < 1e-4);
+    }
+
+    #[tokio::test]
+    async fn knn_after_delete() {
+        let database = embeddings("knn_after_delete").await;
+        let instance = database.using("embeddings").unwrap();
+        let col = instance.value();
+
+        // deleted documents leave the graph
+        col.delete("V_42".to_string()).await;
+        let res = col.knn("approx", &embedding(42), 1, |_, _| true).unwrap();
+        assert_ne!(res.data[0].0, "V_42");
+        let res = col.knn("exact", &embedding(42), 1, |_, _| true).unwrap();
+        assert_ne!(res.data[0].0, "V_42");
+    }
+
+    #[tokio::test]
+    async fn knn_errors() {
+        let database = embeddings("knn_errors").await;
+        let instance = database.using("embeddings").unwrap();
+        let col = instance.value();
+
+        // dimensions are checked on put and on query
+        let doc = QueryBased::from_value(&json!({"approx": [1.0, 2.0]})).unwrap();
+        assert_eq!(col.put("V_X".to_string(), doc).await.err(), Some(IndexError::DimensionMismatch("approx".to_string(), DIMENSION)));
+        assert!(col.get(&"V_X".to_string()).data.is_none());
+        assert!(col.knn("approx", &[1.0], 1, |_, _| true).is_err());
+        assert_eq!(col.knn("unknown", &[1.0], 1, |_, _| true).err(), Some(IndexError::NoSuchIndex));
+    }
+
+    fn people() -> [Value; 4] {
+        [
+            json!({"name":"Julfikar","age":30,"score":7.5,"active":true,"tags":["a","b"],"city":{"name":"KL","zip":50000},"joined":"2023-04-01T10:00:00Z","email":null}),
+            json!({"name":"mark","age":"30","score":null,"active":false,"tags":[],"city":{"name":"Dhaka","zip":1000},"joined":"2021-12-31"}),
+            json!({"name":"Nadia","age":41,"tags":["b","c",1],"city":"none","joined":1700000000}),
+            json!({"name":"ann","age":19.0,"score":1e2,"active":true,"points":[1,2,3]}),
+        ]
+    }
+
+    #[tokio::test]
+    async fn predicate() {
+        let docs = people();
+        // every flql condition evaluates as flql does
+        let conditions = vec![
+            ".age == 30",
+            ".age == \"30\"",
+            ".age > 20",
+            ".age >= 41",
+            ".age < 30 || .active == true",
+            ".age > 20 && .active == true",
+            ".name == \"mark\" OR .name == \"ann\"",
+            "!(.active == true)",
+            ".active != true",
+            ".name CONTAINS \"ar\"",
+            ".tags CONTAINS \"b\"",
+            ".tags CONTAINS 1",
+            ".tags CONTAINS_ANY [\"c\", \"x\"]",
+            ".tags CONTAINS_ALL [\"a\", \"b\"]",
+            ".name CONTAINS_ANY \"xyz\"",
+            ".name STARTS_WITH \"Ju\"",
+            ".name ENDS_WITH \"k\"",
+            ".name IN [\"mark\", \"ann\"]",
+            ".age BETWEEN 18 31",
+            ".name BETWEEN \"a\" \"n\"",
+            ".city.name == \"KL\"",
+            ".city.zip > 10000",
+            ".points.1 == 2",
+            ".points.# == 3",
+            ".missing == NULL",
+            ".email == NULL",
+            ".score + 1 > 8",
+            ".age * 2 == 60",
+            ".name + \"!\" == \"mark!\"",
+            "COERCE .name _lowercase_ == \"julfikar\"",
+            "COERCE .age _number_ == 30",
+            "COERCE .age _string_ == \"30\"",
+            "COERCE .joined _datetime_ > COERCE \"2022-01-01\" _datetime_",
+            ".city == .city",
+            ".tags == [\"a\", \"b\"]",
+            "(.age > 20 || .age < 20) && .name STARTS_WITH \"N\"",
+            ".name > 5",
+        ];
+        for condition in conditions.iter() {
+            let predicate = Predicate::compile(condition).unwrap();
+            let expression = flql::expr_parse(condition).unwrap();
+            for doc in docs.iter() {
+                let json = serde_json::to_vec(doc).unwrap();
+                let expected = matches!(expression.calculate(&json), Ok(flql::exp_parser::Value::Bool(true)));
+                assert_eq!(predicate.matches(doc), expected, "{} on {}", condition, doc);
+            }
+        }
+
+        // gjson patterns are left to flql
+        let predicate = Predicate::compile(".na* == \"mark\"").unwrap();
+        assert!(predicate.matches(&docs[1]));
+        assert!(!predicate.matches(&docs[0]));
+    }
+
+    #[tokio::test]
+    async fn predicate_functions() {
+        let docs = people();
+        let matching = |condition: &str| {
+            let predicate = Predicate::compile(condition).unwrap();
+            docs.iter()
+                .filter(|d| predicate.matches(d))
+                .map(|d| d["name"].as_str().unwrap())
+                .collect::<Vec<&str>>()
+        };
+        assert_eq!(matching("exists(.score)"), vec!["Julfikar", "mark", "ann"]);
+        assert_eq!(matching("exists(.email)"), vec!["Julfikar"]);
+        assert_eq!(matching("!exists(.city.zip)"), vec!["Nadia", "ann"]);
+        assert_eq!(matching("in(.age, [19, 41])"), vec!["Nadia", "ann"]);
+        assert_eq!(matching("in(.tags, [\"c\"])"), vec!["Nadia"]);
+        assert_eq!(matching("matches(.name, \"^[A-Z]\")"), vec!["Julfikar", "Nadia"]);
+        assert_eq!(matching("matches(.tags, \"^[ab]$\") && .active == true"), vec!["Julfikar"]);
+        assert_eq!(matching("lower(.name) == \"nadia\""), vec!["Nadia"]);
+        assert_eq!(matching("upper(.name) STARTS_WITH \"MA\""), vec!["mark"]);
+        assert_eq!(matching("date(.joined) > date(\"2022-06-01\")"), vec!["Julfikar", "Nadia"]);
+        assert_eq!(matching("date(.joined) < now() && date(.joined) > date(\"2021-01-01\")"), vec!["Julfikar", "mark", "Nadia"]);
+    }
+
+    #[tokio::test]
+    async fn predicate_errors() {
+        assert!(Predicate::compile("matches(.name, \"[\")").is_err());
+        assert!(Predicate::compile("lower(.name, .age)").is_err());
+        assert!(Predicate::compile("exists(\"name\")").is_err());
+        assert!(Predicate::compile("nope(.name)").is_err());
+        assert!(Predicate::compile(".age >").is_err());
+        assert!(Predicate::compile("lower(.name").is_err());
+    }
+
+    #[tokio::test]
+    async fn projection() {
+        let doc = json!({
+            "name": "Julfikar",
+            "password": "secret",
+            "address": {"city": "KL", "zip": 50000, "geo": {"lat": 3.1, "lon": 101.6}},
+            "items": [{"sku": "a", "qty": 1}, {"sku": "b", "qty": 2}],
+            "tags": ["x", "y"],
+        });
+
+        let projection = Projection::new(&["name".to_string(), "address.city".to_string()], &[]).unwrap();
+        assert_eq!(projection.apply(&doc), json!({"name": "Julfikar", "address": {"city": "KL"}}));
+
+        let projection = Projection::new(&["address.zip AS zip".to_string(), "items.sku as skus".to_string(), "tags.1 AS tag".to_string()], &[]).unwrap();
+        assert_eq!(projection.apply(&doc), json!({"zip": 50000, "skus": ["a", "b"], "tag": "y"}));
+
+        let projection = Projection::new(&["items.qty".to_string(), "address".to_string()], &["address.geo".to_string()]).unwrap();
+        assert_eq!(projection.apply(&doc), json!({"address": {"city": "KL", "zip": 50000}, "items": [{"qty": 1}, {"qty": 2}]}));
+
+        let projection = Projection::new(&[], &["password".to_string(), "items.sku".to_string(), "address.geo.lat".to_string()]).unwrap();
+        assert_eq!(projection.apply(&doc), json!({
+            "name": "Julfikar",
+            "address": {"city": "KL", "zip": 50000, "geo": {"lon": 101.6}},
+            "items": [{"qty": 1}, {"qty": 2}],
+            "tags": ["x", "y"],
+        }));
+
+        let projection = Projection::new(&["missing".to_string(), "address.nothing".to_string()], &[]).unwrap();
+        assert_eq!(projection.make("k".to_string(), &doc), json!({"_pointer": "k"}));
+        assert_eq!(Projection::default().apply(&doc), doc);
+    }
+
+    #[tokio::test]
+    async fn projection_errors() {
+        assert!(Projection::new(&["name AS".to_string()], &[]).is_err());
+        assert!(Projection::new(&["address..city".to_string()], &[]).is_err());
+        assert!(Projection::new(&[], &["".to_string()]).is_err());
+    }
+
+    #[tokio::test]
+    async fn index_concurrency() {
+        let col_opts = CollectionOptions {
+            name: "concurrent".to_string(),
+            index_opts: vec![format!("email")],
+            ..Default::default()
+        };
+        let database = fixture("index_concurrency", col_opts, vec![
+            ("ann".to_string(), json!({"email": "ann@x.io"})),
+            ("bob".to_string(), json!({"email": "bob@x.io"})),
+        ]).await;
+        let col = Arc::clone(&database.using("concurrent").unwrap());
+
+        // a document is resolved and written under one lock, so only one of concurrent deletes finds it
+        let writes = (0..16).map(|i| {
+            let col = Arc::clone(&col);
+            tokio::spawn(async move {
+                let doc = QueryBased::from_value(&json!({"email": "bob@x.io", "n": i})).unwrap();
+                col.put_index("bob@x.io", doc, Conflict::Reject).await.unwrap().is_some()
+            })
+        }).collect::<Vec<_>>();
+        for write in writes {
+            assert!(write.await.unwrap());
+        }
+        assert_eq!(col.len(), 2);
+        let deletes = (0..16).map(|_| {
+            let col = Arc::clone(&col);
+            tokio::spawn(async move { col.delete_index("bob@x.io").await })
+        }).collect::<Vec<_>>();
+        let mut deleted = 0;
+        for delete in deletes {
+            deleted += usize::from(delete.await.unwrap().is_some());
+        }
+        assert_eq!((deleted, col.len()), (1, 1));
+    }
+}
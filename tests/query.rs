@@ -1,11 +1,23 @@
 #[cfg(test)]
 mod tests {
+    use std::collections::{HashMap, HashSet};
+    use std::sync::Arc;
+    use std::time::Duration;
+
     use log::debug;
+    use rayon::prelude::*;
     use serde::{Deserialize, Serialize};
-    use flinch::database::CollectionOptions;
-    use flinch::doc_trait::ViewConfig;
-    use flinch::headers::{NotificationType, FlinchError, PubSubEvent};
+    use serde_json::{json, Value};
+    use flinch::aggregate::Aggregation;
+    use flinch::database::{CollectionOptions, Conflict, Metric, VectorConfig, VectorMode};
+    use flinch::doc_trait::{Document, Point, ViewConfig};
+    use flinch::errors::{CollectionError, IndexError, QueryError};
+    use flinch::guard::{Cancel, Guard, Limits};
+    use flinch::headers::{ClipSet, ClipSets, Conflicted, FacetValue, NotificationType, FlinchError, Plan, PubSubEvent, QueryResult};
+    use flinch::profile::{Access, Profile};
+    use flinch::projection::Projection;
     use flinch::query::Query;
+    use flinch::statement::{self, split};
 
     const COLLECTION: &str = "demo";
     #[derive(Serialize, Deserialize)]
@@ -14,6 +26,29 @@ mod tests {
         age: i64
     }
 
+    /// a planner on a database of its own, named after the test, with a new collection holding `docs`
+    async fn fixture(db: &str, col_opts: CollectionOptions, docs: &[Value]) -> Query {
+        let mut planner = Query::new_with_name(db).await;
+        create(&mut planner, col_opts, docs).await;
+        planner
+    }
+
+    /// creates a collection in place of the one of the same name and puts `docs` into it
+    async fn create(planner: &mut Query, col_opts: CollectionOptions, docs: &[Value]) {
+        let _ = planner.underlying_db().drop(col_opts.name.as_str()).await;
+        let res = planner.exec(format!("new({});", serde_json::to_string(&col_opts).unwrap()).as_str()).await;
+        assert_eq!(res.error, FlinchError::None);
+        for doc in docs {
+            let res = planner.exec(format!("put({}).into('{}');", doc, col_opts.name).as_str()).await;
+            assert_eq!(res.error, FlinchError::None);
+        }
+    }
+
+    /// a field of every document, in order
+    fn values(data: &[Value], field: &str) -> Value {
+        Value::Array(data.iter().map(|d| d[field].clone()).collect())
+    }
+
     #[tokio::test]
     async fn query() {
         let col_opts = CollectionOptions {
@@ -24,9 +59,11 @@ mod tests {
                 prop: "age".to_string(),
                 expected: "18".to_string(),
                 view_name: "ADULT".to_string(),
+                ..Default::default()
             }],
             range_opts: vec![format!("age")],
             clips_opts: vec![format!("name")],
+            ..Default::default()
         };
         // let (sx, mut _rx) = tokio::sync::mpsc::channel(30000);
         let options = serde_json::to_string(&col_opts).unwrap();
@@ -101,4 +138,1755 @@ mod tests {
             }
         }*/
     }
-}
\ No newline at end of file
+
+    const STATUSES: [&str; 3] = ["paid", "open", "void"];
+
+    /// status, total and customer of the orders, a ninth of them without a total
+    fn order_rows() -> Vec<(&'static str, Option<f64>, usize)> {
+        (0..90).map(|i| (STATUSES[i % 3], if i % 9 == 0 { None } else { Some((i % 10) as f64 + 0.5) }, i % 4)).collect()
+    }
+
+    async fn orders(db: &str) -> Query {
+        let col_opts = CollectionOptions {
+            name: "orders".to_string(),
+            clips_opts: vec![format!("status")],
+            ..Default::default()
+        };
+        let docs = (0..90).map(|i| {
+            let mut doc = json!({
+                "status": STATUSES[i % 3],
+                "total": (i % 10) as f64 + 0.5,
+                "customer": {"id": i % 4},
+                "created": format!("2024-0{}-{:02}T10:00:00Z", 1 + i % 2, 1 + i % 28),
+            });
+            if i % 9 == 0 {
+                doc.as_object_mut().unwrap().remove("total");
+            }
+            doc
+        }).collect::<Vec<Value>>();
+        fixture(db, col_opts, &docs).await
+    }
+
+    #[tokio::test]
+    async fn aggregate_by() {
+        let mut planner = orders("aggregate_by").await;
+        let docs = order_rows();
+        let res = planner.exec("aggregate('count', 'count(total) AS priced', 'sum(total) AS revenue', 'avg(total)', 'min(total)', 'max(total)', 'distinct(customer.id) AS customers').by('status').from('orders');").await;
+        assert_eq!(res.error, FlinchError::None);
+        assert_eq!(values(&res.data, "status"), json!(["open", "paid", "void"]));
+        for row in res.data.iter() {
+            let status = row["status"].as_str().unwrap();
+            let group = docs.iter().filter(|d| d.0 == status).collect::<Vec<_>>();
+            let totals = group.iter().filter_map(|d| d.1).collect::<Vec<f64>>();
+            let sum = totals.iter().sum::<f64>();
+            assert_eq!(row["count"], json!(group.len()));
+            assert_eq!(row["priced"], json!(totals.len()));
+            assert!((row["revenue"].as_f64().unwrap() - sum).abs() < 1e-9);
+            assert!((row["avg(total)"].as_f64().unwrap() - sum / totals.len() as f64).abs() < 1e-9);
+            assert_eq!(row["min(total)"].as_f64().unwrap(), totals.iter().cloned().fold(f64::MAX, f64::min));
+            assert_eq!(row["max(total)"].as_f64().unwrap(), totals.iter().cloned().fold(f64::MIN, f64::max));
+            let mut customers = group.iter().map(|d| d.2).collect::<Vec<usize>>();
+            customers.sort();
+            customers.dedup();
+            assert_eq!(row["customers"], json!(customers.len()));
+        }
+    }
+
+    #[tokio::test]
+    async fn aggregate_when_by_month() {
+        let mut planner = orders("aggregate_when_by_month").await;
+        // condition planned on the clip, date truncation and several groups
+        let res = planner.exec("aggregate('count').by('status', 'month(created) AS month').when('.status == \"paid\" || .status == \"void\"').from('orders').sort('count', 'DESC').limit(3);").await;
+        assert_eq!(res.error, FlinchError::None);
+        assert_eq!(res.data.len(), 3);
+        assert!(res.data.iter().all(|r| r["status"] != "open"));
+        assert!(res.data.iter().all(|r| r["month"] == "2024-01" || r["month"] == "2024-02"));
+        let counts = res.data.iter().map(|r| r["count"].as_u64().unwrap()).collect::<Vec<u64>>();
+        assert!(counts.windows(2).all(|w| w[0] >= w[1]));
+        assert_eq!(counts.iter().sum::<u64>(), 45);
+    }
+
+    #[tokio::test]
+    async fn aggregate_single_group() {
+        let mut planner = orders("aggregate_single_group").await;
+        // a single group without `by`, even when nothing matches
+        let res = planner.exec("aggregate('count', 'sum(total)').from('orders');").await;
+        assert_eq!(res.data, vec![json!({"count": 90, "sum(total)": order_rows().iter().filter_map(|d| d.1).sum::<f64>() as i64})]);
+        let res = planner.exec("aggregate('count', 'avg(total)').when('.status == \"none\"').from('orders');").await;
+        assert_eq!(res.data, vec![json!({"count": 0, "avg(total)": null})]);
+        let res = planner.exec("aggregate('count').by('day(nothing) AS day').from('orders');").await;
+        assert_eq!(res.data, vec![json!({"day": null, "count": 90})]);
+
+        let col = planner.underlying_db().using("orders").unwrap();
+        let aggregation = Aggregation::new(&["max(created) AS last".to_string()], &["year(created) AS year".to_string()]).unwrap();
+        let res = col.aggregate(None, &aggregation, |_, _| true);
+        assert_eq!(res.data, vec![json!({"year": "2024", "last": "2024-02-28T10:00:00Z"})]);
+    }
+
+    #[tokio::test]
+    async fn aggregate_errors() {
+        let mut planner = orders("aggregate_errors").await;
+        for stmt in [
+            "aggregate('median(total)').from('orders');",
+            "aggregate('sum').from('orders');",
+            "aggregate().from('orders');",
+            "aggregate('count').by('week(created)').from('orders');",
+            "aggregate('count').from('orders').limit(2).sort('count');",
+        ] {
+            let res = planner.exec(stmt).await;
+            assert!(matches!(res.error, FlinchError::CustomError(_)), "{}", stmt);
+        }
+        let res = planner.exec("aggregate('count').when('.status ==').from('orders');").await;
+        assert!(matches!(res.error, FlinchError::ExpressionError(_)));
+    }
+
+    /// products in brand, color and tag clips, the cheap ones in the `SALE` view
+    async fn catalog(db: &str) -> Query {
+        let col_opts = CollectionOptions {
+            name: "catalog".to_string(),
+            clips_opts: vec![format!("brand"), format!("color"), format!("tags")],
+            view_opts: vec![ViewConfig {
+                view_name: "SALE".to_string(),
+                condition: Some(".price < 10".to_string()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        fixture(db, col_opts, &[
+            json!({"sku": 1, "brand": "acme", "color": "red", "price": 5}),
+            json!({"sku": 2, "brand": "acme", "color": "red", "price": 20, "tags": ["used"]}),
+            json!({"sku": 3, "brand": "acme", "color": "blue", "price": 8}),
+            json!({"sku": 4, "brand": "zeta", "color": "red", "price": 3}),
+            json!({"sku": 5, "brand": "zeta", "color": "green", "price": 50}),
+        ]).await
+    }
+
+    /// skus of documents in any order
+    fn skus(data: &[Value]) -> Value {
+        let mut skus = data.iter().map(|d| d["sku"].as_i64().unwrap()).collect::<Vec<i64>>();
+        skus.sort();
+        json!(skus)
+    }
+
+    #[tokio::test]
+    async fn clip_sets() {
+        let mut planner = catalog("clip_sets").await;
+        for (links, expected) in [
+            (".all('acme','red')", json!([1, 2])),
+            (".all('acme','red').none('used')", json!([1])),
+            (".any('blue','green')", json!([3, 5])),
+            (".all('acme').any('blue',view('SALE'))", json!([1, 3])),
+            (".all(view('SALE')).none('zeta')", json!([1, 3])),
+            (".none('acme')", json!([4, 5])),
+            (".all('acme').all('red').none('used').none('zeta')", json!([1])),
+            (".all('acme','missing')", json!([])),
+            (".any('missing')", json!([])),
+            (".none('missing')", json!([1, 2, 3, 4, 5])),
+        ] {
+            let res = planner.exec(format!("get.clips{}.from('catalog');", links).as_str()).await;
+            assert_eq!(res.error, FlinchError::None, "{}", links);
+            assert_eq!(skus(&res.data), expected, "{}", links);
+        }
+    }
+
+    #[tokio::test]
+    async fn clip_sets_paged() {
+        let mut planner = catalog("clip_sets_paged").await;
+        // sorted and paged like get, projected like get
+        let res = planner.exec("get.clips.all('red').from('catalog').sort('price','DESC').page(0,2);").await;
+        assert_eq!((values(&res.data, "sku"), res.total), (json!([2, 1]), Some(3)));
+        let res = planner.exec("get.clips.all('red').from('catalog').sort('price','DESC').page(2,2);").await;
+        assert_eq!(values(&res.data, "sku"), json!([4]));
+        let res = planner.exec("get.clips.all('zeta').from('catalog').sort('price','ASC').page(null).select('sku');").await;
+        assert_eq!((&res.data[0]["sku"], &res.data[0]["brand"]), (&json!(4), &Value::Null));
+    }
+
+    #[tokio::test]
+    async fn clip_sets_follow_writes() {
+        let mut planner = catalog("clip_sets_follow_writes").await;
+        let res = planner.exec(format!("put({}).when('.sku == 5').into('catalog');", json!({"sku": 5, "brand": "zeta", "color": "green", "price": 9})).as_str()).await;
+        assert_eq!(res.error, FlinchError::None);
+        let res = planner.exec("get.clips.all(view('SALE'),'zeta').from('catalog');").await;
+        assert_eq!(skus(&res.data), json!([4, 5]));
+
+        let col = planner.underlying_db().using("catalog").unwrap();
+        let sets = ClipSets {
+            all: vec![ClipSet::Clip("acme".to_string())],
+            none: vec![ClipSet::View("SALE".to_string())],
+            ..Default::default()
+        };
+        let res = col.fetch_clips(&sets);
+        assert_eq!(res.data.len(), 1);
+        assert_eq!(res.data[0].1.data["sku"], json!(2));
+        assert_eq!(col.clip_keys(&ClipSets::default()).len(), 5);
+    }
+
+    #[tokio::test]
+    async fn clip_sets_errors() {
+        let mut planner = catalog("clip_sets_errors").await;
+        let res = planner.exec("get.clips.all().from('catalog');").await;
+        assert!(matches!(res.error, FlinchError::CustomError(_)));
+        let res = planner.exec("get.clips.all('red').from('catalog').limit(2);").await;
+        assert!(matches!(res.error, FlinchError::CustomError(_)));
+        let res = planner.exec("get.clips.all('red').from('missing');").await;
+        assert!(matches!(res.error, FlinchError::CollectionError(_)));
+    }
+
+    /// ann and bob, unique by email and handle. collisions are rejected unless a put says otherwise
+    async fn accounts(db: &str) -> (Query, String, String) {
+        let col_opts = CollectionOptions {
+            name: "accounts".to_string(),
+            index_opts: vec![format!("email"), format!("handle")],
+            conflict_opts: Conflict::Reject,
+            ..Default::default()
+        };
+        let mut planner = fixture(db, col_opts, &[
+            json!({"email": "ann@x.io", "handle": "ann", "name": "Ann", "age": 30}),
+            json!({"email": "bob@x.io", "handle": "bob"}),
+        ]).await;
+        let ann = planner.exec("get.index('ann').from('accounts');").await.data[0]["_pointer"].as_str().unwrap().to_string();
+        let bob = planner.exec("get.index('bob').from('accounts');").await.data[0]["_pointer"].as_str().unwrap().to_string();
+        (planner, ann, bob)
+    }
+
+    #[tokio::test]
+    async fn conflict_reject() {
+        let (mut planner, ann, _) = accounts("conflict_reject").await;
+        // nothing is written
+        let res = planner.exec(format!("put({}).into('accounts');", json!({"email": "ann@x.io", "name": "Imposter"})).as_str()).await;
+        assert_eq!(res.error, FlinchError::IndexError(IndexError::DuplicateDocument));
+        assert_eq!(res.conflicts, vec![Conflicted { resolution: Conflict::Reject, pointers: vec![ann] }]);
+        let res = planner.exec("get.index('ann@x.io').from('accounts');").await;
+        assert_eq!(res.data[0]["name"], json!("Ann"));
+    }
+
+    #[tokio::test]
+    async fn conflict_merge() {
+        let (mut planner, ann, _) = accounts("conflict_merge").await;
+        // the other document keeps its pointer
+        let res = planner.exec(format!("put({}).into('accounts').on_conflict('merge');", json!({"email": "ann@x.io", "age": 31, "city": "Oslo"})).as_str()).await;
+        assert_eq!((&res.error, &res.data), (&FlinchError::None, &vec![json!(ann)]));
+        assert_eq!(res.conflicts, vec![Conflicted { resolution: Conflict::Merge, pointers: vec![ann.to_string()] }]);
+        let res = planner.exec("get.index('ann').from('accounts');").await;
+        let merged = &res.data[0];
+        assert_eq!((&merged["_pointer"], &merged["name"], &merged["age"], &merged["city"]), (&json!(ann), &json!("Ann"), &json!(31), &json!("Oslo")));
+
+        // nested objects are merged field by field
+        for prefs in [json!({"lang": "no", "theme": "dark"}), json!({"theme": "light"})] {
+            let res = planner.exec(format!("put({}).into('accounts').on_conflict('merge');", json!({"email": "ann@x.io", "prefs": prefs})).as_str()).await;
+            assert_eq!(res.error, FlinchError::None);
+        }
+        let res = planner.exec("get.index('ann').from('accounts');").await;
+        assert_eq!((&res.data[0]["prefs"], &res.data[0]["city"]), (&json!({"lang": "no", "theme": "light"}), &json!("Oslo")));
+    }
+
+    #[tokio::test]
+    async fn conflict_merge_many() {
+        let (mut planner, ann, bob) = accounts("conflict_merge_many").await;
+        // a document holding values of two others merges them all into the first one
+        let res = planner.exec(format!("put({}).into('accounts').on_conflict('merge');", json!({"email": "ann@x.io", "handle": "bob", "age": 32})).as_str()).await;
+        assert_eq!(res.error, FlinchError::None);
+        let mut pointers = vec![ann, bob];
+        pointers.sort();
+        assert_eq!(res.data, vec![json!(pointers[0])]);
+        assert_eq!(res.conflicts, vec![Conflicted { resolution: Conflict::Merge, pointers: pointers.clone() }]);
+        let res = planner.exec("length('accounts');").await;
+        assert_eq!(res.data, vec![json!(1)]);
+        let res = planner.exec("get.index('bob').from('accounts');").await;
+        let merged = &res.data[0];
+        assert_eq!((&merged["_pointer"], &merged["email"], &merged["name"], &merged["age"]), (&json!(pointers[0]), &json!("ann@x.io"), &json!("Ann"), &json!(32)));
+        let res = planner.exec("get.index('bob@x.io').from('accounts');").await;
+        assert!(res.data.is_empty());
+    }
+
+    #[tokio::test]
+    async fn conflict_replace() {
+        let (mut planner, _, _) = accounts("conflict_replace").await;
+        let res = planner.exec(format!("put({}).into('accounts').on_conflict('replace');", json!({"email": "ann@x.io", "handle": "bob", "name": "Both"})).as_str()).await;
+        assert_eq!(res.error, FlinchError::None);
+        assert_eq!((res.conflicts[0].resolution, res.conflicts[0].pointers.len()), (Conflict::Replace, 2));
+        let res = planner.exec("length('accounts');").await;
+        assert_eq!(res.data, vec![json!(1)]);
+    }
+
+    #[tokio::test]
+    async fn conflict_own_pointer() {
+        let (mut planner, _, _) = accounts("conflict_own_pointer").await;
+        // a document put again under its own pointer does not collide with itself
+        let res = planner.exec(format!("put({}).pointer('p1').into('accounts');", json!({"email": "cid@x.io"})).as_str()).await;
+        assert_eq!(res.error, FlinchError::None);
+        let res = planner.exec(format!("put({}).pointer('p1').into('accounts');", json!({"email": "cid@x.io", "v": 2})).as_str()).await;
+        assert_eq!((&res.error, res.conflicts.len()), (&FlinchError::None, 0));
+        let res = planner.exec(format!("put({}).when('.v == 2').into('accounts').on_conflict('reject');", json!({"email": "ann@x.io"})).as_str()).await;
+        assert_eq!((res.data.len(), res.conflicts[0].resolution), (0, Conflict::Reject));
+        let res = planner.exec("length('accounts');").await;
+        assert_eq!(res.data, vec![json!(3)]);
+
+        let col = planner.underlying_db().using("accounts").unwrap();
+        let doc = Document::from_value(&json!({"email": "cid@x.io", "v": 3})).unwrap();
+        let (pointer, conflicted) = col.put_with("p2".to_string(), doc, Conflict::Merge).await.unwrap();
+        assert_eq!(pointer, "'p1'");
+        assert_eq!(conflicted.unwrap().pointers, vec!["'p1'".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn conflict_errors() {
+        let (mut planner, _, _) = accounts("conflict_errors").await;
+        let res = planner.exec(format!("put({}).into('accounts').on_conflict('upsert');", json!({"a": 1})).as_str()).await;
+        assert!(matches!(res.error, FlinchError::CustomError(_)));
+        let res = planner.exec("get.from('accounts').sort(null).page(null).on_conflict('merge');").await;
+        assert!(matches!(res.error, FlinchError::CustomError(_)));
+    }
+
+    /// people of three cities and one without a city
+    async fn residents(db: &str) -> Query {
+        let col_opts = CollectionOptions {
+            name: "residents".to_string(),
+            index_opts: vec![format!("email")],
+            range_opts: vec![format!("age")],
+            ..Default::default()
+        };
+        let mut docs = ["Oslo", "Bergen", "Oslo", "Rome", "Bergen", "Oslo"].iter().enumerate()
+            .map(|(i, city)| json!({"email": format!("u{}@x.io", i), "age": 20 + i, "city": city}))
+            .collect::<Vec<Value>>();
+        docs.push(json!({"email": "none@x.io", "age": 90, "city": null}));
+        fixture(db, col_opts, &docs).await
+    }
+
+    #[tokio::test]
+    async fn count() {
+        let mut planner = residents("count").await;
+        let res = planner.exec("count.when('.city == \"Oslo\"').from('residents');").await;
+        assert_eq!((&res.error, &res.data), (&FlinchError::None, &vec![json!(3)]));
+        let res = planner.exec("count.when('.age >= 22 && .age < 25').from('residents');").await;
+        assert_eq!(res.data, vec![json!(3)]);
+        let res = planner.exec("count.when('.email == \"u1@x.io\"').from('residents');").await;
+        assert_eq!(res.data, vec![json!(1)]);
+        let res = planner.exec("count.from('residents');").await;
+        assert_eq!(res.data, vec![json!(7)]);
+
+        // indexed conditions are planned, not scanned
+        let res = planner.exec("explain(count.when('.age >= 22 && .age < 25').from('residents'));").await;
+        assert_eq!((&res.data[0]["access"], &res.data[0]["candidates"]), (&json!("Planned"), &json!(3)));
+
+        let col = planner.underlying_db().using("residents").unwrap();
+        assert_eq!(col.count(None, |_, _| true).data, 7);
+        assert_eq!(col.count(Some(".age < 22"), |_, _| true).data, 2);
+    }
+
+    #[tokio::test]
+    async fn exists_when() {
+        let mut planner = residents("exists_when").await;
+        let res = planner.exec("exists.when('.city == \"Rome\"').from('residents');").await;
+        assert_eq!(res.data, vec![json!(true)]);
+        let res = planner.exec("exists.when('.age > 100').from('residents');").await;
+        assert_eq!(res.data, vec![json!(false)]);
+        let col = planner.underlying_db().using("residents").unwrap();
+        assert!(!col.exists_when(None, |k, _| k.is_empty()).data);
+    }
+
+    #[tokio::test]
+    async fn distinct() {
+        let mut planner = residents("distinct").await;
+        // null and missing values are not distinct values
+        let res = planner.exec("distinct('city').from('residents');").await;
+        assert_eq!(res.data, vec![json!("Bergen"), json!("Oslo"), json!("Rome")]);
+        let res = planner.exec("distinct('city').when('.age < 22').from('residents');").await;
+        assert_eq!(res.data, vec![json!("Bergen"), json!("Oslo")]);
+        let col = planner.underlying_db().using("residents").unwrap();
+        assert_eq!(col.distinct("age", Some(".age > 24"), |_, _| true).data, vec![json!(25), json!(90)]);
+    }
+
+    #[tokio::test]
+    async fn count_errors() {
+        let mut planner = residents("count_errors").await;
+        let res = planner.exec("count.when('.age >').from('residents');").await;
+        assert!(matches!(res.error, FlinchError::ExpressionError(_)));
+        let res = planner.exec("exists.when('.age > 1').from('missing');").await;
+        assert!(matches!(res.error, FlinchError::CollectionError(_)));
+    }
+
+    /// shoes and a scarf in brand and color clips. `red` is both a brand and a color
+    async fn shoes(db: &str) -> Query {
+        let col_opts = CollectionOptions {
+            name: "shoes".to_string(),
+            search_opts: vec![format!("title")],
+            clips_opts: vec![format!("brand"), format!("color")],
+            ..Default::default()
+        };
+        fixture(db, col_opts, &[
+            json!({"title": "running shoe", "brand": "acme", "color": "red", "price": 10}),
+            json!({"title": "trail shoe", "brand": "acme", "color": "blue", "price": 20}),
+            json!({"title": "city shoe", "brand": "zeta", "color": "red", "price": 30}),
+            json!({"title": "red scarf", "brand": "zeta", "color": "green", "price": 40}),
+            json!({"title": "kids shoe", "brand": "red", "color": "green", "price": 5}),
+        ]).await
+    }
+
+    fn facet_value(value: &str, count: usize) -> FacetValue {
+        FacetValue { value: value.to_string(), count }
+    }
+
+    #[tokio::test]
+    async fn search_facets() {
+        let planner = shoes("search_facets").await;
+        let col = planner.underlying_db().using("shoes").unwrap();
+        let res = col.search_facets("shoe", &["brand", "color"], 10);
+        assert_eq!(res.data[0].field, "brand");
+        assert_eq!(res.data[0].values, vec![facet_value("acme", 2), facet_value("red", 1), facet_value("zeta", 1)]);
+        // each field counts its own clip set
+        assert_eq!(res.data[1].values, vec![facet_value("red", 2), facet_value("blue", 1), facet_value("green", 1)]);
+
+        // top-N
+        let res = col.search_facets("shoe", &["color"], 1);
+        assert_eq!(res.data[0].values, vec![facet_value("red", 2)]);
+    }
+
+    #[tokio::test]
+    async fn facets_when() {
+        let mut planner = shoes("facets_when").await;
+        let res = planner.facets_when("'.price > 15'".to_string(), vec![format!("brand")], 10, "'shoes'".to_string());
+        assert_eq!(res.error, FlinchError::None);
+        let facet = &res.data[0];
+        assert_eq!(facet["field"], "brand");
+        assert_eq!(facet["values"], json!([{"value": "zeta", "count": 2}, {"value": "acme", "count": 1}]));
+
+        // the same through flql, on every document without a condition
+        let res = planner.exec("facets('brand').when('.price > 15').from('shoes');").await;
+        assert_eq!(res.data[0]["values"], facet["values"]);
+        let res = planner.exec("facets('brand','color').from('shoes').limit(1);").await;
+        assert_eq!(res.error, FlinchError::None);
+        assert_eq!(res.data[0]["values"], json!([{"value": "acme", "count": 2}]));
+        assert_eq!(res.data[1]["values"], json!([{"value": "green", "count": 2}]));
+    }
+
+    #[tokio::test]
+    async fn facets_after_delete() {
+        let planner = shoes("facets_after_delete").await;
+        let col = planner.underlying_db().using("shoes").unwrap();
+        let zeta = col.iter()
+            .filter(|kv| kv.value().object()["brand"] == "zeta")
+            .map(|kv| kv.key().to_string())
+            .collect::<Vec<String>>();
+        for k in zeta {
+            col.delete(k).await;
+        }
+        let res = col.search_facets("shoe", &["brand"], 10);
+        assert_eq!(res.data[0].values, vec![facet_value("acme", 2), facet_value("red", 1)]);
+    }
+
+    #[tokio::test]
+    async fn facets_errors() {
+        let mut planner = shoes("facets_errors").await;
+        let res = planner.exec("facets().from('shoes');").await;
+        assert!(matches!(res.error, FlinchError::CustomError(_)));
+        let res = planner.exec("facets('brand').from('missing');").await;
+        assert!(matches!(res.error, FlinchError::CollectionError(_)));
+    }
+
+    /// stores around Berlin, one on two places, two across the antimeridian and one without a place
+    async fn stores(db: &str) -> Query {
+        let col_opts = CollectionOptions {
+            name: "stores".to_string(),
+            geo_opts: vec![format!("loc")],
+            ..Default::default()
+        };
+        fixture(db, col_opts, &[
+            json!({"name": "gate", "loc": {"lat": 52.5163, "lon": 13.3777}}),
+            json!({"name": "alex", "loc": {"lat": 52.5219, "lon": 13.4132}}),
+            json!({"name": "potsdamer", "loc": {"lat": 52.5096, "lon": 13.3759}}),
+            json!({"name": "chain", "loc": [{"lat": 48.1374, "lon": 11.5755}, {"lat": 52.5200, "lon": 13.3900}]}),
+            json!({"name": "east", "loc": {"lat": -17.7, "lon": 179.9}}),
+            json!({"name": "west", "loc": {"lat": -17.7, "lon": -179.9}}),
+            json!({"name": "nowhere", "loc": "unknown"}),
+        ]).await
+    }
+
+    #[tokio::test]
+    async fn near() {
+        let mut planner = stores("near").await;
+        let res = planner.exec("get.near('loc', 52.5163, 13.3777, 1000).from('stores');").await;
+        assert_eq!(res.error, FlinchError::None);
+        assert_eq!(values(&res.data, "name"), json!(["gate", "potsdamer", "chain"]));
+        assert_eq!(res.data[0]["_distance"], 0.0);
+        let distance = res.data[1]["_distance"].as_f64().unwrap();
+        assert!(distance > 700.0 && distance < 800.0, "{}", distance);
+
+        let res = planner.exec("get.near('loc', 52.5163, 13.3777, 3000).from('stores').limit(4);").await;
+        assert_eq!(values(&res.data, "name"), json!(["gate", "potsdamer", "chain", "alex"]));
+        let res = planner.exec("get.near('loc', 52.5163, 13.3777, 3000).from('stores').limit(2);").await;
+        assert_eq!(values(&res.data, "name"), json!(["gate", "potsdamer"]));
+    }
+
+    #[tokio::test]
+    async fn within_box() {
+        let mut planner = stores("within_box").await;
+        // nearest to the center of the box first. `chain` is on the edge
+        let res = planner.exec("get.within_box('loc', 52.50, 13.37, 52.52, 13.39).from('stores');").await;
+        assert_eq!(res.error, FlinchError::None);
+        assert_eq!(values(&res.data, "name"), json!(["potsdamer", "gate", "chain"]));
+    }
+
+    #[tokio::test]
+    async fn near_antimeridian() {
+        let mut planner = stores("near_antimeridian").await;
+        let res = planner.exec("get.near('loc', -17.7, 179.95, 30000).from('stores');").await;
+        assert_eq!(res.data.len(), 2);
+        let res = planner.exec("get.within_box('loc', -18, 179.5, -17, -179.5).from('stores');").await;
+        assert_eq!(res.data.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn near_after_delete() {
+        let planner = stores("near_after_delete").await;
+        let col = planner.underlying_db().using("stores").unwrap();
+        let gate = Point { lat: 52.5163, lon: 13.3777 };
+        let res = col.near("loc", gate, 100.0, None);
+        assert_eq!(res.data.len(), 1);
+        col.delete(res.data[0].0.clone()).await;
+        assert!(col.near("loc", gate, 100.0, None).data.is_empty());
+    }
+
+    #[tokio::test]
+    async fn near_errors() {
+        let mut planner = stores("near_errors").await;
+        let res = planner.exec("get.near('loc', 'a', 13.3777, 1000).from('stores');").await;
+        assert_ne!(res.error, FlinchError::None);
+        let res = planner.exec("get.near('loc', 52.5163, 13.3777).from('stores');").await;
+        assert_ne!(res.error, FlinchError::None);
+    }
+
+    /// fifty documents of a little over a hundred bytes
+    async fn guarded(db: &str) -> Query {
+        let col_opts = CollectionOptions {
+            name: "guarded".to_string(),
+            search_opts: vec![format!("text")],
+            ..Default::default()
+        };
+        let docs = (0..50).map(|i| json!({"n": i, "text": "x".repeat(100)})).collect::<Vec<Value>>();
+        fixture(db, col_opts, &docs).await
+    }
+
+    const GUARDED: &str = "get.from('guarded').sort(null).page(null);";
+
+    #[tokio::test]
+    async fn guard_max_rows() {
+        let mut planner = guarded("guard_max_rows").await;
+        planner.set_limits(Limits { max_rows: Some(20), ..Default::default() });
+        let res = planner.exec(GUARDED).await;
+        assert!(matches!(res.error, FlinchError::QueryError(QueryError::LimitExceeded(_))), "{:?}", res.error);
+        assert!(res.data.is_empty());
+        let res = planner.exec("get.from('guarded').sort('n','ASC').page(0,20);").await;
+        assert_eq!((res.data.len(), res.total), (20, Some(50)));
+        let res = planner.exec("get.when('.n < 20').from('guarded').sort(null).page(null);").await;
+        assert_eq!((&res.error, res.data.len()), (&FlinchError::None, 20));
+    }
+
+    #[tokio::test]
+    async fn guard_max_bytes() {
+        let mut planner = guarded("guard_max_bytes").await;
+        planner.set_limits(Limits { max_bytes: Some(500), ..Default::default() });
+        let res = planner.exec("get.when('.n < 5').from('guarded').sort(null).page(null);").await;
+        assert!(matches!(res.error, FlinchError::QueryError(QueryError::LimitExceeded(_))));
+        // bytes are the sizes of the stored documents, a page only counts its own
+        let res = planner.exec("get.when('.n < 5').from('guarded').sort(null).page(null).select('n');").await;
+        assert!(matches!(res.error, FlinchError::QueryError(QueryError::LimitExceeded(_))));
+        let res = planner.exec("get.when('.n < 4').from('guarded').sort(null).page(null);").await;
+        assert_eq!((&res.error, res.data.len()), (&FlinchError::None, 4));
+        let res = planner.exec(GUARDED).await;
+        assert!(matches!(res.error, FlinchError::QueryError(QueryError::LimitExceeded(_))));
+        let res = planner.exec("get.from('guarded').sort('n','ASC').page(0,2);").await;
+        assert_eq!((&res.error, res.data.len(), res.total), (&FlinchError::None, 2, Some(50)));
+        planner.set_limits(Limits::default());
+        assert_eq!(planner.exec(GUARDED).await.data.len(), 50);
+    }
+
+    #[tokio::test]
+    async fn guard_deadline() {
+        let planner = guarded("guard_deadline").await;
+        let guard = Guard::new(Limits { timeout: Some(Duration::ZERO), ..Default::default() }, Cancel::new());
+        let res = planner.exec_guarded(statement::parse(GUARDED).unwrap(), guard).await;
+        assert_eq!(res.error, FlinchError::QueryError(QueryError::Timeout));
+        let guard = Guard::new(Limits { timeout: Some(Duration::from_secs(60)), ..Default::default() }, Cancel::new());
+        let res = planner.exec_guarded(statement::parse(GUARDED).unwrap(), guard.clone()).await;
+        assert_eq!((&res.error, res.data.len(), guard.stopped()), (&FlinchError::None, 50, None));
+    }
+
+    #[tokio::test]
+    async fn guard_stops_writes() {
+        let mut planner = guarded("guard_stops_writes").await;
+        for stmt in [
+            "delete.when('.n >= 0').from('guarded');",
+            "delete.from('guarded');",
+            "put({\"n\":99}).when('.n < 10').into('guarded');",
+            "ttl(60).if('.n < 10').into('guarded');",
+        ] {
+            let cancel = Cancel::new();
+            let guard = Guard::new(Limits::default(), cancel.clone());
+            cancel.cancel();
+            let res = planner.exec_guarded(statement::parse(stmt).unwrap(), guard.clone()).await;
+            assert_eq!((&res.error, res.data.len()), (&FlinchError::QueryError(QueryError::Cancelled), 0), "{}", stmt);
+            assert_eq!(guard.stopped(), Some(QueryError::Cancelled));
+
+            let guard = Guard::new(Limits { timeout: Some(Duration::ZERO), ..Default::default() }, Cancel::new());
+            let res = planner.exec_guarded(statement::parse(stmt).unwrap(), guard).await;
+            assert_eq!((&res.error, res.data.len()), (&FlinchError::QueryError(QueryError::Timeout), 0), "{}", stmt);
+        }
+        let res = planner.exec("count.when('.n < 10').from('guarded');").await;
+        assert_eq!(res.data, vec![json!(10)]);
+        let res = planner.exec("length('guarded');").await;
+        assert_eq!(res.data, vec![json!(50)]);
+    }
+
+    #[tokio::test]
+    async fn guard_cancels_write_midway() {
+        let planner = guarded("guard_cancels_write_midway").await;
+        // a stopped write returns what it wrote
+        let cancel = Cancel::new();
+        let parsed = statement::parse("delete.when('.n >= 25').from('guarded');").unwrap();
+        let stop = async {
+            for _ in 0..5 {
+                tokio::task::yield_now().await;
+            }
+            cancel.cancel();
+        };
+        let (res, _) = tokio::join!(planner.exec_guarded(parsed, Guard::new(Limits::default(), cancel.clone())), stop);
+        assert_eq!(res.error, FlinchError::QueryError(QueryError::Cancelled));
+        assert!(!res.data.is_empty() && res.data.len() < 25, "{}", res.data.len());
+        let left = planner.script("length('guarded');", false).await;
+        assert_eq!(left[0].data, vec![json!(50 - res.data.len())]);
+    }
+
+    #[tokio::test]
+    async fn guard_write_limits() {
+        let mut planner = guarded("guard_write_limits").await;
+        // writes are not held to the result limits
+        planner.set_limits(Limits { max_rows: Some(5), max_bytes: Some(100), ..Default::default() });
+        let res = planner.exec("ttl(3600).if('.n >= 40').into('guarded');").await;
+        assert_eq!((&res.error, &res.data), (&FlinchError::None, &vec![json!("TTL was set for 10 keys")]));
+        let res = planner.exec("delete.when('.n >= 30').from('guarded');").await;
+        assert_eq!((&res.error, res.data.len()), (&FlinchError::None, 20));
+    }
+
+    /// ann, bob and cid unique by email
+    async fn mailbox(db: &str) -> Query {
+        let col_opts = CollectionOptions {
+            name: "mailbox".to_string(),
+            index_opts: vec![format!("email")],
+            ..Default::default()
+        };
+        fixture(db, col_opts, &[
+            json!({"email": "ann@x.io", "name": "Ann"}),
+            json!({"email": "bob@x.io", "name": "Bob"}),
+            json!({"email": "cid@x.io", "name": "Cid"}),
+        ]).await
+    }
+
+    #[tokio::test]
+    async fn exists_index() {
+        let mut planner = mailbox("exists_index").await;
+        let res = planner.exec("exists.index('ann@x.io').from('mailbox');").await;
+        assert_eq!(res.data, vec![json!(true)]);
+        let res = planner.exec("exists.index('nobody@x.io').from('mailbox');").await;
+        assert_eq!(res.data, vec![json!(false)]);
+    }
+
+    #[tokio::test]
+    async fn put_index() {
+        let mut planner = mailbox("put_index").await;
+        let ann = planner.exec("get.index('ann@x.io').from('mailbox');").await.data[0]["_pointer"].clone();
+        // the document keeps its pointer and its new values are indexed
+        let res = planner.exec(format!("put({}).index('ann@x.io').into('mailbox');", json!({"email": "ann@y.io", "name": "Ann B"})).as_str()).await;
+        assert_eq!((&res.error, &res.data), (&FlinchError::None, &vec![ann.clone()]));
+        let res = planner.exec("get.index('ann@y.io').from('mailbox');").await;
+        assert_eq!((&res.data[0]["_pointer"], &res.data[0]["name"]), (&ann, &json!("Ann B")));
+        let res = planner.exec("exists.index('ann@x.io').from('mailbox');").await;
+        assert_eq!(res.data, vec![json!(false)]);
+        let res = planner.exec(format!("put({}).index('ann@x.io').into('mailbox');", json!({"email": "new@x.io"})).as_str()).await;
+        assert_eq!((&res.error, res.data.len()), (&FlinchError::None, 0));
+
+        // collisions with other documents are resolved as usual
+        let res = planner.exec(format!("put({}).index('ann@y.io').into('mailbox').on_conflict('reject');", json!({"email": "bob@x.io"})).as_str()).await;
+        assert_eq!(res.error, FlinchError::IndexError(IndexError::DuplicateDocument));
+        assert_eq!(res.conflicts[0].resolution, Conflict::Reject);
+    }
+
+    #[tokio::test]
+    async fn ttl_index() {
+        let mut planner = mailbox("ttl_index").await;
+        let res = planner.exec("ttl(3600).index('bob@x.io').into('mailbox');").await;
+        assert_eq!((&res.error, res.data.len()), (&FlinchError::None, 1));
+        let res = planner.exec("ttl(soon).index('bob@x.io').into('mailbox');").await;
+        assert!(matches!(res.error, FlinchError::ExpressionError(_)));
+    }
+
+    #[tokio::test]
+    async fn delete_index() {
+        let mut planner = mailbox("delete_index").await;
+        let res = planner.exec("delete.index('cid@x.io').from('mailbox');").await;
+        assert_eq!((&res.error, res.data.len()), (&FlinchError::None, 1));
+        let res = planner.exec("delete.index('cid@x.io').from('mailbox');").await;
+        assert_eq!(res.data.len(), 0);
+        let res = planner.exec("length('mailbox');").await;
+        assert_eq!(res.data, vec![json!(2)]);
+        let res = planner.exec("delete.index('ann@x.io').from('missing');").await;
+        assert!(matches!(res.error, FlinchError::CollectionError(_)));
+    }
+
+    /// orders of customers, with reviews and products to look up
+    async fn shop(db: &str) -> (Query, HashMap<String, Value>) {
+        let customers = CollectionOptions {
+            name: "customers".to_string(),
+            index_opts: vec![format!("email"), format!("handle")],
+            ..Default::default()
+        };
+        let mut planner = fixture(db, customers, &[
+            json!({"name": "ann", "email": "ann@x.io", "handle": "annie"}),
+            json!({"name": "bob", "email": "bob@x.io", "handle": "carl@x.io"}),
+            json!({"name": "cid", "email": "cid@x.io", "handle": "cid"}),
+        ]).await;
+        let res = planner.exec("get.from('customers').sort(null).page(null);").await;
+        let pointers = res.data.iter().map(|d| (d["name"].as_str().unwrap().to_string(), d["_pointer"].clone())).collect::<HashMap<String, Value>>();
+
+        let products = CollectionOptions { name: "products".to_string(), ..Default::default() };
+        create(&mut planner, products, &["a", "b", "c"].map(|sku| json!({"sku": sku, "price": 10}))).await;
+        let orders = CollectionOptions { name: "orders".to_string(), ..Default::default() };
+        create(&mut planner, orders, &[
+            json!({"no": 1, "customer": pointers["ann"], "email": "ann@x.io", "items": ["a", "b"]}),
+            json!({"no": 2, "customer": pointers["bob"], "email": "carl@x.io", "items": ["c", "c", "z"]}),
+            json!({"no": 3, "customer": "nobody", "email": "bob@x.io", "items": []}),
+        ]).await;
+        let reviews = CollectionOptions { name: "reviews".to_string(), ..Default::default() };
+        create(&mut planner, reviews, &[(json!(1), 5), (json!(2.0), 3), (json!("1"), 4), (json!(7), 1)].map(|(order, stars)| json!({"order": order, "stars": stars}))).await;
+        (planner, pointers)
+    }
+
+    #[tokio::test]
+    async fn lookup_one() {
+        let (mut planner, pointers) = shop("lookup_one").await;
+        // by pointer, missing matches are null
+        let res = planner.exec("get.from('orders').sort('no','ASC').page(null).lookup_one('customers', 'customer', '_pointer', 'buyer');").await;
+        assert_eq!(res.error, FlinchError::None);
+        let buyers = res.data.iter().map(|d| d["buyer"]["name"].clone()).collect::<Vec<Value>>();
+        assert_eq!(buyers, vec![json!("ann"), json!("bob"), Value::Null]);
+        assert_eq!(res.data[0]["buyer"]["_pointer"], pointers["ann"]);
+
+        // by a hash index, keys of another indexed field do not match
+        let res = planner.exec("get.from('orders').sort('no','ASC').page(null).lookup_one('customers', 'email', 'email');").await;
+        let names = res.data.iter().map(|d| d["customers"]["name"].clone()).collect::<Vec<Value>>();
+        assert_eq!(names, vec![json!("ann"), Value::Null, json!("bob")]);
+    }
+
+    #[tokio::test]
+    async fn lookup_many() {
+        let (mut planner, _) = shop("lookup_many").await;
+        // by a scanned field, numbers match however they are written
+        let res = planner.exec("get.from('orders').sort('no','ASC').page(null).lookup('reviews', 'no', 'order', 'reviews').lookup('products', 'items', 'sku');").await;
+        assert_eq!(res.error, FlinchError::None);
+        let stars = res.data.iter().map(|d| {
+            let mut stars = d["reviews"].as_array().unwrap().iter().map(|r| r["stars"].as_i64().unwrap()).collect::<Vec<i64>>();
+            stars.sort();
+            stars
+        }).collect::<Vec<Vec<i64>>>();
+        assert_eq!(stars, vec![vec![4, 5], vec![3], vec![]]);
+        let skus = res.data.iter().map(|d| values(d["products"].as_array().unwrap(), "sku")).collect::<Vec<Value>>();
+        assert_eq!(skus, vec![json!(["a", "b"]), json!(["c"]), json!([])]);
+
+        let col = planner.underlying_db().using("customers").unwrap();
+        let res = col.join("handle", &[&json!("cid"), &json!("ann@x.io"), &json!(1)]);
+        assert_eq!(res.data.len(), 1);
+        assert_eq!(res.data["cid"][0]["name"], json!("cid"));
+    }
+
+    #[tokio::test]
+    async fn lookup_projected() {
+        let (mut planner, _) = shop("lookup_projected").await;
+        // projections see the embedded documents, pages keep their totals
+        let res = planner.exec("get.when('.no >= 2').from('orders').sort('no','ASC').page(0,1).lookup_one('customers', 'customer', '_pointer', 'buyer').select('no', 'buyer.name AS buyer');").await;
+        assert_eq!(res.error, FlinchError::None);
+        assert_eq!(res.data.len(), 1);
+        let obj = res.data[0].as_object().unwrap();
+        assert_eq!((obj.len(), &obj["no"], &obj["buyer"]), (3, &json!(2), &json!("bob")));
+        assert_eq!(res.total, Some(2));
+        assert!(res.cursor.is_some());
+        let res = planner.exec("get.index('ann@x.io').from('customers').lookup('orders', 'email', 'email', 'orders').exclude('handle');").await;
+        assert_eq!(res.data.len(), 1);
+        assert!(res.data[0].get("handle").is_none());
+        assert_eq!(res.data[0]["orders"][0]["no"], json!(1));
+    }
+
+    #[tokio::test]
+    async fn lookup_errors() {
+        let (mut planner, _) = shop("lookup_errors").await;
+        let res = planner.exec("get.from('orders').sort(null).page(null).lookup('missing', 'customer', '_pointer');").await;
+        assert!(matches!(res.error, FlinchError::CollectionError(_)));
+        let res = planner.exec("get.from('orders').sort(null).page(null).lookup('customers', 'customer');").await;
+        assert!(matches!(res.error, FlinchError::CustomError(_)));
+        let res = planner.exec("get.from('orders').sort(null).page(null).lookup('customers', 'customer', 'a..b');").await;
+        assert!(matches!(res.error, FlinchError::CustomError(_)));
+    }
+
+    /// codes and emails in trigram indexes
+    async fn codes(db: &str) -> Query {
+        let col_opts = CollectionOptions {
+            name: "codes".to_string(),
+            ngram_opts: vec![format!("sku"), format!("email")],
+            ..Default::default()
+        };
+        fixture(db, col_opts, &[
+            json!({"sku": "abX42z", "email": "jane@acme.io"}),
+            json!({"sku": "qq-x421", "email": "x42@mail.io"}),
+            json!({"sku": "abcd bcde", "email": "bob@zeta.io"}),
+            json!({"sku": "ok", "email": "ok@zeta.io"}),
+        ]).await
+    }
+
+    #[tokio::test]
+    async fn search_contains() {
+        let mut planner = codes("search_contains").await;
+        let res = planner.exec("search.contains('x42').from('codes');").await;
+        assert_eq!((&res.error, res.data.len()), (&FlinchError::None, 2));
+        let res = planner.exec("search.contains('x42@').on('email').from('codes');").await;
+        assert_eq!(res.data.len(), 1);
+        let res = planner.exec("search.contains('X42').on('sku').from('codes');").await;
+        assert_eq!(res.data.len(), 2);
+        assert!(res.data.iter().all(|d| d["sku"].as_str().unwrap().to_lowercase().contains("x42")));
+        let res = planner.exec("search.contains('@zeta').on('email').from('codes');").await;
+        assert_eq!(res.data.len(), 2);
+
+        // every gram of `abcde` is in `abcd bcde`. the value check drops it
+        let res = planner.exec("search.contains('abcde').from('codes');").await;
+        assert_eq!(res.data.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn search_contains_short() {
+        let mut planner = codes("search_contains_short").await;
+        // shorter than a gram
+        let res = planner.exec("search.contains('ok').on('sku').from('codes');").await;
+        assert_eq!(res.data.len(), 1);
+        let res = planner.exec("search.contains('z').on('sku').from('codes');").await;
+        assert_eq!(res.data.len(), 1);
+
+        let pointer = res.data[0]["_pointer"].as_str().unwrap().to_string();
+        planner.underlying_db().using("codes").unwrap().delete(pointer).await;
+        let res = planner.exec("search.contains('x42').on('sku').from('codes');").await;
+        assert_eq!(res.data.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn search_contains_errors() {
+        let mut planner = codes("search_contains_errors").await;
+        let res = planner.exec("search.contains().from('codes');").await;
+        assert_ne!(res.error, FlinchError::None);
+        let res = planner.exec("search.contains('x42').from('missing');").await;
+        assert!(matches!(res.error, FlinchError::CollectionError(_)));
+    }
+
+    /// twenty five documents with four scores
+    async fn paged(db: &str) -> Query {
+        let docs = (0..25).map(|i| json!({"n": i, "score": i % 4})).collect::<Vec<Value>>();
+        fixture(db, CollectionOptions { name: "paged".to_string(), ..Default::default() }, &docs).await
+    }
+
+    fn pointers(data: &[Value]) -> Vec<String> {
+        data.iter().map(|d| d["_pointer"].as_str().unwrap().to_string()).collect()
+    }
+
+    /// follows the cursors of a paged `get` until the last page
+    async fn walk(planner: &mut Query, stmt: &str, limit: usize) -> Vec<QueryResult> {
+        let mut pages = vec![planner.exec(stmt.replace("PAGE", format!("0,{}", limit).as_str()).as_str()).await];
+        while let Some(cursor) = pages.last().unwrap().cursor.clone() {
+            let res = planner.exec(stmt.replace("PAGE", format!("'{}',{}", cursor, limit).as_str()).as_str()).await;
+            assert_eq!(res.error, FlinchError::None);
+            pages.push(res);
+        }
+        pages
+    }
+
+    #[tokio::test]
+    async fn page() {
+        let mut planner = paged("page").await;
+        // the same page every time, ordered by pointer without a sort
+        let all = planner.exec("get.from('paged').sort(null).page(null);").await;
+        assert_eq!(all.total, Some(25));
+        assert!(all.cursor.is_none());
+        let mut expected = pointers(&all.data);
+        expected.sort();
+        assert_eq!(pointers(&all.data), expected);
+        let first = planner.exec("get.from('paged').sort(null).page(0,10);").await;
+        assert_eq!(pointers(&first.data), expected[..10]);
+        assert_eq!(first.total, Some(25));
+
+        // cursors walk every document once
+        let pages = walk(&mut planner, "get.from('paged').sort(null).page(PAGE);", 10).await;
+        assert_eq!(pages.iter().map(|p| p.data.len()).collect::<Vec<usize>>(), vec![10, 10, 5]);
+        assert!(pages.iter().all(|p| p.total == Some(25)));
+        assert_eq!(pages.iter().flat_map(|p| pointers(&p.data)).collect::<Vec<String>>(), expected);
+    }
+
+    #[tokio::test]
+    async fn page_sorted() {
+        let mut planner = paged("page_sorted").await;
+        // ties on the sort key are broken by pointer
+        let sorted = planner.exec("get.from('paged').sort('score','DESC').page(null);").await;
+        let pages = walk(&mut planner, "get.from('paged').sort('score','DESC').page(PAGE);", 4).await;
+        assert_eq!(pages.len(), 7);
+        assert_eq!(pages.iter().flat_map(|p| pointers(&p.data)).collect::<Vec<String>>(), pointers(&sorted.data));
+
+        // with a condition and a projection
+        let pages = walk(&mut planner, "get.when('.n >= 5').from('paged').sort('score ASC', 'n DESC').page(PAGE).select('n');", 6).await;
+        let ns = pages.iter().flat_map(|p| p.data.iter().map(|d| d["n"].as_i64().unwrap())).collect::<Vec<i64>>();
+        assert_eq!(ns.len(), 20);
+        assert_eq!(ns[..6], [24, 20, 16, 12, 8, 21]);
+        assert!(pages.iter().all(|p| p.total == Some(20)));
+    }
+
+    #[tokio::test]
+    async fn page_after_put() {
+        let mut planner = paged("page_after_put").await;
+        // documents put after a page was read do not shift the next page
+        let res = planner.exec("get.from('paged').sort('n','ASC').page(0,10);").await;
+        let cursor = res.cursor.unwrap();
+        for n in [-1, 100] {
+            let res = planner.exec(format!("put({}).into('paged');", json!({"n": n, "score": 0})).as_str()).await;
+            assert_eq!(res.error, FlinchError::None);
+        }
+        let res = planner.exec(format!("get.from('paged').sort('n','ASC').page('{}',100);", cursor).as_str()).await;
+        let ns = res.data.iter().map(|d| d["n"].as_i64().unwrap()).collect::<Vec<i64>>();
+        assert_eq!(ns, (10..25).chain([100]).collect::<Vec<i64>>());
+        assert_eq!(res.total, Some(27));
+        assert!(res.cursor.is_none());
+
+        // cursors are tied to their sort
+        let res = planner.exec(format!("get.from('paged').sort('score','DESC').page('{}',10);", cursor).as_str()).await;
+        assert!(matches!(res.error, FlinchError::CustomError(_)));
+    }
+
+    #[tokio::test]
+    async fn page_past_end() {
+        let mut planner = paged("page_past_end").await;
+        let res = planner.exec("get.from('paged').sort(null).page(30,10);").await;
+        assert_eq!(res.error, FlinchError::None);
+        assert!(res.data.is_empty() && res.cursor.is_none());
+        let res = planner.exec("get.when('.n > 20').from('paged').sort(null).page(3,10);").await;
+        assert_eq!((res.data.len(), res.total), (1, Some(4)));
+        let res = planner.exec("get.from('paged').sort(null).page('zz',10);").await;
+        assert!(matches!(res.error, FlinchError::CustomError(_)));
+    }
+
+    /// three hundred documents in every kind of index, some values of other types
+    async fn planned(db: &str) -> Query {
+        let col_opts = CollectionOptions {
+            name: "planned".to_string(),
+            index_opts: vec![format!("sku")],
+            search_opts: vec![format!("title")],
+            range_opts: vec![format!("price"), format!("name")],
+            clips_opts: vec![format!("color"), format!("tags")],
+            ngram_opts: vec![format!("title")],
+            ..Default::default()
+        };
+        let colors = ["red", "green", "blue"];
+        let docs = (0..300).map(|i| json!({
+            "sku": if i % 2 == 0 { json!(i) } else { json!(format!("S-{}", i)) },
+            "name": format!("name {:03}", i),
+            "title": format!("item number {} in {}", i, colors[i % 3]),
+            "price": if i % 10 == 0 { json!(format!("{}", i)) } else { json!(i as f64 / 2.0) },
+            "color": colors[i % 3],
+            "tags": [format!("t{}", i % 7), i % 5 == 0],
+            "stock": i % 4,
+        })).collect::<Vec<Value>>();
+        fixture(db, col_opts, &docs).await
+    }
+
+    #[tokio::test]
+    async fn plan() {
+        let planner = planned("plan").await;
+        let conditions = vec![
+            (".sku == 42", true),
+            (".sku == \"S-43\"", true),
+            ("42 == .sku", true),
+            (".color == \"red\"", true),
+            (".tags CONTAINS \"t3\"", false),
+            (".tags CONTAINS true", true),
+            (".price > 100", true),
+            (".price >= 100 && .price < 110.5", true),
+            ("100 < .price", true),
+            (".price == 20", true),
+            (".price == \"20\"", true),
+            (".price BETWEEN 10 20", true),
+            (".name > \"name 290\"", true),
+            (".name BETWEEN \"name 010\" \"name 020\"", true),
+            (".title == \"item number 7 in green\"", true),
+            (".title CONTAINS \"ber 12\"", true),
+            (".color IN [\"red\", \"blue\"]", true),
+            (".color == \"red\" && .stock == 1", true),
+            (".stock == 1 && .color == \"red\"", true),
+            (".color == \"red\" || .price < 3", true),
+            (".color == \"red\" || .stock == 1", false),
+            ("(.color == \"red\" || .color == \"blue\") && .price > 140", true),
+            ("!(.color == \"red\")", false),
+            (".stock == 1", false),
+            (".sku == 42 && .color == \"green\"", true),
+        ];
+        let col = planner.underlying_db().using("planned").unwrap();
+        for (condition, indexed) in conditions.iter() {
+            let expected = {
+                let expression = flql::expr_parse(condition).unwrap();
+                col.iter()
+                    .filter(|kv| matches!(expression.calculate(kv.value().string().as_bytes()), Ok(flql::exp_parser::Value::Bool(true))))
+                    .map(|kv| kv.key().to_string())
+                    .collect::<HashSet<String>>()
+            };
+            match &col.plan(condition) {
+                Plan::Candidates(keys) => {
+                    assert!(indexed, "{} is not indexed", condition);
+                    assert!(keys.is_superset(&expected), "{} misses matches", condition);
+                    assert!(keys.len() < col.len(), "{} scans everything", condition);
+                }
+                Plan::Scan => assert!(!indexed, "{} is indexed", condition),
+            }
+
+            let res = planner.fetch_when(format!("'{}'", condition), "'planned'".to_string(), None, None);
+            assert_eq!(res.error, FlinchError::None);
+            let found = res.data.iter()
+                .map(|d| d["_pointer"].as_str().unwrap().to_string())
+                .collect::<HashSet<String>>();
+            assert_eq!(found, expected, "{}", condition);
+        }
+        assert_eq!(col.plan(".sku == 1001"), Plan::Candidates(HashSet::new()));
+        assert_eq!(col.plan(".price BETWEEN 20 10"), Plan::Candidates(HashSet::new()));
+        assert_eq!(col.plan("not a condition"), Plan::Scan);
+    }
+
+    #[tokio::test]
+    async fn plan_writes() {
+        let mut planner = planned("plan_writes").await;
+        let res = planner.exec("put({\"sku\":\"S-X\",\"color\":\"black\"}).when('.sku == \"S-1\"').into('planned');").await;
+        assert_eq!((&res.error, res.data.len()), (&FlinchError::None, 1));
+        let res = planner.exec("get.when('.color == \"black\"').from('planned').sort(null).page(null);").await;
+        assert_eq!(values(&res.data, "sku"), json!(["S-X"]));
+
+        let res = planner.exec("delete.when('.price > 140').from('planned');").await;
+        assert_eq!((&res.error, res.data.len()), (&FlinchError::None, 18));
+        let res = planner.exec("get.when('.price > 140').from('planned').sort(null).page(null);").await;
+        assert!(res.data.is_empty());
+    }
+
+    #[tokio::test]
+    async fn when_functions() {
+        let col_opts = CollectionOptions {
+            name: "predicates".to_string(),
+            clips_opts: vec![format!("name")],
+            ..Default::default()
+        };
+        let mut planner = fixture("when_functions", col_opts, &[
+            json!({"name": "Julfikar", "score": 7.5}),
+            json!({"name": "mark", "score": null}),
+            json!({"name": "Nadia"}),
+            json!({"name": "ann", "score": 1e2}),
+        ]).await;
+        let res = planner.exec("get.when('in(.name, [\"mark\", \"ann\", \"Nadia\"]) && exists(.score)').from('predicates').sort('name','ASC').page(null);").await;
+        assert_eq!(res.error, FlinchError::None);
+        assert_eq!(values(&res.data, "name"), json!(["ann", "mark"]));
+        let res = planner.exec("get.when('matches(.name, \"(\")').from('predicates').sort(null).page(null);").await;
+        assert_ne!(res.error, FlinchError::None);
+    }
+
+    /// people put through prepared statements, one with quotes in its name and pointer
+    async fn prepared(db: &str) -> Query {
+        let col_opts = CollectionOptions {
+            name: "prepared".to_string(),
+            index_opts: vec![format!("email")],
+            ..Default::default()
+        };
+        let mut planner = fixture(db, col_opts, &[]).await;
+        // a whole document, a document field, a pointer and a collection name
+        let put = planner.prepare("put($1).into($2);").unwrap();
+        assert_eq!(put.params(), 2);
+        for (name, age) in [("ann", 31), ("bob", 25), ("o'neil \"x\"", 40)] {
+            let doc = json!({"name": name, "email": format!("{}@x.io", age), "age": age});
+            let res = planner.execute(&put, &[doc, json!("prepared")]).await;
+            assert_eq!(res.error, FlinchError::None);
+        }
+        let put = planner.prepare("put({\"name\":$1,\"age\":$2,\"tag\":\"$1\"}).pointer($3).into('prepared');").unwrap();
+        let res = planner.execute(&put, &[json!("x') || true"), json!(7), json!("p'1")]).await;
+        assert_eq!(res.error, FlinchError::None);
+        let res = planner.exec("length('prepared');").await;
+        assert_eq!(res.data, vec![json!(4)]);
+        planner
+    }
+
+    #[tokio::test]
+    async fn prepared_pointer() {
+        let planner = prepared("prepared_pointer").await;
+        let get = planner.prepare("get.pointer($1).from('prepared');").unwrap();
+        let res = planner.execute(&get, &[json!("p'1")]).await;
+        assert_eq!(res.data.len(), 1);
+        assert_eq!((&res.data[0]["name"], &res.data[0]["tag"]), (&json!("x') || true"), &json!("$1")));
+        let index = planner.prepare("get.index($1).from('prepared');").unwrap();
+        let res = planner.execute(&index, &[json!("25@x.io")]).await;
+        assert_eq!(res.data[0]["name"], json!("bob"));
+    }
+
+    #[tokio::test]
+    async fn prepared_when() {
+        let planner = prepared("prepared_when").await;
+        // values in conditions are literals, never statement text
+        let when = planner.prepare("get.when('.name == $1').from('prepared').sort(null).page(null);").unwrap();
+        for (name, found) in [("ann", 1), ("o'neil \"x\"", 1), ("a\\", 0), ("x') || true", 1), ("\" || true || \"", 0), ("nobody", 0)] {
+            let res = planner.execute(&when, &[json!(name)]).await;
+            assert_eq!(res.data.len(), found, "{}", name);
+        }
+        let when = planner.prepare("get.when('.age >= $1 && .age < $2').from($3).sort('age','ASC').page(null);").unwrap();
+        let res = planner.execute(&when, &[json!(25), json!(40), json!("prepared")]).await;
+        assert_eq!(values(&res.data, "age"), json!([25, 31]));
+        let res = planner.execute(&when, &[json!(0), json!(10), json!("prepared")]).await;
+        assert_eq!(res.data.len(), 1);
+
+        let delete = planner.prepare("delete.when('.age < $1').from('prepared');").unwrap();
+        let res = planner.execute(&delete, &[json!(26)]).await;
+        assert_eq!(res.error, FlinchError::None);
+        let res = planner.script("length('prepared');", false).await;
+        assert_eq!(res[0].data, vec![json!(2)]);
+    }
+
+    #[tokio::test]
+    async fn prepared_errors() {
+        let planner = prepared("prepared_errors").await;
+        // parameters are checked when bound
+        let when = planner.prepare("get.when('.age >= $1 && .age < $2').from($3).sort('age','ASC').page(null);").unwrap();
+        let res = planner.execute(&when, &[json!(1)]).await;
+        assert!(matches!(res.error, FlinchError::CustomError(_)));
+        let res = planner.execute(&when, &[json!({"a": 1}), json!(2), json!("prepared")]).await;
+        assert!(matches!(res.error, FlinchError::CustomError(_)));
+        let put = planner.prepare("put($1).into('prepared');").unwrap();
+        let res = planner.execute(&put, &[json!("text")]).await;
+        assert!(matches!(res.error, FlinchError::CustomError(_)));
+        let get = planner.prepare("get.pointer($1).from('prepared');").unwrap();
+        let res = planner.execute(&get, &[json!(["a"])]).await;
+        assert!(matches!(res.error, FlinchError::CustomError(_)));
+
+        // placeholders flinch reads while parsing cannot be bound
+        assert!(planner.prepare("get.from('prepared').sort(null).page(0,$1);").is_err());
+        assert!(planner.prepare("get.when('.a == $0').from('prepared').sort(null).page(null);").is_err());
+        assert!(planner.prepare("get.from('prepared'").is_err());
+    }
+
+    /// fifty people of two teams, by email and age
+    async fn profiled(db: &str) -> Query {
+        let col_opts = CollectionOptions {
+            name: "profiled".to_string(),
+            index_opts: vec![format!("email")],
+            range_opts: vec![format!("age")],
+            ..Default::default()
+        };
+        let docs = (0..50).map(|i| json!({"email": format!("u{}@x.io", i), "age": i, "team": if i % 2 == 0 { "red" } else { "blue" }})).collect::<Vec<Value>>();
+        fixture(db, col_opts, &docs).await
+    }
+
+    /// profile of the only result of an `explain` or `profile`
+    fn profile_of(res: &QueryResult) -> Profile {
+        serde_json::from_value::<Profile>(res.data[0].clone()).unwrap()
+    }
+
+    fn stages(profile: &Profile) -> Vec<&str> {
+        profile.stages.iter().map(|s| s.name.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn explain() {
+        let mut planner = profiled("explain").await;
+        // explain plans only
+        let res = planner.exec("explain(get.when('.age > 40').from('profiled').sort(null).page(null));").await;
+        assert_eq!(res.error, FlinchError::None);
+        let profile = profile_of(&res);
+        assert_eq!((profile.statement.as_str(), profile.collection.as_deref()), ("get.when", Some("profiled")));
+        assert_eq!((profile.access, profile.candidates, profile.results), (Access::Planned, Some(9), None));
+        assert_eq!(stages(&profile), vec!["parse", "plan"]);
+
+        let res = planner.exec("explain(get.when('.team == \"red\"').from('profiled').sort(null).page(null).select('age'));").await;
+        let profile = profile_of(&res);
+        assert_eq!((profile.access, profile.candidates), (Access::Scan, Some(50)));
+
+        let res = planner.exec("explain(delete.when('.age < 10').from('profiled'));").await;
+        let profile = profile_of(&res);
+        assert_eq!((profile.statement.as_str(), profile.access, profile.candidates), ("delete.when", Access::Planned, Some(10)));
+        let res = planner.exec("length('profiled');").await;
+        assert_eq!(res.data, vec![json!(50)]);
+    }
+
+    #[tokio::test]
+    async fn profile() {
+        let mut planner = profiled("profile").await;
+        // profile runs the statement and times every stage
+        let res = planner.exec("profile(get.when('.age > 40 && .team == \"red\"').from('profiled').sort('age','DESC').page(0,3).select('age'));").await;
+        assert_eq!(res.error, FlinchError::None);
+        let profile = profile_of(&res);
+        assert_eq!((profile.access, profile.candidates, profile.results), (Access::Planned, Some(9), Some(3)));
+        assert_eq!(stages(&profile), vec!["parse", "plan", "scan", "filter", "sort", "paginate", "serialize"]);
+
+        let profile = profile_of(&planner.exec("profile(get.from('profiled').sort(null).page(null));").await);
+        assert_eq!((profile.statement.as_str(), profile.access, profile.candidates, profile.results), ("get", Access::Scan, Some(50), Some(50)));
+        assert_eq!(stages(&profile), vec!["parse", "plan", "scan", "sort", "paginate", "serialize"]);
+
+        let profile = profile_of(&planner.exec("profile(get.index('u7@x.io').from('profiled'));").await);
+        assert_eq!((profile.statement.as_str(), profile.access, profile.results), ("get.index", Access::HashIndex, Some(1)));
+        assert_eq!(stages(&profile), vec!["parse", "plan", "execute", "serialize"]);
+
+        let profile = profile_of(&planner.exec("profile(aggregate('count').when('.age >= 45').from('profiled'));").await);
+        assert_eq!((profile.access, profile.candidates, profile.results), (Access::Planned, Some(5), Some(1)));
+    }
+
+    #[tokio::test]
+    async fn profile_errors() {
+        let mut planner = profiled("profile_errors").await;
+        // the error of the statement is kept with its profile
+        let res = planner.exec("profile(get.from('nothing').sort(null).page(null));").await;
+        assert!(matches!(res.error, FlinchError::CollectionError(_)));
+        assert_eq!(res.data.len(), 1);
+        let res = planner.exec("explain(get.from('nothing').sort(null).page(null);").await;
+        assert!(matches!(res.error, FlinchError::CustomError(_)));
+        let res = planner.exec("profile(get.nothing);").await;
+        assert!(matches!(res.error, FlinchError::CustomError(_)));
+    }
+
+    /// ten users under pointers `p0` to `p9`
+    async fn projected(db: &str) -> Query {
+        let col_opts = CollectionOptions {
+            name: "projected".to_string(),
+            index_opts: vec![format!("name")],
+            range_opts: vec![format!("age")],
+            ..Default::default()
+        };
+        let mut planner = fixture(db, col_opts, &[]).await;
+        for i in 0..10 {
+            let doc = json!({"name": format!("user {}", i), "age": 20 + i, "password": "secret", "address": {"city": "KL", "zip": i}, "tags": ["x"]});
+            let res = planner.exec(format!("put({}).pointer('p{}').into('projected');", doc, i).as_str()).await;
+            assert_eq!(res.error, FlinchError::None);
+        }
+        planner
+    }
+
+    #[tokio::test]
+    async fn select() {
+        let mut planner = projected("select").await;
+        let res = planner.exec("get.from('projected').sort(null).page(null).select('name', 'address.zip AS zip');").await;
+        assert_eq!((&res.error, res.data.len()), (&FlinchError::None, 10));
+        for d in res.data.iter() {
+            let obj = d.as_object().unwrap();
+            assert_eq!(obj.len(), 3);
+            assert!(obj.contains_key("name") && obj.contains_key("zip") && obj.contains_key("_pointer"));
+        }
+        // pointers keep their quotes
+        let res = planner.exec("get.pointer('p3').from('projected').select('address').exclude('address.city');").await;
+        assert_eq!(res.data, vec![json!({"address": {"zip": 3}, "_pointer": "'p3'"})]);
+        let res = planner.exec("get.index('user 4').from('projected').select('age AS years');").await;
+        assert_eq!(res.data, vec![json!({"years": 24, "_pointer": "'p4'"})]);
+
+        let col = planner.underlying_db().using("projected").unwrap();
+        let projection = Projection::new(&["address.city AS city".to_string()], &[]).unwrap();
+        assert_eq!(col.project(&"'p1'".to_string(), &projection).data, Some(json!({"city": "KL", "_pointer": "'p1'"})));
+        assert!(col.project(&"none".to_string(), &projection).data.is_none());
+    }
+
+    #[tokio::test]
+    async fn exclude() {
+        let mut planner = projected("exclude").await;
+        let res = planner.exec("get.when('.age > 25').from('projected').sort(null).page(null).exclude('password', 'address');").await;
+        assert_eq!((&res.error, res.data.len()), (&FlinchError::None, 4));
+        assert!(res.data.iter().all(|d| d.get("password").is_none() && d.get("address").is_none() && d.get("age").is_some()));
+        let res = planner.exec("get.range(start:'21',end:'23',on:'age').from('projected').exclude('password', 'address', 'tags');").await;
+        assert_eq!(res.data.len(), 3);
+        assert!(res.data.iter().all(|d| d.as_object().unwrap().len() == 3));
+        let res = planner.exec("get.from('projected').sort(null).page(null).select('name AS');").await;
+        assert_ne!(res.error, FlinchError::None);
+    }
+
+    /// six people by email and age, `extra` is dropped first
+    async fn scripted(db: &str) -> Query {
+        let col_opts = CollectionOptions {
+            name: "scripted".to_string(),
+            index_opts: vec![format!("email")],
+            range_opts: vec![format!("age")],
+            search_opts: vec![format!("email")],
+            ..Default::default()
+        };
+        let docs = (0..6).map(|i| json!({"email": format!("u{}@x.io", i), "age": 20 + i})).collect::<Vec<Value>>();
+        let planner = fixture(db, col_opts, &docs).await;
+        let _ = planner.underlying_db().drop("extra").await;
+        planner
+    }
+
+    async fn scripted_docs(planner: &Query) -> Vec<Value> {
+        let res = planner.script("get.from('scripted').sort(null).page(null);", false).await;
+        let mut data = res[0].data.clone();
+        data.sort_by_key(|d| d["_pointer"].to_string());
+        data
+    }
+
+    #[tokio::test]
+    async fn script_split() {
+        assert_eq!(split(" put({\"a\":\"x;y\"}).into('c;d');;\n length('c') ; get.when('.a == \"1;2\"').from('c').sort(null).page(null)"), vec![
+            "put({\"a\":\"x;y\"}).into('c;d');".to_string(),
+            "length('c');".to_string(),
+            "get.when('.a == \"1;2\"').from('c').sort(null).page(null);".to_string(),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn script() {
+        let planner = Query::new_with_name("script").await;
+        let _ = planner.underlying_db().drop("scripted").await;
+        // every statement runs and has its own result
+        let mut script = format!("new({});", serde_json::to_string(&CollectionOptions { name: "scripted".to_string(), ..Default::default() }).unwrap());
+        for i in 0..6 {
+            script.push_str(format!("put({}).into('scripted');", json!({"email": format!("u{}@x.io", i), "age": 20 + i})).as_str());
+        }
+        script.push_str("put({\"a\":1}).into('missing'); get.nothing; length('scripted');");
+        let res = planner.script(script.as_str(), false).await;
+        assert_eq!(res.len(), 10);
+        assert!(res[..7].iter().all(|r| r.error == FlinchError::None));
+        assert!(matches!(res[7].error, FlinchError::QueryError(_) | FlinchError::CollectionError(_)), "{:?}", res[7].error);
+        assert!(matches!(res[8].error, FlinchError::CustomError(_)));
+        assert_eq!(res[9].data, vec![json!(6)]);
+    }
+
+    #[tokio::test]
+    async fn script_rollback() {
+        let planner = scripted("script_rollback").await;
+        let before = scripted_docs(&planner).await;
+        let pointer = before.iter().find(|d| d["age"] == json!(20)).unwrap()["_pointer"].as_str().unwrap().to_string();
+        // a failed atomic script undoes its writes
+        let migration = format!("\
+            put({}).into('scripted');\
+            put({}).into('scripted');\
+            delete.when('.age >= 24').from('scripted');\
+            delete.pointer('{}').from('scripted');\
+            ttl(3600).if('.age < 22').into('scripted');\
+            new({});\
+            put({{\"a\":1}}).into('extra');\
+            get.when('.age > 20').from('scripted').sort(null).page(null);\
+            put({{\"a\":1}}).into('missing');\
+            put({{\"a\":2}}).into('scripted');",
+            json!({"email": "u1@x.io", "age": 99}), json!({"email": "new@x.io", "age": 1}), pointer,
+            serde_json::to_string(&CollectionOptions { name: "extra".to_string(), ..Default::default() }).unwrap(),
+        );
+        let res = planner.script(migration.as_str(), true).await;
+        assert_eq!(res.len(), 10);
+        assert!(res[..8].iter().all(|r| r.error == FlinchError::None), "{:?}", res);
+        assert_eq!(res[7].data.len(), 2);
+        assert!(res[8].error.ne(&FlinchError::None));
+        assert!(matches!(res[9].error, FlinchError::CustomError(_)));
+        assert_eq!(scripted_docs(&planner).await, before);
+        assert!(planner.underlying_db().using("extra").is_err());
+        let res = planner.script("get.index('u1@x.io').from('scripted'); get.range(start:'20',end:'25',on:'age').from('scripted');", false).await;
+        assert_eq!(res[0].data[0]["age"], json!(21));
+        assert_eq!(res[1].data.len(), 6);
+    }
+
+    #[tokio::test]
+    async fn script_rollback_drop() {
+        let planner = scripted("script_rollback_drop").await;
+        let before = scripted_docs(&planner).await;
+        // dropped collections come back with their documents
+        let res = planner.script("drop('scripted'); get.nothing;", true).await;
+        assert!(matches!(res[1].error, FlinchError::CustomError(_)));
+        assert_eq!(scripted_docs(&planner).await, before);
+    }
+
+    #[tokio::test]
+    async fn script_concurrent_writer() {
+        let planner = scripted("script_concurrent_writer").await;
+        let before = scripted_docs(&planner).await;
+        // writes of other queries wait for an atomic script and survive its rollback
+        let failing = format!("put({}).into('scripted'); put({}).into('scripted'); put({{\"a\":1}}).into('missing');",
+            json!({"email": "a@x.io", "age": 1}), json!({"email": "b@x.io", "age": 2}));
+        let writer = async {
+            tokio::task::yield_now().await;
+            planner.script(format!("put({}).into('scripted');", json!({"email": "other@x.io", "age": 50})).as_str(), false).await
+        };
+        let (res, other) = tokio::join!(planner.script(failing.as_str(), true), writer);
+        assert!(res[2].error.ne(&FlinchError::None));
+        assert_eq!(other[0].error, FlinchError::None);
+        let res = planner.script("get.index('other@x.io').from('scripted'); get.index('a@x.io').from('scripted');", false).await;
+        assert_eq!(res[0].data[0]["age"], json!(50));
+        assert!(res[1].data.is_empty());
+        let res = planner.script("delete.index('other@x.io').from('scripted');", false).await;
+        assert_eq!(res[0].error, FlinchError::None);
+        assert_eq!(scripted_docs(&planner).await, before);
+    }
+
+    #[tokio::test]
+    async fn script_atomic() {
+        let planner = scripted("script_atomic").await;
+        // a successful atomic script keeps its writes
+        let res = planner.script("delete.when('.age >= 24').from('scripted'); length('scripted');", true).await;
+        assert_eq!(res[1].data, vec![json!(4)]);
+        assert_eq!(scripted_docs(&planner).await.len(), 4);
+    }
+
+    /// documents missing the sort keys or holding them in other types
+    async fn sorted(db: &str) -> Query {
+        fixture(db, CollectionOptions { name: "sorted".to_string(), ..Default::default() }, &[
+            json!({"id": "a", "price": 10, "team": "red", "joined": "2024-03-01", "meta": {"rank": 3}}),
+            json!({"id": "b", "price": 2.5, "team": "blue", "joined": "2023-12-31T23:00:00Z", "meta": {"rank": 1}}),
+            json!({"id": "c", "price": "9", "team": "red", "joined": "Jan 5 2024"}),
+            json!({"id": "d", "team": "blue", "joined": "2024-02-01", "meta": {"rank": 2}}),
+            json!({"id": "e", "price": 100, "team": "red", "meta": {"rank": null}}),
+            json!({"id": "f", "price": true, "team": "green", "joined": "2022-06-15"}),
+        ]).await
+    }
+
+    #[tokio::test]
+    async fn sort() {
+        let mut planner = sorted("sort").await;
+        // the flql clause sorts numbers, and never panics on missing or mixed fields
+        let res = planner.exec("get.from('sorted').sort('price','ASC').page(null);").await;
+        assert_eq!(res.error, FlinchError::None);
+        assert_eq!(values(&res.data, "id"), json!(["d", "f", "b", "a", "e", "c"]));
+        let res = planner.exec("get.from('sorted').sort('price','DESC').page(null);").await;
+        assert_eq!(values(&res.data, "id"), json!(["c", "e", "a", "b", "f", "d"]));
+
+        // several keys with their own direction
+        let res = planner.exec("get.from('sorted').sort('team ASC', 'price DESC').page(null);").await;
+        assert_eq!(res.error, FlinchError::None);
+        assert_eq!(values(&res.data, "id"), json!(["b", "d", "f", "c", "e", "a"]));
+        let res = planner.exec("get.from('sorted').sort('team','DESC','id','ASC').page(null);").await;
+        assert_eq!(values(&res.data, "id"), json!(["a", "c", "e", "f", "b", "d"]));
+    }
+
+    #[tokio::test]
+    async fn sort_nulls() {
+        let mut planner = sorted("sort_nulls").await;
+        // dates, nested paths and nulls placement
+        let res = planner.exec("get.from('sorted').sort('joined ASC NULLS LAST').page(null);").await;
+        assert_eq!(values(&res.data, "id"), json!(["f", "b", "c", "d", "a", "e"]));
+        let res = planner.exec("get.from('sorted').sort('meta.rank DESC').page(null);").await;
+        assert_eq!(values(&res.data[..3], "id"), json!(["a", "d", "b"]));
+        let res = planner.exec("get.from('sorted').sort('meta.rank ASC NULLS LAST', 'id DESC').page(null);").await;
+        assert_eq!(values(&res.data, "id"), json!(["b", "d", "a", "f", "e", "c"]));
+        let res = planner.exec("get.from('sorted').sort('meta.rank DESC NULLS FIRST', 'id').page(0,3);").await;
+        assert_eq!(values(&res.data, "id"), json!(["c", "e", "f"]));
+
+        // with a condition and a projection
+        let res = planner.exec("get.when('.team != \"green\"').from('sorted').sort('team DESC', 'joined').page(null).select('id');").await;
+        assert_eq!(res.error, FlinchError::None);
+        assert_eq!(values(&res.data, "id"), json!(["e", "c", "a", "b", "d"]));
+    }
+
+    #[tokio::test]
+    async fn sort_errors() {
+        let mut planner = sorted("sort_errors").await;
+        let res = planner.exec("get.from('sorted').sort('price SIDEWAYS').page(null);").await;
+        assert!(matches!(res.error, FlinchError::CustomError(_)));
+        let res = planner.exec("get.from('sorted').sort('price', 'ASC', '').page(null);").await;
+        assert!(matches!(res.error, FlinchError::CustomError(_)));
+    }
+
+    /// ten points on a line
+    async fn points(db: &str) -> Query {
+        let col_opts = CollectionOptions {
+            name: "points".to_string(),
+            vector_opts: vec![VectorConfig {
+                field: "pos".to_string(),
+                dimension: 2,
+                metric: Metric::L2,
+                mode: VectorMode::Exact,
+            }],
+            ..Default::default()
+        };
+        let docs = (0..10).map(|i| json!({"n": i, "even": i % 2 == 0, "pos": [i, 0]})).collect::<Vec<Value>>();
+        fixture(db, col_opts, &docs).await
+    }
+
+    #[tokio::test]
+    async fn knn() {
+        let mut planner = points("knn").await;
+        let res = planner.exec("get.knn('pos', [3.9, 0], 2).from('points');").await;
+        assert_eq!(res.error, FlinchError::None);
+        assert_eq!(values(&res.data, "n"), json!([4, 3]));
+        assert!(res.data[0]["_score"].as_f64().unwrap() < 0.2);
+
+        let res = planner.exec("get.knn('pos', [3.9, 0], 2).when('.even == true').from('points');").await;
+        assert_eq!(res.error, FlinchError::None);
+        assert_eq!(values(&res.data, "n"), json!([4, 2]));
+    }
+
+    #[tokio::test]
+    async fn knn_errors() {
+        let mut planner = points("knn_errors").await;
+        let res = planner.exec("get.knn('pos', [3.9], 2).from('points');").await;
+        assert_ne!(res.error, FlinchError::None);
+        let res = planner.exec(format!("put({}).into('points');", json!({"n": 10, "pos": [1, 2, 3]})).as_str()).await;
+        assert_eq!(res.error, FlinchError::IndexError(IndexError::DimensionMismatch("pos".to_string(), 2)));
+        let res = planner.exec("length('points');").await;
+        assert_eq!(res.data, vec![json!(10)]);
+    }
+
+    /// three people in a team view, a view of due dates and a view of adults. the blue one expires
+    async fn teams(db: &str) -> Query {
+        let col_opts = CollectionOptions {
+            name: "teams".to_string(),
+            index_opts: vec![format!("email")],
+            view_opts: vec![
+                ViewConfig {
+                    prop: "team".to_string(),
+                    expected: "red".to_string(),
+                    view_name: "RED".to_string(),
+                    ..Default::default()
+                },
+                ViewConfig {
+                    view_name: "DUE".to_string(),
+                    condition: Some("date(.due) < now()".to_string()),
+                    ..Default::default()
+                },
+                ViewConfig {
+                    view_name: "ADULT".to_string(),
+                    condition: Some(".age >= 18".to_string()),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let soon = chrono::Utc::now().timestamp() + 1;
+        let mut planner = fixture(db, col_opts, &[
+            json!({"email": "a@x.io", "team": "red", "age": 30, "due": 0}),
+            json!({"email": "b@x.io", "team": "red", "age": 12, "due": soon}),
+            json!({"email": "c@x.io", "team": "blue", "age": 40, "due": soon}),
+        ]).await;
+        let res = planner.exec("ttl(3600).index('c@x.io').into('teams');").await;
+        assert_eq!(res.data.len(), 1);
+        planner
+    }
+
+    #[tokio::test]
+    async fn list_views() {
+        let mut planner = teams("list_views").await;
+        let res = planner.exec("views('teams');").await;
+        assert_eq!(res.data, vec![
+            json!({"view": "RED", "size": 2}),
+            json!({"view": "DUE", "size": 1}),
+            json!({"view": "ADULT", "size": 2}),
+        ]);
+        // conditions on the current time are decided again on rebuild
+        tokio::time::sleep(Duration::from_millis(2100)).await;
+        let res = planner.exec("rebuild.views('teams');").await;
+        assert_eq!(res.error, FlinchError::None);
+        assert_eq!(res.data[1], json!({"view": "DUE", "size": 3}));
+    }
+
+    #[tokio::test]
+    async fn drop_view() {
+        let mut planner = teams("drop_view").await;
+        // the documents, their expiries, the other views and the collection are kept
+        let held = Arc::clone(planner.underlying_db().using("teams").unwrap().value());
+        let res = planner.exec("drop.view('ADULT').from('teams');").await;
+        assert_eq!((&res.error, &res.data), (&FlinchError::None, &vec![json!("ADULT")]));
+        let res = planner.exec("views('teams');").await;
+        assert_eq!(res.data, vec![json!({"view": "RED", "size": 2}), json!({"view": "DUE", "size": 1})]);
+        let res = planner.exec("get.view('ADULT').from('teams');").await;
+        assert_eq!(res.data.len(), 0);
+        let res = planner.exec("length('teams');").await;
+        assert_eq!(res.data, vec![json!(3)]);
+        let res = planner.exec("get.index('c@x.io').from('teams');").await;
+        assert_eq!(res.data[0]["team"], json!("blue"));
+        assert!(Arc::ptr_eq(planner.underlying_db().using("teams").unwrap().value(), &held));
+        assert_eq!(held.opts().view_opts.len(), 2);
+        assert_eq!(held.views(), vec![("RED".to_string(), 2), ("DUE".to_string(), 1)]);
+
+        // a failed atomic script puts the view back
+        let res = planner.script("drop.view('RED').from('teams'); put({\"a\":1}).into('missing');", true).await;
+        assert!(res[1].error.ne(&FlinchError::None));
+        assert_eq!(held.views(), vec![("RED".to_string(), 2), ("DUE".to_string(), 1)]);
+    }
+
+    #[tokio::test]
+    async fn drop_view_errors() {
+        let mut planner = teams("drop_view_errors").await;
+        let res = planner.exec("drop.view('NOPE').from('teams');").await;
+        assert_eq!(res.error, FlinchError::CollectionError(CollectionError::NoSuchView));
+        let res = planner.exec("drop.view('RED').from('missing');").await;
+        assert_eq!(res.error, FlinchError::CollectionError(CollectionError::NoSuchCollection));
+    }
+
+    #[tokio::test]
+    async fn delete_view() {
+        let mut planner = teams("delete_view").await;
+        // the documents of the view are deleted, the view stays
+        let res = planner.exec("delete.view('RED').from('teams');").await;
+        assert_eq!(res.error, FlinchError::None);
+        let res = planner.exec("get.from('teams').sort(null).page(null);").await;
+        assert_eq!(values(&res.data, "email"), json!(["c@x.io"]));
+        let col = planner.underlying_db().using("teams").unwrap();
+        assert_eq!(col.views(), vec![("RED".to_string(), 0), ("DUE".to_string(), 0), ("ADULT".to_string(), 1)]);
+    }
+
+    /// people in views by value, by condition with sort and projection, and by the current time
+    async fn viewed(db: &str) -> Query {
+        let col_opts = CollectionOptions {
+            name: "viewed".to_string(),
+            index_opts: vec![format!("email")],
+            view_opts: vec![
+                ViewConfig {
+                    prop: "age".to_string(),
+                    expected: "\"18\"".to_string(),
+                    view_name: "EIGHTEEN".to_string(),
+                    ..Default::default()
+                },
+                ViewConfig {
+                    view_name: "SENIOR".to_string(),
+                    condition: Some(".age >= 60".to_string()),
+                    sort: Some("'age DESC'".to_string()),
+                    select: vec![format!("name"), format!("age AS years")],
+                    ..Default::default()
+                },
+                ViewConfig {
+                    view_name: "EU".to_string(),
+                    condition: Some("in(.country, [\"DE\", \"FR\"])".to_string()),
+                    sort: Some("'name'".to_string()),
+                    ..Default::default()
+                },
+                ViewConfig {
+                    view_name: "UPCOMING".to_string(),
+                    condition: Some("date(.due) > now()".to_string()),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        fixture(db, col_opts, &[
+            json!({"email": "a@x.io", "name": "Ann", "age": 18, "country": "DE"}),
+            json!({"email": "b@x.io", "name": "Bob", "age": "18", "country": "US"}),
+            json!({"email": "c@x.io", "name": "Cid", "age": 70, "country": "FR"}),
+            json!({"email": "d@x.io", "name": "Dan", "age": 65, "country": "US"}),
+            json!({"email": "e@x.io", "name": "Eve", "age": 40}),
+        ]).await
+    }
+
+    #[tokio::test]
+    async fn views() {
+        let mut planner = viewed("views").await;
+        // numbers and strings match the expected value however it is quoted
+        let res = planner.exec("get.view('EIGHTEEN').from('viewed');").await;
+        assert_eq!(res.data.len(), 2);
+        // documents are sorted and projected the way the view declares
+        let res = planner.exec("get.view('SENIOR').from('viewed');").await;
+        assert_eq!(res.data, vec![json!({"name": "Cid", "years": 70}), json!({"name": "Dan", "years": 65})]);
+        let res = planner.exec("get.view('EU').from('viewed');").await;
+        assert_eq!(values(&res.data, "name"), json!(["Ann", "Cid"]));
+
+        let col = planner.underlying_db().using("viewed").unwrap();
+        let senior = col.fetch_view("SENIOR");
+        assert_eq!(senior.data.len(), 2);
+        assert_eq!(senior.data[1].1, json!({"name": "Dan", "years": 65}));
+        assert_eq!(col.fetch_view("NOPE").data.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn views_follow_writes() {
+        let mut planner = viewed("views_follow_writes").await;
+        // a document may leave one view and stay in another
+        let res = planner.exec(format!("put({}).index('c@x.io').into('viewed');", json!({"email": "c@x.io", "name": "Cid", "age": 18, "country": "FR"})).as_str()).await;
+        assert_eq!(res.error, FlinchError::None);
+        let res = planner.exec("get.view('SENIOR').from('viewed');").await;
+        assert_eq!(res.data, vec![json!({"name": "Dan", "years": 65})]);
+        let res = planner.exec("get.view('EIGHTEEN').from('viewed');").await;
+        assert_eq!(res.data.len(), 3);
+        let res = planner.exec("get.view('EU').from('viewed');").await;
+        assert_eq!(res.data.len(), 2);
+        let res = planner.exec("delete.index('a@x.io').from('viewed');").await;
+        assert_eq!(res.error, FlinchError::None);
+        let res = planner.exec("get.view('EU').from('viewed');").await;
+        assert_eq!(res.data, vec![json!({"email": "c@x.io", "name": "Cid", "age": 18, "country": "FR"})]);
+        let res = planner.exec("get.view('EIGHTEEN').from('viewed');").await;
+        assert_eq!(res.data.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn views_on_time() {
+        let mut planner = viewed("views_on_time").await;
+        // a document leaves its views on put, even when their condition no longer holds
+        let soon = chrono::Utc::now().timestamp() + 1;
+        let res = planner.exec(format!("put({}).into('viewed');", json!({"email": "f@x.io", "name": "Fay", "due": soon})).as_str()).await;
+        assert_eq!(res.error, FlinchError::None);
+        let res = planner.exec("get.view('UPCOMING').from('viewed');").await;
+        assert_eq!(res.data.len(), 1);
+        tokio::time::sleep(Duration::from_millis(2100)).await;
+        let res = planner.exec(format!("put({}).index('f@x.io').into('viewed');", json!({"email": "f@x.io", "name": "Fay"})).as_str()).await;
+        assert_eq!(res.error, FlinchError::None);
+        let res = planner.exec("get.view('UPCOMING').from('viewed');").await;
+        assert_eq!(res.data.len(), 0);
+        let col = planner.underlying_db().using("viewed").unwrap();
+        assert!(col.views().contains(&("UPCOMING".to_string(), 0)));
+    }
+
+    #[tokio::test]
+    async fn bad_options() {
+        let mut planner = Query::new_with_name("bad_options").await;
+        let _ = planner.underlying_db().drop("bad").await;
+        let bad = |view: ViewConfig| serde_json::to_string(&CollectionOptions { name: "bad".to_string(), view_opts: vec![view], ..Default::default() }).unwrap();
+        for options in [
+            "{\"name\":5}".to_string(),
+            "{\"name\":\"bad\"}".to_string(),
+            "{\"name\":\"bad\",\"index_opts\":\"email\",\"search_opts\":[],\"view_opts\":[],\"range_opts\":[],\"clips_opts\":[]}".to_string(),
+            "{\"name\":\"bad\",\"index_opts\":[],\"search_opts\":[],\"view_opts\":[],\"range_opts\":[],\"clips_opts\":[],\"conflict_opts\":\"Upsert\"}".to_string(),
+        ] {
+            let res = planner.exec(format!("new({});", options).as_str()).await;
+            assert!(matches!(res.error, FlinchError::QueryError(QueryError::ConfigureParseError(_))), "{}", options);
+        }
+        // views that do not compile are refused
+        for view in [
+            ViewConfig { view_name: "BAD".to_string(), condition: Some(".age >".to_string()), ..Default::default() },
+            ViewConfig { view_name: "BAD".to_string(), sort: Some("'age SIDEWAYS'".to_string()), ..Default::default() },
+            ViewConfig { view_name: "".to_string(), condition: Some(".age > 1".to_string()), ..Default::default() },
+        ] {
+            let res = planner.exec(format!("new({});", bad(view.clone())).as_str()).await;
+            assert_ne!(res.error, FlinchError::None, "{:?}", view);
+        }
+        let res = planner.exec("new(nope);").await;
+        assert!(matches!(res.error, FlinchError::CustomError(_)));
+        assert!(planner.underlying_db().using("bad").is_err());
+
+        let res = planner.exec(format!("new({});", serde_json::to_string(&CollectionOptions { name: "bad".to_string(), ..Default::default() }).unwrap()).as_str()).await;
+        assert_eq!(res.error, FlinchError::None);
+        let res = planner.exec(format!("new({});", serde_json::to_string(&CollectionOptions { name: "bad".to_string(), ..Default::default() }).unwrap()).as_str()).await;
+        assert_eq!(res.error, FlinchError::QueryError(QueryError::CollectionError(CollectionError::DuplicateCollection)));
+    }
+
+    #[tokio::test]
+    async fn unknown_fields() {
+        let col_opts = CollectionOptions {
+            name: "known".to_string(),
+            clips_opts: vec![format!("brand")],
+            geo_opts: vec![format!("loc")],
+            ngram_opts: vec![format!("sku")],
+            ..Default::default()
+        };
+        let docs = (0..5).map(|i| json!({"n": i, "brand": "acme", "sku": "abc", "loc": {"lat": 1.0, "lon": 1.0}})).collect::<Vec<Value>>();
+        let mut planner = fixture("unknown_fields", col_opts, &docs).await;
+
+        // fields no document holds, or no index is made for, find nothing
+        for stmt in [
+            "distinct('nope').from('known');",
+            "get.near('nope', 1.0, 1.0, 100).from('known');",
+            "search.contains('abc').on('nope').from('known');",
+            "get.range(start:'1',end:'5',on:'nope').from('known');",
+            "get.view('NOPE').from('known');",
+        ] {
+            let res = planner.exec(stmt).await;
+            assert_eq!((&res.error, res.data.len()), (&FlinchError::None, 0), "{}", stmt);
+        }
+        let res = planner.exec("facets('nope').from('known');").await;
+        assert_eq!(res.data, vec![json!({"field": "nope", "values": []})]);
+        let res = planner.exec("count.when('.nope == 1').from('known');").await;
+        assert_eq!(res.data, vec![json!(0)]);
+        // sorts and projections keep every document
+        let res = planner.exec("get.from('known').sort('nope','ASC').page(null);").await;
+        assert_eq!(res.data.len(), 5);
+        let res = planner.exec("get.from('known').sort(null).page(null).select('nope');").await;
+        assert!(res.data.iter().all(|d| d.as_object().unwrap().keys().eq(["_pointer"].iter())));
+        let res = planner.exec("get.knn('nope', [1, 0], 2).from('known');").await;
+        assert_eq!(res.error, FlinchError::IndexError(IndexError::NoSuchIndex));
+    }
+}
@@ -1,6 +1,7 @@
 #[cfg(test)]
 mod tests {
     use serde::{Serialize, Deserialize};
+    use serde_json::json;
     use std::path::Path;
     use std::sync::Arc;
     use flinch::database::CollectionOptions;
@@ -90,9 +91,11 @@ mod tests {
                 prop: "age".to_string(),
                 expected: "18".to_string(),
                 view_name: "ADULT".to_string(),
+                ..Default::default()
             }],
             range_opts: vec![format!("age")],
             clips_opts: vec![format!("name")],
+            ..Default::default()
         };
         let options = serde_json::to_string(&col_opts).unwrap();
         let col_created = schema.flql(format!("new({});",options.as_str()).as_str(),session_id.clone()).await;
@@ -136,6 +139,29 @@ mod tests {
             assert_eq!(x.error, FlinchError::None);
         }
 
+        // bad options create nothing
+        let res = schema.flql("new({\"name\":\"bad\",\"index_opts\":\"name\"});", session_id.clone()).await;
+        assert!(res.error.ne(&FlinchError::None),"{:?}",res);
+        let res = schema.flql("length('bad');", session_id.clone()).await;
+        assert!(res.error.ne(&FlinchError::None),"{:?}",res);
+
+        // prepared statements run on the database of the session
+        let prepared = schema.prepare("get.when('.name == $1').from($2).sort(null).page(null);").unwrap();
+        let res = schema.execute(&prepared, &[json!("julfikar7"), json!(COLLECTION)], session_id.clone()).await;
+        assert_eq!(res.data.len(),1);
+        let res = schema.execute(&prepared, &[json!("julfikar7")], session_id.clone()).await;
+        assert!(matches!(res.error, FlinchError::CustomError(_)),"{:?}",res);
+        assert!(schema.prepare("get.from($1").is_err());
+
+        // scripts refuse database statements and roll back when atomic
+        let res = schema.script(format!("put({{\"name\":\"x\"}}).into('{}'); db.drop('linux');", COLLECTION).as_str(), true, session_id.clone()).await;
+        assert!(res[0].error.eq(&FlinchError::None),"{:?}",res);
+        assert!(matches!(res[1].error, FlinchError::CustomError(_)),"{:?}",res);
+        let res = schema.flql(format!("length('{}');", COLLECTION).as_str(), session_id.clone()).await;
+        assert_eq!(res.data, vec![json!(record_size)]);
+        let res = schema.script(format!("length('{}');", COLLECTION).as_str(), false, "nobody".to_string()).await;
+        assert!(matches!(res[0].error, FlinchError::SchemaError(_)),"{:?}",res);
+
         let login = schema.login("julfikar","julfikar123@","windows");
         assert!(login.is_ok(), "{}", login.err().unwrap());
 